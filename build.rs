@@ -1,93 +1,251 @@
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{Read, BufReader, BufWriter, Write};
 use std::path::Path;
+use std::process;
 use std::collections::HashMap;
 use std::fmt;
 
 use serde_derive::*;
-use serde_yaml;
-use yaml_merge_keys;
-use phf_codegen;
 use serde::de::{self, Deserialize, Deserializer};
 
+#[path = "src/error.rs"]
+#[allow(dead_code)]
+mod error;
+use self::error::Error;
+
 #[path = "src/types/mod.rs"]
+#[allow(dead_code, unused_imports)]
 mod types;
 use self::types::*;
 
+#[path = "src/expression/mod.rs"]
+#[allow(dead_code)]
+mod expression;
+use self::expression::Expression;
+
+#[path = "src/command/validate.rs"]
+mod validate;
+use self::validate::RawCommand;
+
 fn main() {
-  let device = "V200KW2_6";
+  let config_dir = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("config");
 
-  let config_path = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("config").join(format!("{}.yml", device));
+  let mut devices = fs::read_dir(&config_dir).unwrap()
+    .map(|entry| entry.unwrap().path())
+    .filter(|path| path.extension().map(|ext| ext == "yml").unwrap_or(false))
+    .map(|path| path.file_stem().unwrap().to_str().unwrap().to_owned())
+    .collect::<Vec<_>>();
+  devices.sort();
 
-  let file = File::open(config_path).unwrap();
+  let features = manifest_features();
 
-  let mut content = String::new();
-  BufReader::new(file).read_to_string(&mut content).unwrap();
+  devices.retain(|device| {
+    let feature = format!("device-{}", device);
+
+    if !features.contains(&feature) {
+      println!("cargo:warning=config/{}.yml is ignored since there is no `{}` feature in Cargo.toml", device, feature);
+      return false
+    }
 
-  let config: Configuration = serde_yaml::from_value(yaml_merge_keys::merge_keys_serde(serde_yaml::from_str::<serde_yaml::Value>(&content).unwrap()).unwrap()).unwrap();
+    env::var_os(format!("CARGO_FEATURE_DEVICE_{}", device.to_uppercase())).is_some()
+  });
+
+  println!("cargo:rerun-if-changed={}", config_dir.display());
 
   let path = Path::new(&env::var("OUT_DIR").unwrap()).join("codegen.rs");
   let mut file = BufWriter::new(File::create(&path).unwrap());
 
-  let protocol = config.device.protocol;
+  let mut registry = Vec::new();
+  let mut errors = Vec::new();
 
-  let mut map = phf_codegen::Map::<&str>::new();
+  for device in &devices {
+    let config_path = config_dir.join(format!("{}.yml", device));
+    println!("cargo:rerun-if-changed={}", config_path.display());
 
-  for (name, command) in config.commands.iter() {
-    map.entry(name, &format!("{:?}", command));
-  }
+    let file_content = File::open(&config_path).unwrap();
+
+    let mut content = String::new();
+    BufReader::new(file_content).read_to_string(&mut content).unwrap();
+
+    let config = match Configuration::parse(&content) {
+      Ok(config) => config,
+      Err(err) => {
+        errors.push(format!("{}: {}", config_path.display(), err));
+        continue
+      }
+    };
+
+    for (name, message) in config.validate() {
+      let location = match command_line(&content, name) {
+        Some(line) => format!("{}:{}", config_path.display(), line),
+        None => config_path.display().to_string(),
+      };
+
+      errors.push(format!("{}: command `{}`: {}", location, name, message));
+    }
+
+    let protocol = config.device.protocol;
+
+    let mut map = phf_codegen::Map::<&str>::new();
+
+    for (name, command) in config.commands.iter() {
+      map.entry(name, &format!("{:?}", command));
+    }
+
+    writeln!(&mut file, "static {}_COMMANDS: phf::Map<&'static str, Command> = {};", device, map.build()).unwrap();
 
-  writeln!(&mut file, "static {}_COMMANDS: phf::Map<&'static str, Command> = {};", device, map.build()).unwrap();
+    write!(&mut file, "
+      #[derive(Debug, Default, Clone, Copy)]
+      pub struct {device};
 
-  write!(&mut file, "
-    #[derive(Debug)]
-    pub enum {} {{}}
+      impl Device for {device} {{
+        fn protocol(&self) -> ProtocolKind {{
+          ProtocolKind::{protocol}
+        }}
 
-    impl Device for {} {{
-      type Protocol = {};
+        fn command(&self, name: &str) -> Option<&Command> {{
+          {device}_COMMANDS.get(name)
+        }}
 
-      #[inline(always)]
-      fn map() -> &'static phf::Map<&'static str, Command> {{
-        &{}_COMMANDS
+        fn commands(&self) -> Vec<&str> {{
+          {device}_COMMANDS.keys().cloned().collect()
+        }}
       }}
-    }}
-  ", device, device, protocol, device).unwrap();
+    ", device = device, protocol = protocol).unwrap();
+
+    let id = match &config.device.id {
+      Some(id) => format!("Some(&{:?})", id),
+      None => "None".into(),
+    };
+
+    registry.push(format!("
+      DeviceInfo {{
+        name: {:?},
+        protocol: ProtocolKind::{},
+        id: {},
+        device: &{},
+        connect: connect_device::<{}>,
+      }},
+    ", device, protocol, id, device, device));
 
-  write!(&mut file, "pub type V200KW2 = V200KW2_6;").unwrap();
+    for alias in &config.device.aliases {
+      writeln!(&mut file, "/// Alias for [`{}`].", device).unwrap();
+      writeln!(&mut file, "pub use self::{} as {};", device, alias).unwrap();
+    }
+  }
+
+  if !errors.is_empty() {
+    for error in &errors {
+      eprintln!("error: {}", error);
+    }
+
+    eprintln!("{} error(s) found in device configurations", errors.len());
+    process::exit(1);
+  }
+
+  writeln!(&mut file, "
+    /// All device types compiled into the crate.
+    pub static DEVICES: &[DeviceInfo] = &[{}];
+  ", registry.join("")).unwrap();
+}
+
+fn expr(expr: &Option<Expression>) -> String {
+  match expr {
+    Some(expr) => format!("Some(Expr::Static({:?}, {{ static PARSED: std::sync::OnceLock<crate::expression::Expression> = std::sync::OnceLock::new(); &PARSED }}))", expr.source()),
+    None => "None".into(),
+  }
+}
+
+fn cow(s: &Option<String>) -> String {
+  match s {
+    Some(s) => format!("Some(std::borrow::Cow::Borrowed({:?}))", s),
+    None => "None".into(),
+  }
+}
+
+/// Returns the names of all features declared in the crate's manifest.
+fn manifest_features() -> Vec<String> {
+  let manifest_path = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("Cargo.toml");
+  println!("cargo:rerun-if-changed={}", manifest_path.display());
+
+  let manifest = fs::read_to_string(&manifest_path).unwrap();
+
+  manifest.lines()
+    .skip_while(|line| line.trim() != "[features]")
+    .skip(1)
+    .take_while(|line| !line.trim_start().starts_with('['))
+    .filter_map(|line| line.split('=').next())
+    .map(|name| name.trim().trim_matches('"').to_owned())
+    .filter(|name| !name.is_empty() && !name.starts_with('#'))
+    .collect()
+}
+
+/// Returns the line number of the command `name` in the `commands` section.
+fn command_line(content: &str, name: &str) -> Option<usize> {
+  let key = format!("{}:", name);
+
+  content.lines().enumerate()
+    .skip_while(|(_, line)| !line.starts_with("commands:"))
+    .find(|(_, line)| line.trim_start() == key || line.trim_start().starts_with(&format!("{} ", key)))
+    .map(|(i, _)| i + 1)
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Configuration {
   pub device: Device,
-  pub commands: HashMap<String, Command>,
+  pub commands: HashMap<String, RawCommand>,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct Device {
-  protocol: String
+impl Configuration {
+  pub fn parse(content: &str) -> Result<Configuration, String> {
+    let value = serde_yaml::from_str::<serde_yaml::Value>(content).map_err(|err| err.to_string())?;
+    let value = yaml_merge_keys::merge_keys_serde(value).map_err(|err| err.to_string())?;
+    serde_yaml::from_value(value).map_err(|err| err.to_string())
+  }
+
+  /// Checks all commands, returning the name of the offending command and a message for every problem.
+  pub fn validate(&self) -> Vec<(&str, String)> {
+    let mut names = self.commands.keys().map(|name| name.as_str()).collect::<Vec<_>>();
+    names.sort();
+
+    let mut errors = Vec::new();
+
+    for name in &names {
+      for message in self.commands[*name].validate() {
+        errors.push((*name, message));
+      }
+    }
+
+    // Composite values intentionally cover the values of other commands.
+    let ranges = names.into_iter()
+      .filter(|name| self.commands[*name].fields.is_none())
+      .map(|name| {
+        let command = &self.commands[name];
+        (name, validate::value_range(command.addr, command.byte_pos(), command.byte_len(), command.bit_pos, command.bit_len))
+      })
+      .collect::<Vec<_>>();
+
+    errors.extend(validate::overlaps(&ranges));
+
+    errors
+  }
 }
 
-/// A command which can be executed on an Optolink connection.
-#[derive(Deserialize)]
-pub struct Command {
-  addr: u16,
-  mode: AccessMode,
-  unit: Unit,
-  block_len: Option<usize>,
-  byte_len: Option<usize>,
-  byte_pos: Option<usize>,
-  bit_pos: Option<usize>,
-  bit_len: Option<usize>,
-  factor: Option<f64>,
-  mapping: Option<HashMap<Vec<u8>, String>>,
+#[derive(Debug, Deserialize)]
+pub struct Device {
+  protocol: String,
+  id: Option<Vec<u8>>,
+  /// Additional names under which the device type is exported.
+  #[serde(default)]
+  aliases: Vec<String>,
 }
 
-impl fmt::Debug for Command {
+impl fmt::Debug for RawCommand {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    let block_len = self.block_len.unwrap_or_else(|| self.unit.size());
-    let byte_len = self.byte_len.unwrap_or_else(|| self.unit.size());
-    let byte_pos = self.byte_pos.unwrap_or(0);
+    let block_len = self.block_len();
+    let byte_len = self.byte_len();
+    let byte_pos = self.byte_pos();
 
     let mapping = if let Some(mapping) = &self.mapping {
       let mut map = phf_codegen::Map::new();
@@ -96,7 +254,17 @@ impl fmt::Debug for Command {
         map.entry(Bytes::from_bytes(k), &format!("{:?}", v));
       }
 
-      format!("Some({})", map.build())
+      format!("Some(Mapping::Static({}))", map.build())
+    } else {
+      "None".into()
+    };
+
+    let fields = if self.fields.is_some() {
+      let fields = self.field_commands().into_iter().map(|(name, field)| {
+        format!("({:?}, {:?})", name, field)
+      }).collect::<Vec<_>>();
+
+      format!("Some(Fields::Static(&[{}]))", fields.join(", "))
     } else {
       "None".into()
     };
@@ -111,12 +279,20 @@ impl fmt::Debug for Command {
        .field("bit_len", &self.bit_len)
        .field("bit_pos", &self.bit_pos)
        .field("factor", &self.factor.unwrap_or(1.0))
+       .field("get_expr", &format_args!("{}", expr(&self.get_expr)))
+       .field("set_expr", &format_args!("{}", expr(&self.set_expr().unwrap_or(None))))
        .field("mapping", &format_args!("{}", mapping))
+       .field("fields", &format_args!("{}", fields))
+       .field("description", &format_args!("{}", cow(&self.description)))
+       .field("unit_symbol", &format_args!("{}", cow(&self.unit_symbol)))
+       .field("min", &self.min)
+       .field("max", &self.max)
+       .field("step", &self.step)
        .finish()
   }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum AccessMode {
   Read,
   Write,
@@ -132,12 +308,12 @@ impl<'de> Deserialize<'de> for AccessMode {
       "read" => Ok(AccessMode::Read),
       "write" => Ok(AccessMode::Write),
       "read_write" => Ok(AccessMode::ReadWrite),
-      variant => Err(de::Error::unknown_variant(&variant, &["read", "write", "read_write"])),
+      variant => Err(de::Error::unknown_variant(variant, &["read", "write", "read_write"])),
     }
   }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Unit {
   I8,
   I16,
@@ -147,6 +323,25 @@ pub enum Unit {
   U32,
   SysTime,
   CycleTime,
+  Array,
+  Map,
+}
+
+impl fmt::Display for Unit {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Unit::I8 => write!(f, "i8"),
+      Unit::I16 => write!(f, "i16"),
+      Unit::I32 => write!(f, "i32"),
+      Unit::U8 => write!(f, "u8"),
+      Unit::U16 => write!(f, "u16"),
+      Unit::U32 => write!(f, "u32"),
+      Unit::SysTime => write!(f, "systime"),
+      Unit::CycleTime => write!(f, "cycletime"),
+      Unit::Array => write!(f, "array"),
+      Unit::Map => write!(f, "map"),
+    }
+  }
 }
 
 impl Unit {
@@ -160,6 +355,7 @@ impl Unit {
       Unit::U32 => std::mem::size_of::<u32>(),
       Unit::SysTime => std::mem::size_of::<SysTime>(),
       Unit::CycleTime => std::mem::size_of::<CycleTime>(),
+      Unit::Array | Unit::Map => 0,
     }
   }
 }
@@ -178,7 +374,9 @@ impl<'de> Deserialize<'de> for Unit {
       "u32" => Ok(Unit::U32),
       "systime" => Ok(Unit::SysTime),
       "cycletime" => Ok(Unit::CycleTime),
-      variant => Err(de::Error::unknown_variant(&variant, &["i8", "i16", "i32", "u8", "u16", "u32", "systime", "cycletime"])),
+      "array" => Ok(Unit::Array),
+      "map" => Ok(Unit::Map),
+      variant => Err(de::Error::unknown_variant(variant, &["i8", "i16", "i32", "u8", "u16", "u32", "systime", "cycletime", "array", "map"])),
     }
   }
 }
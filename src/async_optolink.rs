@@ -0,0 +1,169 @@
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::time::timeout;
+use tokio_serial::{DataBits, Parity, SerialPortBuilderExt, StopBits};
+
+use crate::{AsyncProtocol, Optolink, ProtocolKind, protocol::{Kw2, P300}};
+
+/// A byte stream over which an [`AsyncOptolink`] connection can be established.
+///
+/// This is implemented for all types implementing `AsyncRead` and `AsyncWrite`.
+pub trait AsyncTransport: AsyncRead + AsyncWrite + Unpin + Send + fmt::Debug {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + fmt::Debug> AsyncTransport for T {}
+
+async fn with_timeout<T>(duration: Duration, future: impl Future<Output = io::Result<T>>) -> io::Result<T> {
+  match timeout(duration, future).await {
+    Ok(res) => res,
+    Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "operation timed out")),
+  }
+}
+
+/// An asynchronous Optolink connection via either a serial or TCP connection.
+#[derive(Debug)]
+pub struct AsyncOptolink {
+  device: Box<dyn AsyncTransport>,
+  timeout: Option<Duration>,
+}
+
+impl AsyncOptolink {
+  /// Creates an Optolink connection using the given transport.
+  pub fn new(transport: impl AsyncTransport + 'static) -> AsyncOptolink {
+    log::trace!("AsyncOptolink::new(…)");
+
+    AsyncOptolink { device: Box::new(transport), timeout: None }
+  }
+
+  /// Opens a serial device.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use vcontrol::AsyncOptolink;
+  ///
+  /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+  /// let mut device = AsyncOptolink::open("/dev/ttyUSB0")?;
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn open(port: impl AsRef<Path>) -> io::Result<AsyncOptolink> {
+    log::trace!("AsyncOptolink::open(…)");
+
+    let port = port.as_ref();
+
+    let tty = tokio_serial::new(port.to_string_lossy(), 4800)
+      .parity(Parity::Even)
+      .stop_bits(StopBits::Two)
+      .data_bits(DataBits::Eight)
+      .open_native_async()
+      .map_err(|err| {
+        let err = io::Error::from(err);
+
+        if err.kind() == io::ErrorKind::NotFound {
+          return io::Error::new(err.kind(), format!("{}: {}", err, port.display()))
+        }
+
+        err
+      })?;
+
+    Ok(AsyncOptolink::new(tty))
+  }
+
+  /// Connects to a device via TCP.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use vcontrol::AsyncOptolink;
+  ///
+  /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+  /// let mut device = AsyncOptolink::connect(("localhost", 1234)).await?;
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub async fn connect(addr: impl ToSocketAddrs) -> io::Result<AsyncOptolink> {
+    log::trace!("AsyncOptolink::connect(…)");
+
+    let stream = TcpStream::connect(addr).await?;
+    Ok(AsyncOptolink::new(stream))
+  }
+
+  /// Purge all contents of the input buffer.
+  pub async fn purge(&mut self) -> io::Result<()> {
+    log::trace!("AsyncOptolink::purge()");
+
+    let mut buf = [0; 64];
+
+    // A zero timeout only polls the read once, so this
+    // stops as soon as no more data is available.
+    while let Ok(Ok(n)) = timeout(Duration::new(0, 0), self.device.read(&mut buf)).await {
+      if n == 0 {
+        break
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Set timeout for operations on the Optolink device.
+  pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+    self.timeout = timeout;
+  }
+
+  /// Returns the timeout for operations on the Optolink device, or the default timeout if none is set.
+  pub(crate) fn timeout(&self) -> Duration {
+    self.timeout.unwrap_or(Optolink::TIMEOUT)
+  }
+
+  /// Detects which protocol the device supports.
+  ///
+  /// This first tries to negotiate the `P300` protocol and
+  /// falls back to waiting for a `Kw2` synchronization byte.
+  pub async fn detect_protocol(&mut self) -> io::Result<ProtocolKind> {
+    log::trace!("AsyncOptolink::detect_protocol()");
+
+    let timeout = self.timeout;
+
+    self.set_timeout(Some(Optolink::DETECT_TIMEOUT));
+    let p300 = <P300 as AsyncProtocol>::negotiate(self).await;
+    self.set_timeout(timeout);
+
+    match p300 {
+      Ok(()) => return Ok(ProtocolKind::P300),
+      Err(err) => log::debug!("AsyncOptolink::detect_protocol() P300 negotiation failed: {}", err),
+    }
+
+    Kw2::sync_async(self).await?;
+
+    Ok(ProtocolKind::Kw2)
+  }
+
+  /// Reads the exact number of bytes required to fill `buf`.
+  pub async fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+    log::trace!("AsyncOptolink::read_exact(…)");
+
+    let duration = self.timeout();
+    with_timeout(duration, async move { self.device.read_exact(buf).await.map(|_| ()) }).await
+  }
+
+  /// Writes all bytes in `buf`.
+  pub async fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+    log::trace!("AsyncOptolink::write_all(…)");
+
+    let duration = self.timeout();
+    with_timeout(duration, self.device.write_all(buf)).await
+  }
+
+  /// Flushes the output buffer.
+  pub async fn flush(&mut self) -> io::Result<()> {
+    log::trace!("AsyncOptolink::flush()");
+
+    with_timeout(self.timeout(), self.device.flush()).await
+  }
+}
@@ -22,7 +22,7 @@ pub fn recv_sync(socket: &mut TcpStream, string: &str) -> Result<String, io::Err
     response.push(buf[0] as char);
 
     if response.ends_with(string) {
-      response.split_off(response.len() - string.len());
+      response.truncate(response.len() - string.len());
       break;
     }
   }
@@ -40,7 +40,7 @@ pub fn send_server(socket: &mut TcpStream, string: &str) -> Result<(), io::Error
 
   socket.set_nonblocking(false)?;
 
-  socket.write(string.as_bytes())?;
+  socket.write_all(string.as_bytes())?;
   socket.flush()?;
 
   Ok(())
@@ -136,14 +136,14 @@ fn main() {
   let commands: Vec<String> = if let Some(commands) = matches.values_of("command") {
     commands.flat_map(|command| command.split(",")).map(|s| s.to_owned()).collect()
   } else if let Some(commandfile) = matches.value_of("commandfile") {
-    let file = File::open(commandfile).expect(&format!("could not open command file '{}'", commandfile));
-    BufReader::new(file).lines().collect::<Result<_, _>>().expect(&format!("error reading command file '{}'", commandfile))
+    let file = File::open(commandfile).unwrap_or_else(|_| panic!("could not open command file '{}'", commandfile));
+    BufReader::new(file).lines().collect::<Result<_, _>>().unwrap_or_else(|_| panic!("error reading command file '{}'", commandfile))
   } else {
     Vec::new()
   };
 
   let mut out: Box<dyn Write> = if let Some(outfile) = matches.value_of("outfile") {
-    Box::new(File::create(outfile).expect(&format!("could not create output file '{}'", outfile)))
+    Box::new(File::create(outfile).unwrap_or_else(|_| panic!("could not create output file '{}'", outfile)))
   } else {
     Box::new(io::stdout())
   };
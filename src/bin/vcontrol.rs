@@ -1,9 +1,8 @@
 use std::process::exit;
 
 use clap::{crate_version, Arg, App, SubCommand, AppSettings::ArgRequiredElseHelp};
-use serde_json;
 
-use vcontrol::{Optolink, VControl, device::V200KW2, Value};
+use vcontrol::{Configuration, DynVControl, Error, Optolink, VControl, Value, WeeklySchedule};
 
 fn main() {
   let app = App::new("vcontrol")
@@ -29,6 +28,17 @@ fn main() {
                 .takes_value(true)
                 .conflicts_with("device")
                 .help("port of the device"))
+              .arg(Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .takes_value(true)
+                .help("path of a YAML device configuration (default: detect device)"))
+              .arg(Arg::with_name("protocol")
+                .long("protocol")
+                .takes_value(true)
+                .possible_values(&["Kw2", "P300"])
+                .conflicts_with("config")
+                .help("protocol used by the device (default: detect protocol)"))
               .subcommand(SubCommand::with_name("get")
                 .about("get value")
                 .arg(Arg::with_name("command")
@@ -41,13 +51,27 @@ fn main() {
                   .required(true))
                 .arg(Arg::with_name("value")
                   .help("value")
+                  .required(true)))
+              .subcommand(SubCommand::with_name("get-error-history")
+                .about("get error history as JSON"))
+              .subcommand(SubCommand::with_name("get-schedule")
+                .about("get weekly schedule as JSON")
+                .arg(Arg::with_name("program")
+                  .help("name of the timer program, e.g. hotwater")
+                  .required(true)))
+              .subcommand(SubCommand::with_name("set-schedule")
+                .about("set weekly schedule from JSON")
+                .arg(Arg::with_name("program")
+                  .help("name of the timer program, e.g. hotwater")
+                  .required(true))
+                .arg(Arg::with_name("schedule")
+                  .help("JSON object with the keys mon to sun")
                   .required(true)));
 
   let matches = app.get_matches();
 
-  let mut vcontrol = if let Some(device) = matches.value_of("device") {
+  let device = if let Some(device) = matches.value_of("device") {
     Optolink::open(device)
-      .map(|device| VControl::<V200KW2>::connect(device).unwrap())
   } else if let Some(port) = matches.value_of("port") {
     let host = matches.value_of("host").unwrap_or("localhost");
     let port = port.parse().unwrap_or_else(|_| {
@@ -56,10 +80,22 @@ fn main() {
     });
 
     Optolink::connect((host, port))
-      .map(|device| VControl::<V200KW2>::connect(device).unwrap())
   } else {
     unreachable!()
-  }.unwrap_or_else(|err| {
+  };
+
+  let vcontrol = device.map_err(Error::from).and_then(|device| {
+    if let Some(config) = matches.value_of("config") {
+      let config = Configuration::open(config)?;
+      Ok(Box::new(VControl::connect_with(device, config)?) as Box<dyn DynVControl>)
+    } else if let Some(protocol) = matches.value_of("protocol") {
+      vcontrol::connect_with_protocol(device, protocol.parse()?)
+    } else {
+      vcontrol::connect(device)
+    }
+  });
+
+  let mut vcontrol = vcontrol.unwrap_or_else(|err| {
     eprintln!("Error: {}", err);
     exit(1);
   });
@@ -82,7 +118,7 @@ fn main() {
     let command = matches.value_of("command").unwrap();
     let value = matches.value_of("value").unwrap();
 
-    let value: Value = serde_json::from_str(&value).unwrap_or(Value::String(value.to_string()));
+    let value: Value = serde_json::from_str(value).unwrap_or_else(|_| value.parse().unwrap());
 
     match vcontrol.set(command, &value) {
       Ok(()) => {},
@@ -92,4 +128,48 @@ fn main() {
       }
     }
   }
+
+  if matches.subcommand_matches("get-error-history").is_some() {
+    match vcontrol.get_error_history() {
+      Ok(history) => {
+        println!("{}", serde_json::to_string(&history).unwrap());
+      },
+      Err(err) => {
+        eprintln!("Error: {}", err);
+        exit(1);
+      }
+    }
+  }
+
+  if let Some(matches) = matches.subcommand_matches("get-schedule") {
+    let program = matches.value_of("program").unwrap();
+
+    match vcontrol.get_schedule(program) {
+      Ok(schedule) => {
+        println!("{}", serde_json::to_string(&schedule).unwrap());
+      },
+      Err(err) => {
+        eprintln!("Error: {}", err);
+        exit(1);
+      }
+    }
+  }
+
+  if let Some(matches) = matches.subcommand_matches("set-schedule") {
+    let program = matches.value_of("program").unwrap();
+    let schedule = matches.value_of("schedule").unwrap();
+
+    let schedule: WeeklySchedule = serde_json::from_str(schedule).unwrap_or_else(|err| {
+      eprintln!("Error: Could not parse schedule: {}", err);
+      exit(1);
+    });
+
+    match vcontrol.set_schedule(program, &schedule) {
+      Ok(()) => {},
+      Err(err) => {
+        eprintln!("Error: {}", err);
+        exit(1);
+      }
+    }
+  }
 }
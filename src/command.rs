@@ -1,27 +1,40 @@
-use phf;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::OnceLock;
+
 use serde::de::{self, Deserialize, Deserializer};
 
-use crate::{Error, Optolink, protocol::Protocol, Unit, Value, ToBytes, types::Bytes};
+use crate::{Error, FromBytes, Optolink, ProtocolKind, Unit, Value, ToBytes, expression::{Expression, Number}, types::{Bytes, Decimal}};
+#[cfg(feature = "async")]
+use crate::AsyncOptolink;
 
-#[derive(Debug, Clone, Copy)]
-pub(crate) enum AccessMode {
+/// Whether a command can be read, written or both.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessMode {
   Read,
   Write,
   ReadWrite,
 }
 
 impl AccessMode {
+  /// Returns whether the command can be read.
   pub fn is_read(self) -> bool {
-    match self {
-      AccessMode::Read | AccessMode::ReadWrite => true,
-      _ => false,
-    }
+    matches!(self, AccessMode::Read | AccessMode::ReadWrite)
   }
 
+  /// Returns whether the command can be written.
   pub fn is_write(self) -> bool {
+    matches!(self, AccessMode::Write | AccessMode::ReadWrite)
+  }
+}
+
+impl fmt::Display for AccessMode {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     match self {
-      AccessMode::Write | AccessMode::ReadWrite => true,
-      _ => false,
+      AccessMode::Read => write!(f, "read"),
+      AccessMode::Write => write!(f, "write"),
+      AccessMode::ReadWrite => write!(f, "read_write"),
     }
   }
 }
@@ -35,12 +48,101 @@ impl<'de> Deserialize<'de> for AccessMode {
       "read" => Ok(AccessMode::Read),
       "write" => Ok(AccessMode::Write),
       "read_write" => Ok(AccessMode::ReadWrite),
-      variant => Err(de::Error::unknown_variant(&variant, &["read", "write", "read_write"])),
+      variant => Err(de::Error::unknown_variant(variant, &["read", "write", "read_write"])),
+    }
+  }
+}
+
+mod validate;
+pub(crate) use self::validate::{RawCommand, overlaps};
+
+/// A mapping between raw bytes and their textual representation.
+///
+/// Generated devices use a static `phf` map, runtime-loaded devices an owned one.
+#[derive(Debug)]
+pub(crate) enum Mapping {
+  #[allow(dead_code)] // Unused if no device types are compiled in.
+  Static(phf::map::Map<Bytes, &'static str>),
+  Dynamic(HashMap<Vec<u8>, String>),
+}
+
+impl Mapping {
+  pub fn get(&self, bytes: &[u8]) -> Option<&str> {
+    match self {
+      Mapping::Static(map) => map.get(&Bytes::from_bytes(bytes)).copied(),
+      Mapping::Dynamic(map) => map.get(bytes).map(|s| s.as_str()),
+    }
+  }
+
+  /// Returns the bytes mapped to the given text.
+  pub fn key(&self, text: &str) -> Option<Vec<u8>> {
+    match self {
+      Mapping::Static(map) => map.entries().find_map(|(key, value)| if *value == text { Some(key.to_bytes()) } else { None }),
+      Mapping::Dynamic(map) => map.iter().find_map(|(key, value)| if value == text { Some(key.clone()) } else { None }),
+    }
+  }
+
+  pub fn keys(&self) -> Vec<Vec<u8>> {
+    match self {
+      Mapping::Static(map) => map.keys().map(|key| key.to_bytes()).collect(),
+      Mapping::Dynamic(map) => map.keys().cloned().collect(),
+    }
+  }
+}
+
+/// The named sub-fields of a command with unit `array` or `map`, which are all decoded from the same block.
+///
+/// For an `array`, the names are the indices of the fields.
+#[derive(Debug)]
+pub(crate) enum Fields {
+  #[allow(dead_code)] // Unused if no device types are compiled in.
+  Static(&'static [(&'static str, Command)]),
+  Dynamic(Vec<(String, Command)>),
+}
+
+impl Fields {
+  pub fn iter(&self) -> Box<dyn Iterator<Item = (&str, &Command)> + '_> {
+    match self {
+      Fields::Static(fields) => Box::new(fields.iter().map(|(name, field)| (*name, field))),
+      Fields::Dynamic(fields) => Box::new(fields.iter().map(|(name, field)| (name.as_str(), field))),
+    }
+  }
+}
+
+/// An expression converting between the raw value and the value of a command.
+///
+/// Generated devices store the source, which was validated at build time and is parsed on first use,
+/// runtime-loaded devices the parsed expression.
+#[derive(Debug)]
+pub(crate) enum Expr {
+  #[allow(dead_code)] // Unused if no device types are compiled in.
+  Static(&'static str, &'static OnceLock<Expression>),
+  Dynamic(Expression),
+}
+
+impl Expr {
+  pub fn source(&self) -> &str {
+    match self {
+      Expr::Static(source, _) => source,
+      Expr::Dynamic(expression) => expression.source(),
+    }
+  }
+
+  pub fn expression(&self) -> &Expression {
+    match self {
+      Expr::Static(source, parsed) => parsed.get_or_init(|| source.parse().expect("expression was validated at build time")),
+      Expr::Dynamic(expression) => expression,
     }
   }
+
+  pub fn eval(&self, value: Number, bytes: &[u8]) -> Result<Number, Error> {
+    self.expression().eval(value, bytes).map_err(|err| Error::InvalidArgument(format!("failed to evaluate `{}`: {}", self.source(), err)))
+  }
 }
 
 /// A command which can be executed on an Optolink connection.
+///
+/// This is a read-only view of a command's definition, see [`Device::iter`](crate::Device::iter).
 #[derive(Debug)]
 pub struct Command {
   pub(crate) addr: u16,
@@ -52,28 +154,209 @@ pub struct Command {
   pub(crate) bit_pos: Option<usize>,
   pub(crate) bit_len: Option<usize>,
   pub(crate) factor: f64,
-  pub(crate) mapping: Option<phf::map::Map<Bytes, &'static str>>,
+  pub(crate) get_expr: Option<Expr>,
+  pub(crate) set_expr: Option<Expr>,
+  pub(crate) mapping: Option<Mapping>,
+  pub(crate) fields: Option<Fields>,
+  pub(crate) description: Option<Cow<'static, str>>,
+  pub(crate) unit_symbol: Option<Cow<'static, str>>,
+  pub(crate) min: Option<f64>,
+  pub(crate) max: Option<f64>,
+  pub(crate) step: Option<f64>,
 }
 
 impl Command {
+  /// Returns the address of the command's block.
+  pub fn addr(&self) -> u16 {
+    self.addr
+  }
+
+  /// Returns whether the command can be read, written or both.
+  pub fn mode(&self) -> AccessMode {
+    self.mode
+  }
+
+  /// Returns the type of the raw value.
+  pub fn unit(&self) -> &Unit {
+    &self.unit
+  }
+
+  /// Returns the number of bytes read from or written to `addr`.
+  pub fn block_len(&self) -> usize {
+    self.block_len
+  }
+
+  /// Returns the number of bytes of the value within the block.
+  pub fn byte_len(&self) -> usize {
+    self.byte_len
+  }
+
+  /// Returns the position of the value within the block.
+  pub fn byte_pos(&self) -> usize {
+    self.byte_pos
+  }
+
+  /// Returns the position of the value's first bit within the block, if it is a bit field.
+  pub fn bit_pos(&self) -> Option<usize> {
+    self.bit_pos
+  }
+
+  /// Returns the number of bits of the value, if it is a bit field.
+  pub fn bit_len(&self) -> Option<usize> {
+    self.bit_pos.map(|_| self.bit_len.unwrap_or(1))
+  }
+
+  /// Returns the factor the raw value is divided by.
+  pub fn factor(&self) -> f64 {
+    self.factor
+  }
+
+  /// Returns the expression converting the raw value `$v` and the block's bytes `$bN` into the value, if any.
+  ///
+  /// It is used instead of the `factor`.
+  pub fn get_expr(&self) -> Option<&str> {
+    self.get_expr.as_ref().map(Expr::source)
+  }
+
+  /// Returns the expression converting the value `$v` into the raw value, if any.
+  ///
+  /// It is used instead of the `factor`. For writable commands, it is derived from the `get_expr` if omitted.
+  pub fn set_expr(&self) -> Option<&str> {
+    self.set_expr.as_ref().map(Expr::source)
+  }
+
+  /// Returns the raw bytes and the corresponding text of every variant, if the value is mapped.
+  pub fn mapping(&self) -> Option<Vec<(Vec<u8>, &str)>> {
+    self.mapping.as_ref().map(|mapping| {
+      let mut keys = mapping.keys();
+      keys.sort();
+
+      keys.into_iter().filter_map(|key| mapping.get(&key).map(|text| (key, text))).collect()
+    })
+  }
+
+  /// Returns the name and definition of every field, if the unit is `array` or `map`.
+  pub fn fields(&self) -> Option<Vec<(&str, &Command)>> {
+    self.fields.as_ref().map(|fields| fields.iter().collect())
+  }
+
+  /// Returns a human-readable description of the command.
+  pub fn description(&self) -> Option<&str> {
+    self.description.as_deref()
+  }
+
+  /// Returns the symbol of the physical unit of the value, e.g. `°C`.
+  pub fn unit_symbol(&self) -> Option<&str> {
+    self.unit_symbol.as_deref()
+  }
+
+  /// Returns the smallest value which can be set.
+  pub fn min(&self) -> Option<f64> {
+    self.min
+  }
+
+  /// Returns the largest value which can be set.
+  pub fn max(&self) -> Option<f64> {
+    self.max
+  }
+
+  /// Returns the increment in which the value can be changed, starting from [`min`](Command::min) or 0.
+  pub fn step(&self) -> Option<f64> {
+    self.step
+  }
+
+  /// The addresses of the bytes actually containing the value.
+  pub(crate) fn value_range(&self) -> validate::ValueRange {
+    validate::value_range(self.addr, self.byte_pos, self.byte_len, self.bit_pos, self.bit_len)
+  }
+
   #[inline]
-  fn addr(&self) -> Vec<u8> {
+  fn addr_bytes(&self) -> Vec<u8> {
     self.addr.to_be().to_bytes()
   }
 
-  pub fn get<P: Protocol>(&self, o: &mut Optolink) -> Result<Value, Error> {
+  pub(crate) fn get(&self, o: &mut Optolink, protocol: ProtocolKind) -> Result<Value, Error> {
     log::trace!("Command::get(…)");
 
+    self.decode(self.read_block(o, protocol)?)
+  }
+
+  /// Reads the whole block without decoding it.
+  pub(crate) fn read_block(&self, o: &mut Optolink, protocol: ProtocolKind) -> Result<Vec<u8>, Error> {
+    self.check_read()?;
+
+    let mut buf = vec![0; self.block_len];
+    protocol.get(o, &self.addr_bytes(), &mut buf)?;
+
+    Ok(buf)
+  }
+
+  pub(crate) fn set(&self, o: &mut Optolink, protocol: ProtocolKind, input: &Value) -> Result<(), Error> {
+    log::trace!("Command::set(…)");
+
+    self.check_write()?;
+
+    protocol.set(o, &self.addr_bytes(), &self.encode(input)?).map_err(Into::into)
+  }
+
+  #[cfg(feature = "async")]
+  pub(crate) async fn get_async(&self, o: &mut AsyncOptolink, protocol: ProtocolKind) -> Result<Value, Error> {
+    log::trace!("Command::get_async(…)");
+
+    self.decode(self.read_block_async(o, protocol).await?)
+  }
+
+  /// Reads the whole block without decoding it.
+  #[cfg(feature = "async")]
+  pub(crate) async fn read_block_async(&self, o: &mut AsyncOptolink, protocol: ProtocolKind) -> Result<Vec<u8>, Error> {
+    self.check_read()?;
+
+    let mut buf = vec![0; self.block_len];
+    protocol.get_async(o, &self.addr_bytes(), &mut buf).await?;
+
+    Ok(buf)
+  }
+
+  #[cfg(feature = "async")]
+  pub(crate) async fn set_async(&self, o: &mut AsyncOptolink, protocol: ProtocolKind, input: &Value) -> Result<(), Error> {
+    log::trace!("Command::set_async(…)");
+
+    self.check_write()?;
+
+    protocol.set_async(o, &self.addr_bytes(), &self.encode(input)?).await.map_err(Into::into)
+  }
+
+  fn check_read(&self) -> Result<(), Error> {
     if !self.mode.is_read() {
       return Err(Error::UnsupportedMode(format!("Address 0x{:04X} does not support reading.", self.addr)))
     }
 
-    let block_len = self.block_len;
+    Ok(())
+  }
+
+  fn check_write(&self) -> Result<(), Error> {
+    if !self.mode.is_write() {
+      return Err(Error::UnsupportedMode(format!("Address 0x{:04X} does not support writing.", self.addr)))
+    }
+
+    Ok(())
+  }
+
+  /// Converts a block read from the device into a value.
+  pub(crate) fn decode(&self, mut buf: Vec<u8>) -> Result<Value, Error> {
+    if let Some(fields) = &self.fields {
+      let values = fields.iter().map(|(name, field)| Ok((name, field.decode(buf.clone())?)));
+
+      return match self.unit {
+        Unit::Array => values.map(|value| value.map(|(_, value)| value)).collect::<Result<_, Error>>().map(Value::Array),
+        _ => values.map(|value| value.map(|(name, value)| (name.to_owned(), value))).collect::<Result<_, Error>>().map(Value::Map),
+      }
+    }
+
     let byte_len = self.byte_len;
     let byte_pos = self.byte_pos;
 
-    let mut buf = vec![0; block_len];
-    P::get(o, &self.addr(), &mut buf)?;
+    let block = if self.get_expr.is_some() { buf.clone() } else { Vec::new() };
 
     if let Some(bit_pos) = self.bit_pos {
       let byte = buf[bit_pos / 8];
@@ -83,16 +366,200 @@ impl Command {
       buf.push((byte << (bit_pos % 8)) >> (8 - bit_len));
     }
 
-    self.unit.bytes_to_output(&buf[byte_pos..(byte_pos + byte_len)], self.factor, &self.mapping)
+    let bytes = &buf[byte_pos..(byte_pos + byte_len)];
+
+    if let Some(get_expr) = &self.get_expr {
+      let raw = self.unit.raw_value(bytes)
+                  .ok_or_else(|| Error::InvalidArgument(format!("unit {} is not numeric", self.unit)))?;
+
+      // Integers and exact divisions by powers of ten are decimals, like values scaled by a `factor`.
+      return get_expr.eval(Number::Int(raw), &block).map(|n| match n {
+        Number::Int(n) => Value::Decimal(Decimal::new(n, 0)),
+        Number::Float(n) => Decimal::from_f64(n, 9).map(Value::Decimal).unwrap_or(Value::Number(n)),
+      })
+    }
+
+    self.unit.bytes_to_output(bytes, self.factor, &self.mapping)
   }
 
-  pub fn set<P: Protocol>(&self, o: &mut Optolink, input: &Value) -> Result<(), Error> {
-    log::trace!("Command::set(…)");
+  /// Converts a value into the bytes to be written to the device.
+  fn encode(&self, input: &Value) -> Result<Vec<u8>, Error> {
+    self.check_range(input)?;
 
-    if !self.mode.is_write() {
-      return Err(Error::UnsupportedMode(format!("Address 0x{:04X} does not support writing.", self.addr)))
+    if let Some(fields) = &self.fields {
+      return self.encode_fields(fields, input)
     }
 
-    P::set(o, &self.addr(), &self.unit.input_to_bytes(input, self.factor, &self.mapping)?).map_err(Into::into)
+    if let Some(set_expr) = &self.set_expr {
+      let n = match *input {
+        Value::Number(n) => n,
+        Value::Decimal(decimal) => decimal.to_f64(),
+        _ => return Err(Error::InvalidArgument(format!("expected number, found {:?}", input))),
+      };
+
+      let raw = set_expr.eval(Number::from_f64(n), &[])?;
+
+      return self.unit.input_to_bytes(&Value::Number(raw.into()), 1.0, &None)
+    }
+
+    self.unit.input_to_bytes(input, self.factor, &self.mapping)
+  }
+
+  /// Encodes every field of a composite value into its position within a single block.
+  fn encode_fields(&self, fields: &Fields, input: &Value) -> Result<Vec<u8>, Error> {
+    let values = match (&self.unit, input) {
+      (Unit::Array, Value::Array(values)) if values.len() == fields.iter().count() => {
+        fields.iter().zip(values).map(|((_, field), value)| (field, value)).collect::<Vec<_>>()
+      },
+      (Unit::Map, Value::Map(values)) if values.len() == fields.iter().count() => {
+        fields.iter().map(|(name, field)| {
+          values.get(name).map(|value| (field, value))
+            .ok_or_else(|| Error::InvalidArgument(format!("missing field {:?}", name)))
+        }).collect::<Result<Vec<_>, _>>()?
+      },
+      (unit, input) => {
+        return Err(Error::InvalidArgument(format!("expected {} with {} fields, found {}", unit, fields.iter().count(), input)))
+      },
+    };
+
+    let mut block = vec![0; self.block_len];
+
+    for (field, value) in values {
+      let bytes = field.encode(value)?;
+
+      if let Some(bit_pos) = field.bit_pos {
+        let bit_len = field.bit_len.unwrap_or(1);
+
+        if u16::from(bytes[0]) >= 1 << bit_len {
+          return Err(Error::InvalidArgument(format!("value {} does not fit into {} bit(s)", value, bit_len)))
+        }
+
+        block[bit_pos / 8] |= (bytes[0] << (8 - bit_len)) >> (bit_pos % 8);
+      } else {
+        if bytes.len() > field.byte_len {
+          return Err(Error::InvalidArgument(format!("value {} does not fit into {} byte(s)", value, field.byte_len)))
+        }
+
+        block[field.byte_pos..(field.byte_pos + bytes.len())].copy_from_slice(&bytes);
+      }
+    }
+
+    Ok(block)
+  }
+
+  fn check_range(&self, input: &Value) -> Result<(), Error> {
+    let n = match *input {
+      Value::Number(n) => Some(n),
+      Value::Decimal(decimal) => Some(decimal.to_f64()),
+      _ => None,
+    };
+
+    if let Some(n) = n {
+      match (self.min, self.max) {
+        (Some(min), Some(max)) if n < min || n > max => {
+          return Err(Error::InvalidArgument(format!("value {} is outside of the range {} to {}", n, min, max)))
+        },
+        (Some(min), None) if n < min => {
+          return Err(Error::InvalidArgument(format!("value {} is less than the minimum {}", n, min)))
+        },
+        (None, Some(max)) if n > max => {
+          return Err(Error::InvalidArgument(format!("value {} is greater than the maximum {}", n, max)))
+        },
+        _ => {},
+      }
+
+      if let Some(step) = self.step {
+        let base = self.min.unwrap_or(0.0);
+        let steps = (n - base) / step;
+
+        if (steps - steps.round()).abs() > 1e-9 {
+          return Err(Error::InvalidArgument(format!("value {} is not a multiple of the step {} from {}", n, step, base)))
+        }
+      }
+    }
+
+    Ok(())
+  }
+}
+
+impl RawCommand {
+  /// Validates the command and applies the defaults.
+  pub(crate) fn into_command(self) -> Result<Command, String> {
+    let errors = self.validate();
+    if !errors.is_empty() {
+      return Err(errors.join(", "))
+    }
+
+    let block_len = self.block_len();
+    let set_expr = self.set_expr()?;
+
+    let fields = match self.fields {
+      Some(_) => Some(Fields::Dynamic(
+        self.field_commands().into_iter()
+          .map(|(name, field)| field.into_command().map(|field| (name, field)))
+          .collect::<Result<_, _>>()?,
+      )),
+      None => None,
+    };
+
+    Ok(Command {
+      addr: self.addr,
+      mode: self.mode,
+      block_len,
+      byte_len: self.byte_len.unwrap_or_else(|| self.unit.size()),
+      byte_pos: self.byte_pos.unwrap_or(0),
+      bit_pos: self.bit_pos,
+      bit_len: self.bit_len,
+      factor: self.factor.unwrap_or(1.0),
+      get_expr: self.get_expr.map(Expr::Dynamic),
+      set_expr: set_expr.map(Expr::Dynamic),
+      mapping: self.mapping.map(Mapping::Dynamic),
+      fields,
+      unit: self.unit,
+      description: self.description.map(Cow::Owned),
+      unit_symbol: self.unit_symbol.map(Cow::Owned),
+      min: self.min,
+      max: self.max,
+      step: self.step,
+    })
+  }
+}
+
+impl<'de> Deserialize<'de> for Command {
+  fn deserialize<D>(deserializer: D) -> Result<Command, D::Error>
+  where
+      D: Deserializer<'de>,
+  {
+    RawCommand::deserialize(deserializer)?.into_command().map_err(de::Error::custom)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn encode_field_too_long() {
+    let mut command = serde_yaml::from_str::<Command>("
+      addr: 0x3536
+      mode: read_write
+      unit: array
+      fields:
+        - unit: u8
+        - unit: u16
+          byte_pos: 1
+    ").unwrap();
+
+    assert_eq!(command.encode(&"[7, 513]".parse().unwrap()).unwrap(), [7, 1, 2]);
+
+    // Configurations are validated, so only a corrupted field can be shorter than its unit.
+    if let Some(Fields::Dynamic(fields)) = &mut command.fields {
+      fields[1].1.byte_len = 1;
+    }
+
+    match command.encode(&"[7, 513]".parse().unwrap()) {
+      Err(Error::InvalidArgument(message)) => assert_eq!(message, "value 513 does not fit into 1 byte(s)"),
+      result => panic!("expected invalid argument, found {:?}", result),
+    }
   }
 }
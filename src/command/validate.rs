@@ -0,0 +1,299 @@
+//! Command definitions as written in a configuration and their validation.
+//!
+//! This module is shared with the build script, so generated devices and
+//! runtime-loaded configurations are checked using the same rules.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+
+use serde_derive::Deserialize;
+
+use super::{AccessMode, Expression, Unit};
+
+/// A command as written in a configuration, i.e. before defaults are applied.
+#[derive(Deserialize)]
+pub struct RawCommand {
+  pub addr: u16,
+  pub mode: AccessMode,
+  pub unit: Unit,
+  pub block_len: Option<usize>,
+  pub byte_len: Option<usize>,
+  pub byte_pos: Option<usize>,
+  pub bit_pos: Option<usize>,
+  pub bit_len: Option<usize>,
+  pub factor: Option<f64>,
+  pub get_expr: Option<Expression>,
+  pub set_expr: Option<Expression>,
+  pub mapping: Option<HashMap<Vec<u8>, String>>,
+  pub fields: Option<RawFields>,
+  pub description: Option<String>,
+  pub unit_symbol: Option<String>,
+  pub min: Option<f64>,
+  pub max: Option<f64>,
+  pub step: Option<f64>,
+}
+
+/// The `fields` of a command, either a list for unit `array` or a map for unit `map`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum RawFields {
+  List(Vec<RawField>),
+  Map(BTreeMap<String, RawField>),
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RawField {
+  pub unit: Unit,
+  pub byte_len: Option<usize>,
+  pub byte_pos: Option<usize>,
+  pub bit_pos: Option<usize>,
+  pub bit_len: Option<usize>,
+  pub factor: Option<f64>,
+  pub mapping: Option<HashMap<Vec<u8>, String>>,
+  pub unit_symbol: Option<String>,
+}
+
+impl RawField {
+  /// The end of the field within the block.
+  fn end(&self) -> usize {
+    match self.bit_pos {
+      Some(bit_pos) => bit_pos / 8 + 1,
+      None => self.byte_pos.unwrap_or(0) + self.byte_len.unwrap_or_else(|| self.unit.size()),
+    }
+  }
+}
+
+impl RawCommand {
+  pub fn block_len(&self) -> usize {
+    self.block_len.unwrap_or_else(|| {
+      match self.fields.is_some() {
+        true => self.raw_fields().iter().map(|(_, field)| field.end()).max().unwrap_or(0),
+        false => self.unit.size(),
+      }
+    })
+  }
+
+  pub fn byte_len(&self) -> usize {
+    self.byte_len.unwrap_or_else(|| self.unit.size())
+  }
+
+  pub fn byte_pos(&self) -> usize {
+    self.byte_pos.unwrap_or(0)
+  }
+
+  fn raw_fields(&self) -> Vec<(String, RawField)> {
+    match &self.fields {
+      Some(RawFields::List(fields)) => fields.iter().enumerate().map(|(i, field)| (i.to_string(), field.clone())).collect(),
+      Some(RawFields::Map(fields)) => fields.iter().map(|(name, field)| (name.clone(), field.clone())).collect(),
+      None => Vec::new(),
+    }
+  }
+
+  /// Returns every field as a command sharing this command's block.
+  pub fn field_commands(&self) -> Vec<(String, RawCommand)> {
+    let block_len = self.block_len();
+
+    self.raw_fields().into_iter().map(|(name, field)| (name, RawCommand {
+      addr: self.addr,
+      mode: self.mode,
+      unit: field.unit,
+      block_len: Some(block_len),
+      byte_len: field.byte_len,
+      byte_pos: field.byte_pos,
+      bit_pos: field.bit_pos,
+      bit_len: field.bit_len,
+      factor: field.factor,
+      get_expr: None,
+      set_expr: None,
+      mapping: field.mapping,
+      fields: None,
+      description: None,
+      unit_symbol: field.unit_symbol,
+      min: None,
+      max: None,
+      step: None,
+    })).collect()
+  }
+
+  /// The `set_expr`, which is derived from the `get_expr` for writable commands if omitted.
+  pub fn set_expr(&self) -> Result<Option<Expression>, String> {
+    match (&self.get_expr, &self.set_expr) {
+      (Some(get_expr), None) if matches!(self.mode, AccessMode::Write | AccessMode::ReadWrite) => {
+        let inverse = get_expr.inverse()
+          .map_err(|err| format!("cannot derive set_expr from get_expr `{}`: {}", get_expr.source(), err))?;
+
+        Ok(Some(inverse))
+      },
+      (_, set_expr) => Ok(set_expr.clone()),
+    }
+  }
+
+  /// Checks the command, returning a message for every problem.
+  pub fn validate(&self) -> Vec<String> {
+    let block_len = self.block_len();
+    let byte_len = self.byte_len();
+    let byte_pos = self.byte_pos();
+
+    let mut errors = Vec::new();
+
+    if let Some(factor) = self.factor {
+      if factor == 0.0 || !factor.is_finite() {
+        errors.push(format!("factor must be a finite, non-zero number, found {}", factor));
+      }
+    }
+
+    if byte_pos + byte_len > block_len {
+      errors.push(format!("byte_pos ({}) + byte_len ({}) exceeds block_len ({})", byte_pos, byte_len, block_len));
+    }
+
+    if let Some(bit_pos) = self.bit_pos {
+      let bit_len = self.bit_len.unwrap_or(1);
+
+      if bit_pos >= block_len * 8 {
+        errors.push(format!("bit_pos ({}) is outside of the block with {} bits", bit_pos, block_len * 8));
+      } else if bit_len == 0 || bit_pos % 8 + bit_len > 8 {
+        errors.push(format!("bit_pos ({}) + bit_len ({}) crosses a byte boundary", bit_pos, bit_len));
+      }
+
+      if byte_pos != 0 || byte_len != 1 {
+        errors.push("bit_pos requires byte_pos 0 and byte_len 1".to_string());
+      }
+    } else if self.bit_len.is_some() {
+      errors.push("bit_len requires bit_pos".to_string());
+    }
+
+    match (&self.unit, &self.fields) {
+      (Unit::Array, Some(RawFields::List(_))) | (Unit::Map, Some(RawFields::Map(_))) => {
+        let fields = self.field_commands();
+
+        if fields.is_empty() {
+          errors.push(format!("unit {} requires at least one field", self.unit));
+        }
+
+        for (name, field) in &fields {
+          if matches!(field.unit, Unit::Array | Unit::Map) {
+            errors.push(format!("field `{}` cannot have unit {}", name, field.unit));
+            continue
+          }
+
+          for message in field.validate() {
+            errors.push(format!("field `{}`: {}", name, message));
+          }
+        }
+
+        if self.mapping.is_some() || self.bit_pos.is_some() || self.byte_len.is_some() || self.byte_pos.is_some() || self.factor.is_some() {
+          errors.push("mapping, bit_pos, byte_len, byte_pos and factor are not supported for composite values, use fields instead".to_string());
+        }
+      },
+      (Unit::Array, _) => errors.push("unit array requires a list of fields".to_string()),
+      (Unit::Map, _) => errors.push("unit map requires a map of fields".to_string()),
+      (_, Some(_)) => errors.push(format!("fields are not supported for unit {}", self.unit)),
+      (_, None) => {},
+    }
+
+    let numeric = self.mapping.is_none() && !matches!(self.unit, Unit::SysTime | Unit::CycleTime | Unit::Array | Unit::Map);
+
+    if self.get_expr.is_some() || self.set_expr.is_some() {
+      if !numeric || self.factor.is_some() {
+        errors.push("get_expr and set_expr require a numeric unit without factor or mapping".to_string());
+      }
+
+      if let Err(err) = self.set_expr() {
+        errors.push(err);
+      }
+    }
+
+    if !numeric && (self.min.is_some() || self.max.is_some() || self.step.is_some()) {
+      errors.push("min, max and step are only supported for numeric values".to_string());
+    }
+
+    if let (Some(min), Some(max)) = (self.min, self.max) {
+      if min > max {
+        errors.push(format!("min ({}) is greater than max ({})", min, max));
+      }
+    }
+
+    if let Some(step) = self.step {
+      if step <= 0.0 || !step.is_finite() {
+        errors.push(format!("step must be a finite, positive number, found {}", step));
+      }
+    }
+
+    if let Some(mapping) = &self.mapping {
+      if byte_len != 1 && byte_len != 2 {
+        errors.push(format!("mappings are only supported for 1 or 2 bytes, found byte_len {}", byte_len));
+      } else {
+        let mut keys = mapping.keys().filter(|key| key.len() != byte_len).collect::<Vec<_>>();
+        keys.sort();
+
+        for key in keys {
+          errors.push(format!("mapping key {:#04X?} has {} byte(s), expected {}", key, key.len(), byte_len));
+        }
+      }
+    } else if self.bit_pos.is_none() && byte_len != self.unit.size() {
+      errors.push(format!("byte_len ({}) does not match the size of unit {} ({})", byte_len, self.unit, self.unit.size()));
+    }
+
+    errors
+  }
+}
+
+/// The memory actually containing a value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueRange {
+  /// The addresses of the bytes.
+  pub bytes: Range<usize>,
+  /// The absolute bit addresses, i.e. `addr * 8 + bit`, for bit fields.
+  pub bits: Option<Range<usize>>,
+}
+
+impl ValueRange {
+  /// Returns whether two values share any memory. Bit fields in the same byte only overlap if they share a bit.
+  pub fn overlaps(&self, other: &ValueRange) -> bool {
+    let intersect = |a: &Range<usize>, b: &Range<usize>| a.start < b.end && b.start < a.end;
+
+    match (&self.bits, &other.bits) {
+      (Some(bits), Some(other_bits)) => intersect(bits, other_bits),
+      _ => intersect(&self.bytes, &other.bytes),
+    }
+  }
+}
+
+/// Returns the memory actually containing a value.
+pub fn value_range(addr: u16, byte_pos: usize, byte_len: usize, bit_pos: Option<usize>, bit_len: Option<usize>) -> ValueRange {
+  let addr = usize::from(addr);
+
+  match bit_pos {
+    Some(bit_pos) => {
+      let start = addr * 8 + bit_pos;
+
+      ValueRange {
+        bytes: (addr + bit_pos / 8)..(addr + bit_pos / 8 + 1),
+        bits: Some(start..(start + bit_len.unwrap_or(1))),
+      }
+    },
+    None => ValueRange { bytes: (addr + byte_pos)..(addr + byte_pos + byte_len), bits: None },
+  }
+}
+
+/// Checks that the values of different commands do not overlap, returning
+/// the name of the offending command and a message for every overlap.
+///
+/// Composite values intentionally cover the values of other commands and should not be passed.
+pub fn overlaps<'a>(ranges: &[(&'a str, ValueRange)]) -> Vec<(&'a str, String)> {
+  let mut errors = Vec::new();
+
+  for (i, (name, range)) in ranges.iter().enumerate() {
+    for (other, other_range) in &ranges[(i + 1)..] {
+      if range.overlaps(other_range) {
+        errors.push((*name, format!(
+          "value at 0x{:04X}..0x{:04X} overlaps with command `{}` at 0x{:04X}..0x{:04X}",
+          range.bytes.start, range.bytes.end, other, other_range.bytes.start, other_range.bytes.end,
+        )));
+      }
+    }
+  }
+
+  errors
+}
@@ -1,9 +1,604 @@
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::Write;
 use std::io::{self, Read, BufReader};
 use std::fs::File;
 use std::str::FromStr;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 
 use serde_derive::*;
-use serde_yaml;
-use yaml_merge_keys;
+
+use crate::{command, Command, Device, ProtocolKind, Unit};
+
+#[derive(Debug, Deserialize)]
+struct DeviceInfo {
+  protocol: ProtocolKind,
+  id: Option<Vec<u8>>,
+}
+
+/// A device definition loaded at runtime.
+///
+/// The YAML schema is the same as for the files in the `config` directory,
+/// including merge keys (`<<:`) and defaults, so a file can be changed
+/// without recompiling the crate.
+///
+/// # Examples
+///
+/// ```no_run
+/// use vcontrol::{Configuration, Optolink, VControl};
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let config = Configuration::open("config/V200KW2_6.yml")?;
+/// let mut vcontrol = VControl::connect_with(Optolink::open("/dev/ttyUSB0")?, config)?;
+/// println!("{:?}", vcontrol.get("outside_temp_actual")?);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "RawConfiguration")]
+pub struct Configuration {
+  device: DeviceInfo,
+  commands: HashMap<String, Command>,
+}
+
+#[derive(Deserialize)]
+struct RawConfiguration {
+  device: DeviceInfo,
+  commands: HashMap<String, Command>,
+}
+
+impl TryFrom<RawConfiguration> for Configuration {
+  type Error = String;
+
+  fn try_from(raw: RawConfiguration) -> Result<Configuration, Self::Error> {
+    let errors = overlaps(&raw.commands).into_iter()
+      .map(|(name, message)| format!("command `{}`: {}", name, message))
+      .collect::<Vec<_>>();
+
+    if !errors.is_empty() {
+      return Err(errors.join(", "))
+    }
+
+    Ok(Configuration { device: raw.device, commands: raw.commands })
+  }
+}
+
+/// Returns the name of the offending command and a message for every pair of commands with overlapping values.
+pub(crate) fn overlaps(commands: &HashMap<String, Command>) -> Vec<(&str, String)> {
+  let mut names = commands.keys().map(|name| name.as_str()).collect::<Vec<_>>();
+  names.sort_unstable();
+
+  // Composite values intentionally cover the values of other commands.
+  let ranges = names.into_iter()
+    .filter(|name| commands[*name].fields.is_none())
+    .map(|name| (name, commands[name].value_range()))
+    .collect::<Vec<_>>();
+
+  command::overlaps(&ranges)
+}
+
+impl Configuration {
+  #[cfg(feature = "vcontrold")]
+  pub(crate) fn new(protocol: ProtocolKind, id: Option<Vec<u8>>, commands: HashMap<String, Command>) -> Configuration {
+    Configuration { device: DeviceInfo { protocol, id }, commands }
+  }
+
+  /// Loads a configuration from the YAML file at `path`.
+  pub fn open(path: impl AsRef<Path>) -> Result<Configuration, io::Error> {
+    log::trace!("Configuration::open(…)");
+
+    let mut content = String::new();
+    BufReader::new(File::open(path)?).read_to_string(&mut content)?;
+
+    content.parse()
+  }
+
+  /// Returns the identification bytes of the device, if specified.
+  pub fn id(&self) -> Option<&[u8]> {
+    self.device.id.as_deref()
+  }
+
+  /// Converts the configuration into YAML which can be loaded again using [`Configuration::open`].
+  ///
+  /// Fields with default values are omitted.
+  pub fn to_yaml(&self) -> String {
+    fn bytes(bytes: &[u8]) -> String {
+      format!("[{}]", bytes.iter().map(|byte| format!("0x{:02X}", byte)).collect::<Vec<_>>().join(", "))
+    }
+
+    /// Writes the position of the value within the block.
+    fn layout(yaml: &mut String, command: &Command, indent: &str) {
+      if command.byte_len != command.unit.size() {
+        writeln!(yaml, "{}byte_len: {}", indent, command.byte_len).unwrap();
+      }
+      if command.byte_pos != 0 {
+        writeln!(yaml, "{}byte_pos: {}", indent, command.byte_pos).unwrap();
+      }
+      if let Some(bit_pos) = command.bit_pos {
+        writeln!(yaml, "{}bit_pos: {}", indent, bit_pos).unwrap();
+      }
+      if let Some(bit_len) = command.bit_len {
+        writeln!(yaml, "{}bit_len: {}", indent, bit_len).unwrap();
+      }
+      if command.factor != 1.0 {
+        writeln!(yaml, "{}factor: {}", indent, command.factor).unwrap();
+      }
+      if let Some(get_expr) = command.get_expr() {
+        writeln!(yaml, "{}get_expr: '{}'", indent, get_expr.replace('\'', "''")).unwrap();
+      }
+      if let Some(set_expr) = command.set_expr() {
+        writeln!(yaml, "{}set_expr: '{}'", indent, set_expr.replace('\'', "''")).unwrap();
+      }
+    }
+
+    fn unit_symbol(yaml: &mut String, command: &Command, indent: &str) {
+      if let Some(unit_symbol) = command.unit_symbol() {
+        writeln!(yaml, "{}unit_symbol: '{}'", indent, unit_symbol.replace('\'', "''")).unwrap();
+      }
+    }
+
+    fn mapping(yaml: &mut String, command: &Command, indent: &str) {
+      if let Some(mapping) = &command.mapping {
+        writeln!(yaml, "{}mapping:", indent).unwrap();
+
+        let mut keys = mapping.keys();
+        keys.sort();
+
+        for key in keys {
+          let text = mapping.get(&key).unwrap_or_default();
+          writeln!(yaml, "{}  {}: '{}'", indent, bytes(&key), text.replace('\'', "''")).unwrap();
+        }
+      }
+    }
+
+    let mut yaml = String::new();
+
+    writeln!(yaml, "device:").unwrap();
+    writeln!(yaml, "  protocol: {}", self.device.protocol).unwrap();
+    if let Some(id) = &self.device.id {
+      writeln!(yaml, "  id: {}", bytes(id)).unwrap();
+    }
+
+    writeln!(yaml).unwrap();
+    writeln!(yaml, "commands:").unwrap();
+
+    let mut names = self.commands.keys().collect::<Vec<_>>();
+    names.sort();
+
+    for name in names {
+      let command = &self.commands[name];
+
+      writeln!(yaml, "  {}:", name).unwrap();
+      writeln!(yaml, "    addr: 0x{:04X}", command.addr).unwrap();
+      writeln!(yaml, "    mode: {}", command.mode).unwrap();
+      writeln!(yaml, "    unit: {}", command.unit).unwrap();
+
+      if command.block_len != command.unit.size() {
+        writeln!(yaml, "    block_len: {}", command.block_len).unwrap();
+      }
+      layout(&mut yaml, command, "    ");
+      if let Some(description) = command.description() {
+        writeln!(yaml, "    description: '{}'", description.replace('\'', "''")).unwrap();
+      }
+      unit_symbol(&mut yaml, command, "    ");
+      if let Some(min) = command.min {
+        writeln!(yaml, "    min: {}", min).unwrap();
+      }
+      if let Some(max) = command.max {
+        writeln!(yaml, "    max: {}", max).unwrap();
+      }
+      if let Some(step) = command.step {
+        writeln!(yaml, "    step: {}", step).unwrap();
+      }
+      mapping(&mut yaml, command, "    ");
+
+      if let Some(fields) = &command.fields {
+        writeln!(yaml, "    fields:").unwrap();
+
+        for (name, field) in fields.iter() {
+          match command.unit {
+            Unit::Array => writeln!(yaml, "      -").unwrap(),
+            _ => writeln!(yaml, "      {}:", name).unwrap(),
+          }
+
+          writeln!(yaml, "        unit: {}", field.unit).unwrap();
+          layout(&mut yaml, field, "        ");
+          unit_symbol(&mut yaml, field, "        ");
+          mapping(&mut yaml, field, "        ");
+        }
+      }
+    }
+
+    yaml
+  }
+}
+
+impl FromStr for Configuration {
+  type Err = io::Error;
+
+  fn from_str(s: &str) -> Result<Configuration, Self::Err> {
+    let invalid_data = |err| io::Error::new(io::ErrorKind::InvalidData, err);
+
+    let value = serde_yaml::from_str::<serde_yaml::Value>(s).map_err(invalid_data)?;
+    let value = yaml_merge_keys::merge_keys_serde(value)
+                  .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+
+    serde_yaml::from_value(value).map_err(invalid_data)
+  }
+}
+
+impl Device for Configuration {
+  fn protocol(&self) -> ProtocolKind {
+    self.device.protocol
+  }
+
+  fn command(&self, name: &str) -> Option<&Command> {
+    self.commands.get(name)
+  }
+
+  fn commands(&self) -> Vec<&str> {
+    self.commands.keys().map(|name| name.as_str()).collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::{Error, Optolink, Simulator, VControl, Value};
+  #[cfg(feature = "device-V200KW2_6")]
+  use crate::device::V200KW2;
+
+  const V200KW2_6: &str = include_str!("../config/V200KW2_6.yml");
+
+  #[cfg(feature = "device-V200KW2_6")]
+  #[test]
+  fn same_as_generated() {
+    let config = V200KW2_6.parse::<Configuration>().unwrap();
+
+    assert_eq!(config.protocol(), V200KW2.protocol());
+    assert_eq!(config.id(), Some(&[0x20, 0x98][..]));
+
+    let mut commands = config.commands();
+    commands.sort();
+    let mut generated = V200KW2.commands();
+    generated.sort();
+    assert_eq!(commands, generated);
+
+    for name in commands {
+      let command = config.command(name).unwrap();
+      let generated = V200KW2.command(name).unwrap();
+
+      assert_eq!(command.addr, generated.addr, "{}", name);
+      assert_eq!(command.block_len, generated.block_len, "{}", name);
+      assert_eq!(command.byte_len, generated.byte_len, "{}", name);
+      assert_eq!(command.byte_pos, generated.byte_pos, "{}", name);
+      assert_eq!(command.bit_pos, generated.bit_pos, "{}", name);
+      assert_eq!(command.factor, generated.factor, "{}", name);
+      assert_eq!((command.get_expr(), command.set_expr()), (generated.get_expr(), generated.set_expr()), "{}", name);
+      assert_eq!(command.description(), generated.description(), "{}", name);
+      assert_eq!(command.unit_symbol(), generated.unit_symbol(), "{}", name);
+      assert_eq!((command.min(), command.max(), command.step()), (generated.min(), generated.max(), generated.step()), "{}", name);
+
+      let keys = |command: &Command| command.mapping.as_ref().map(|mapping| {
+        let mut keys = mapping.keys();
+        keys.sort();
+        keys
+      });
+      assert_eq!(keys(command), keys(generated), "{}", name);
+
+      let fields = |command: &Command| command.fields().map(|fields| {
+        fields.into_iter().map(|(name, field)| (name.to_owned(), field.block_len, field.byte_pos, field.bit_pos)).collect::<Vec<_>>()
+      });
+      assert_eq!(fields(command), fields(generated), "{}", name);
+    }
+  }
+
+  #[test]
+  fn merge_keys_and_defaults() {
+    let config = "
+      device:
+        protocol: P300
+      temp: &temp
+        unit: i16
+        factor: 10
+      commands:
+        room_temp:
+          <<: *temp
+          addr: 0x2306
+          mode: read_write
+        error:
+          addr: 0x7507
+          mode: read
+          unit: u8
+          block_len: 9
+          mapping:
+            [0x00]: OK
+            [0x10]: FAIL
+    ".parse::<Configuration>().unwrap();
+
+    assert_eq!(config.protocol(), ProtocolKind::P300);
+    assert_eq!(config.id(), None);
+
+    let room_temp = config.command("room_temp").unwrap();
+    assert_eq!(room_temp.block_len, 2);
+    assert_eq!(room_temp.byte_len, 2);
+    assert_eq!(room_temp.byte_pos, 0);
+    assert_eq!(room_temp.factor, 10.0);
+
+    let error = config.command("error").unwrap();
+    assert_eq!(error.block_len, 9);
+    assert_eq!(error.byte_len, 1);
+    assert_eq!(error.mapping.as_ref().unwrap().get(&[0x10]), Some("FAIL"));
+  }
+
+  #[test]
+  fn invalid() {
+    assert!("device:\n  protocol: Kw3\ncommands: {}".parse::<Configuration>().is_err());
+
+    let config = "
+      device:
+        protocol: Kw2
+      commands:
+        temp:
+          addr: 0x0800
+          mode: read
+          unit: i16
+          block_len: 2
+          byte_pos: 1
+    ".parse::<Configuration>();
+    assert_eq!(config.unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+    let config = "
+      device:
+        protocol: Kw2
+      commands:
+        flags:
+          addr: 0x3535
+          mode: read
+          unit: array
+          fields:
+            flag:
+              unit: u8
+    ".parse::<Configuration>();
+    assert!(config.unwrap_err().to_string().contains("requires a list of fields"));
+
+    let invalid = [
+      ("unit: u8\n          bit_pos: 0\n          bit_len: 0", "crosses a byte boundary"),
+      ("unit: u8\n          bit_pos: 0\n          bit_len: 9", "crosses a byte boundary"),
+      ("unit: u8\n          bit_pos: 6\n          bit_len: 3", "crosses a byte boundary"),
+      ("unit: u8\n          bit_len: 2", "bit_len requires bit_pos"),
+      ("unit: u16\n          bit_pos: 3", "bit_pos requires byte_pos 0 and byte_len 1"),
+      ("unit: u8\n          block_len: 2\n          byte_pos: 1\n          bit_pos: 3", "bit_pos requires byte_pos 0 and byte_len 1"),
+      ("unit: u8\n          factor: 0", "factor must be a finite, non-zero number"),
+      ("unit: u16\n          byte_len: 1", "does not match the size of unit u16"),
+      ("unit: u8\n          mapping:\n            [0x00, 0x01]: ON", "has 2 byte(s), expected 1"),
+      ("unit: u8\n          byte_len: 3\n          block_len: 3\n          mapping:\n            [0x00, 0x01, 0x02]: ON", "only supported for 1 or 2 bytes"),
+      ("unit: u8\n          min: 10\n          max: 5", "min (10) is greater than max (5)"),
+    ];
+
+    for (command, message) in &invalid {
+      let config = format!("
+      device:
+        protocol: Kw2
+      commands:
+        test:
+          addr: 0x2323
+          mode: read_write
+          {}
+      ", command).parse::<Configuration>();
+
+      let err = config.unwrap_err().to_string();
+      assert!(err.contains(message), "{:?}: {}", command, err);
+    }
+
+    let config = "
+      device:
+        protocol: Kw2
+      commands:
+        temp:
+          addr: 0x0800
+          mode: read
+          unit: i16
+        temp_low:
+          addr: 0x0801
+          mode: read
+          unit: u8
+    ".parse::<Configuration>();
+    let err = config.unwrap_err().to_string();
+    assert!(err.contains("command `temp`: value at 0x0800..0x0802 overlaps with command `temp_low`"), "{}", err);
+
+    let overlapping = [
+      ("unit: u8", "unit: u8"),
+      ("unit: u8", "unit: u8\n          bit_pos: 3"),
+      ("unit: u8\n          bit_pos: 2\n          bit_len: 2", "unit: u8\n          bit_pos: 3"),
+    ];
+
+    for (a, b) in &overlapping {
+      let config = format!("
+      device:
+        protocol: Kw2
+      commands:
+        a:
+          addr: 0x2323
+          mode: read
+          {}
+        b:
+          addr: 0x2323
+          mode: read
+          {}
+      ", a, b).parse::<Configuration>();
+
+      let err = config.unwrap_err().to_string();
+      assert!(err.contains("command `a`: value at 0x2323..0x2324 overlaps with command `b`"), "{:?}: {}", (a, b), err);
+    }
+
+    let config = "
+      device:
+        protocol: Kw2
+      commands:
+        flag:
+          addr: 0x2323
+          mode: read
+          unit: u8
+          bit_pos: 3
+        flags:
+          addr: 0x2323
+          mode: read
+          unit: u8
+          bit_pos: 4
+          bit_len: 4
+    ".parse::<Configuration>();
+    assert!(config.is_ok(), "{:?}", config);
+  }
+
+  #[test]
+  fn composite() {
+    let config = "
+      device:
+        protocol: Kw2
+      commands:
+        error_01_entry:
+          addr: 0x7507
+          mode: read_write
+          unit: map
+          fields:
+            code:
+              unit: u8
+              mapping:
+                [0x00]: OK
+                [0x10]: FAIL
+            time:
+              unit: systime
+              byte_pos: 1
+        flags:
+          addr: 0x3535
+          mode: read_write
+          unit: array
+          fields:
+            - unit: u8
+              bit_pos: 0
+            - unit: u8
+              bit_pos: 6
+              bit_len: 2
+    ".parse::<Configuration>().unwrap();
+
+    let entry = config.command("error_01_entry").unwrap();
+    assert_eq!(entry.block_len, 9);
+    assert_eq!(entry.fields().unwrap().iter().map(|(name, _)| *name).collect::<Vec<_>>(), ["code", "time"]);
+
+    let reparsed = config.to_yaml().parse::<Configuration>().unwrap();
+    assert_eq!(reparsed.to_yaml(), config.to_yaml());
+
+    let mut simulator = Simulator::new(&config);
+    simulator.write_memory(0x7507, &[0x10, 0x20, 0x18, 0x12, 0x23, 0x07, 0x17, 0x49, 0x31]);
+    simulator.write_memory(0x3535, &[0b1000_0010]);
+
+    let mut vcontrol = VControl::connect_with(Optolink::simulate(simulator), config).unwrap();
+
+    let entry = vcontrol.get("error_01_entry").unwrap();
+    assert_eq!(entry.to_string(), r#"{"code": "FAIL", "time": "2018-12-23T17:49:31"}"#);
+    assert_eq!(vcontrol.get("flags").unwrap().to_string(), "[1, 2]");
+
+    vcontrol.set("flags", &"[0, 3]".parse().unwrap()).unwrap();
+    assert_eq!(vcontrol.get("flags").unwrap().to_string(), "[0, 3]");
+
+    vcontrol.set("error_01_entry", &r#"{"code": "OK", "time": "2019-01-02T03:04:05"}"#.parse().unwrap()).unwrap();
+    assert_eq!(vcontrol.get("error_01_entry").unwrap().to_string(), r#"{"code": "OK", "time": "2019-01-02T03:04:05"}"#);
+
+    assert!(vcontrol.set("flags", &"[0]".parse().unwrap()).is_err());
+
+    match vcontrol.set("flags", &"[1, 4]".parse().unwrap()) {
+      Err(Error::InvalidArgument(message)) => assert_eq!(message, "value 4 does not fit into 2 bit(s)"),
+      result => panic!("expected invalid argument, found {:?}", result),
+    }
+    assert!(vcontrol.set("flags", &"[2, 0]".parse().unwrap()).is_err());
+    assert_eq!(vcontrol.get("flags").unwrap().to_string(), "[0, 3]");
+    assert!(vcontrol.set("error_01_entry", &r#"{"code": "OK"}"#.parse().unwrap()).is_err());
+  }
+
+  #[test]
+  fn expression() {
+    let config = "
+      device:
+        protocol: Kw2
+      commands:
+        outside_temp:
+          addr: 0x5525
+          mode: read
+          unit: u16
+          get_expr: '($b1 << 8 | $b0) / 2.0 - 20'
+        room_temp_offset:
+          addr: 0x2306
+          mode: read_write
+          unit: u8
+          get_expr: $v - 64
+    ".parse::<Configuration>().unwrap();
+
+    let offset = config.command("room_temp_offset").unwrap();
+    assert_eq!((offset.get_expr(), offset.set_expr()), (Some("$v - 64"), Some("($v + 64)")));
+
+    let reparsed = config.to_yaml().parse::<Configuration>().unwrap();
+    assert_eq!(reparsed.to_yaml(), config.to_yaml());
+
+    let mut simulator = Simulator::new(&config);
+    simulator.write_memory(0x5525, &85u16.to_le_bytes());
+    simulator.write_memory(0x2306, &[61]);
+
+    let mut vcontrol = VControl::connect_with(Optolink::simulate(simulator), config).unwrap();
+
+    assert_eq!(vcontrol.get("outside_temp").unwrap().to_string(), "22.5");
+    assert_eq!(vcontrol.get("room_temp_offset").unwrap().to_string(), "-3");
+
+    vcontrol.set("room_temp_offset", &Value::Number(5.0)).unwrap();
+    assert_eq!(vcontrol.get("room_temp_offset").unwrap().to_string(), "5");
+    assert!(vcontrol.set("room_temp_offset", &Value::Number(200.0)).is_err());
+
+    let invalid = |command: &str| format!("device:\n  protocol: Kw2\ncommands:\n  temp:\n{}", command).parse::<Configuration>().unwrap_err().to_string();
+
+    assert!(invalid("    addr: 0x0800\n    mode: read\n    unit: i16\n    get_expr: $v +").contains("unexpected end of input"));
+    assert!(invalid("    addr: 0x0800\n    mode: read\n    unit: i16\n    factor: 10\n    get_expr: $v").contains("without factor"));
+    assert_eq!(
+      invalid("    addr: 0x0800\n    mode: read_write\n    unit: i16\n    get_expr: i16($v) / 10"),
+      "cannot derive set_expr from get_expr `i16($v) / 10`: function i16 is not invertible",
+    );
+    assert!(invalid("    addr: 0x0800\n    mode: read\n    unit: i16\n    get_expr: i16($v) / 10\n    factor: 2").contains("without factor"));
+  }
+
+  #[test]
+  fn to_yaml() {
+    let config = V200KW2_6.parse::<Configuration>().unwrap();
+    let yaml = config.to_yaml();
+
+    let reparsed = yaml.parse::<Configuration>().unwrap();
+    assert_eq!(reparsed.to_yaml(), yaml);
+    assert_eq!(reparsed.id(), config.id());
+
+    let mut commands = reparsed.commands();
+    commands.sort();
+    let mut expected = config.commands();
+    expected.sort();
+    assert_eq!(commands, expected);
+  }
+
+  #[test]
+  fn vcontrol() {
+    let config = V200KW2_6.parse::<Configuration>().unwrap();
+
+    let mut simulator = Simulator::new(&config);
+    simulator.write_memory(0x0802, &215i16.to_le_bytes());
+
+    let mut vcontrol = VControl::connect_with(Optolink::simulate(simulator), config).unwrap();
+
+    match vcontrol.get("boiler_temp_actual").unwrap() {
+      Value::Decimal(decimal) => assert_eq!(decimal.to_string(), "21.5"),
+      value => panic!("expected number, found {:?}", value),
+    }
+
+    vcontrol.set("operatingmode", &Value::String("RED".into())).unwrap();
+    match vcontrol.get("operatingmode").unwrap() {
+      Value::String(s) => assert_eq!(s, "RED"),
+      value => panic!("expected string, found {:?}", value),
+    }
+  }
+}
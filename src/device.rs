@@ -1,40 +1,164 @@
-use phf;
+use std::fmt;
 
-use crate::{Error, Command, Optolink, Protocol, Value};
+use crate::{Error, Command, DynVControl, Optolink, ProtocolKind, VControl};
 
 #[allow(clippy::unreadable_literal)]
+#[allow(unused_imports)]
 mod codegen {
   use super::*;
-  use crate::types::Bytes;
-  use crate::protocol::*;
+  use crate::{AccessMode, Unit, command::{Expr, Fields, Mapping}, types::Bytes};
 
   include!(concat!(env!("OUT_DIR"), "/codegen.rs"));
 }
 
 pub use self::codegen::*;
 
+/// A device definition, i.e. its protocol and supported commands.
+///
+/// This is implemented by the device types generated from the `config`
+/// directory and by runtime-loaded [`Configuration`](crate::Configuration)s.
 pub trait Device {
-  type Protocol: Protocol;
+  /// Returns the protocol used to communicate with the device.
+  fn protocol(&self) -> ProtocolKind;
 
-  fn map() -> &'static phf::Map<&'static str, Command>;
+  /// Returns the command with the given name.
+  fn command(&self, name: &str) -> Option<&Command>;
 
-  fn commands() -> Vec<&'static str> {
-    Self::map().keys().cloned().collect::<Vec<_>>()
+  /// Returns the names of all supported commands.
+  fn commands(&self) -> Vec<&str>;
+
+  /// Returns an iterator over all supported commands, sorted by name.
+  fn iter(&self) -> Box<dyn Iterator<Item = (&str, &Command)> + '_> {
+    let mut names = self.commands();
+    names.sort_unstable();
+
+    Box::new(names.into_iter().filter_map(move |name| self.command(name).map(|command| (name, command))))
+  }
+}
+
+impl<D: Device + ?Sized> Device for &D {
+  fn protocol(&self) -> ProtocolKind {
+    (**self).protocol()
+  }
+
+  fn command(&self, name: &str) -> Option<&Command> {
+    (**self).command(name)
+  }
+
+  fn commands(&self) -> Vec<&str> {
+    (**self).commands()
+  }
+}
+
+impl fmt::Debug for dyn Device + Sync {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("Device")
+      .field("protocol", &self.protocol())
+      .field("commands", &self.commands().len())
+      .finish()
+  }
+}
+
+/// A device type compiled into the crate, see [`DEVICES`].
+///
+/// Which device types are compiled in is selected using the `device-*` cargo features.
+#[derive(Clone, Copy)]
+pub struct DeviceInfo {
+  name: &'static str,
+  protocol: ProtocolKind,
+  id: Option<&'static [u8]>,
+  device: &'static (dyn Device + Sync),
+  connect: fn(Optolink) -> Result<Box<dyn DynVControl>, Error>,
+}
+
+impl fmt::Debug for DeviceInfo {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("DeviceInfo")
+      .field("name", &self.name)
+      .field("protocol", &self.protocol)
+      .field("id", &self.id)
+      .finish()
   }
+}
 
-  fn command(name: &str) -> Option<&Command> {
-    Self::map().get(name)
+impl DeviceInfo {
+  /// Returns the name of the device type, i.e. the name of its configuration file.
+  pub fn name(&self) -> &'static str {
+    self.name
   }
 
-  fn get(o: &mut Optolink, cmd: &Command) -> Result<Value, Error> {
-    log::trace!("Device::get(…)");
+  /// Returns the protocol used by the device.
+  pub fn protocol(&self) -> ProtocolKind {
+    self.protocol
+  }
 
-    cmd.get::<Self::Protocol>(o)
+  /// Returns the identification bytes read from `system_type`, if known.
+  pub fn id(&self) -> Option<&'static [u8]> {
+    self.id
   }
 
-  fn set(o: &mut Optolink, cmd: &Command, input: &Value) -> Result<(), Error> {
-    log::trace!("Device::set(…)");
+  /// Returns the device definition, e.g. to connect asynchronously.
+  pub fn device(&self) -> &'static (dyn Device + Sync) {
+    self.device
+  }
+
+  /// Returns the device type with the given name.
+  pub fn find(name: &str) -> Option<&'static DeviceInfo> {
+    DEVICES.iter().find(|device| device.name == name)
+  }
+
+  /// Connects to a device of this type.
+  pub fn connect(&self, device: Optolink) -> Result<Box<dyn DynVControl>, Error> {
+    (self.connect)(device)
+  }
+}
+
+#[allow(dead_code)] // Unused if no device types are compiled in.
+fn connect_device<D: Device + Default + fmt::Debug + Send + 'static>(device: Optolink) -> Result<Box<dyn DynVControl>, Error> {
+  Ok(Box::new(VControl::<D>::connect(device)?))
+}
+
+/// Returns the device type with the identification bytes `id`.
+pub(crate) fn identify(id: &[u8]) -> Result<&'static DeviceInfo, Error> {
+  DEVICES.iter().find(|info| info.id == Some(id)).ok_or_else(|| Error::UnknownDevice(id.to_vec()))
+}
+
+#[cfg(all(test, feature = "device-V200KW2_6"))]
+mod tests {
+  use super::*;
+
+  use crate::Unit;
+
+  #[test]
+  fn iter() {
+    let (name, command) = V200KW2.iter().find(|(name, _)| *name == "hotwater_temp_desired").unwrap();
+    assert_eq!(name, "hotwater_temp_desired");
+    assert_eq!(command.addr(), 0x6300);
+    assert!(command.mode().is_read() && command.mode().is_write());
+    assert_eq!(command.unit(), &Unit::U8);
+    assert_eq!((command.block_len(), command.byte_len(), command.byte_pos()), (1, 1, 0));
+    assert_eq!(command.bit_pos(), None);
+    assert_eq!(command.factor(), 1.0);
+    assert_eq!(command.mapping(), None);
+
+    let (_, command) = V200KW2.iter().find(|(name, _)| *name == "vacationmode_state").unwrap();
+    assert_eq!((command.bit_pos(), command.bit_len()), (Some(7), Some(1)));
+
+    let (_, command) = V200KW2.iter().find(|(name, _)| *name == "operatingmode").unwrap();
+    let variants = command.mapping().unwrap();
+    assert_eq!(variants[0], (vec![0x00], "NWW"));
+
+    assert_eq!(V200KW2.iter().count(), V200KW2.commands().len());
+  }
+
+  #[test]
+  fn registry() {
+    let info = DeviceInfo::find("V200KW2_6").unwrap();
+    assert_eq!(info.protocol(), V200KW2.protocol());
+    assert_eq!(info.id(), Some(&[0x20, 0x98][..]));
+
+    assert_eq!(info.device().commands().len(), V200KW2.commands().len());
 
-    cmd.set::<Self::Protocol>(o, input)
+    assert!(DeviceInfo::find("V200KW2_5").is_none());
   }
 }
@@ -7,6 +7,7 @@ pub enum Error {
   UnsupportedMode(String),
   InvalidArgument(String),
   UnknownEnumVariant(String),
+  UnknownDevice(Vec<u8>),
   Io(io::Error)
 }
 
@@ -23,7 +24,17 @@ impl fmt::Display for Error {
       Error::UnsupportedMode(description) => description.fmt(f),
       Error::InvalidArgument(description) => description.fmt(f),
       Error::UnknownEnumVariant(description) => description.fmt(f),
+      Error::UnknownDevice(id) => write!(f, "no device found for ID [{}]", id.iter().map(|byte| format!("0x{:02X}", byte)).collect::<Vec<String>>().join(", ")),
       Error::Io(err) => err.fmt(f),
     }
   }
 }
+
+impl std::error::Error for Error {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Error::Io(err) => Some(err),
+      _ => None,
+    }
+  }
+}
@@ -0,0 +1,123 @@
+use serde_derive::*;
+
+use crate::{Command, Device, Error, Value, types::SysTime};
+
+/// An entry of the device's error history.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ErrorEntry {
+  code: u8,
+  description: Option<String>,
+  time: Option<SysTime>,
+}
+
+impl ErrorEntry {
+  /// Returns the raw error code.
+  pub fn code(&self) -> u8 {
+    self.code
+  }
+
+  /// Returns the description of the error code, if it is known.
+  pub fn description(&self) -> Option<&str> {
+    self.description.as_deref()
+  }
+
+  /// Returns the time at which the error occurred, if it is valid.
+  pub fn time(&self) -> Option<&SysTime> {
+    self.time.as_ref()
+  }
+}
+
+/// The error history of a device, newest entry first.
+///
+/// This is read from the `error_01` to `error_NN` commands and their
+/// corresponding `error_NN_time` commands, which share the same block.
+/// Empty slots are omitted. It is serialized as a list of objects
+/// with the keys `code`, `description` and `time`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct ErrorHistory {
+  entries: Vec<ErrorEntry>,
+}
+
+impl ErrorHistory {
+  /// Returns all entries, newest entry first.
+  pub fn entries(&self) -> &[ErrorEntry] {
+    &self.entries
+  }
+
+  /// Returns whether there are no entries.
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  /// Returns the `error_NN` command and the corresponding `error_NN_time` command for every slot.
+  pub(crate) fn slots<D: Device + ?Sized>(device: &D) -> Result<Vec<(&Command, Option<&Command>)>, Error> {
+    let slots = (1..).map(|i| format!("error_{:02}", i))
+      .map_while(|name| device.command(&name).map(|state| (state, device.command(&format!("{}_time", name)))))
+      .collect::<Vec<_>>();
+
+    if slots.is_empty() {
+      return Err(Error::UnsupportedCommand("error_01".into()))
+    }
+
+    Ok(slots)
+  }
+
+  /// Adds the entry contained in a block, unless the slot is empty.
+  pub(crate) fn push(&mut self, state: &Command, time: Option<&Command>, block: Vec<u8>) -> Result<(), Error> {
+    if block.iter().all(|&byte| byte == 0x00) || block.iter().all(|&byte| byte == 0xff) {
+      return Ok(())
+    }
+
+    let code = block[state.byte_pos()];
+
+    let description = match state.decode(block.clone()) {
+      Ok(Value::String(description)) => Some(description),
+      Ok(_) | Err(Error::UnknownEnumVariant(_)) => None,
+      Err(err) => return Err(err),
+    };
+
+    let time = match time.map(|time| time.decode(block)).transpose()? {
+      Some(Value::SysTime(time)) if time.is_valid() => Some(time),
+      _ => None,
+    };
+
+    self.entries.push(ErrorEntry { code, description, time });
+
+    Ok(())
+  }
+}
+
+#[cfg(all(test, feature = "device-V200KW2_6"))]
+mod tests {
+  use super::*;
+
+  use crate::device::V200KW2;
+
+  #[test]
+  fn slots() {
+    let slots = ErrorHistory::slots(&V200KW2).unwrap();
+    assert_eq!(slots.len(), 10);
+    assert!(slots.iter().all(|(state, time)| time.map(Command::addr) == Some(state.addr())));
+  }
+
+  #[test]
+  fn push() {
+    let slots = ErrorHistory::slots(&V200KW2).unwrap();
+    let (state, time) = slots[0];
+
+    let mut history = ErrorHistory::default();
+    history.push(state, time, vec![0x00; 9]).unwrap();
+    history.push(state, time, vec![0x10, 0x20, 0x18, 0x12, 0x23, 0x07, 0x17, 0x49, 0x31]).unwrap();
+    history.push(state, time, vec![0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]).unwrap();
+
+    let entries = history.entries();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].code(), 0x10);
+    assert_eq!(entries[0].description(), Some("Notbetrieb-Außentemp"));
+    assert_eq!(entries[0].time().map(ToString::to_string), Some("2018-12-23T17:49:31".into()));
+    assert_eq!(entries[1].code(), 0x11);
+    assert_eq!(entries[1].description(), None);
+    assert_eq!(entries[1].time(), None);
+  }
+}
@@ -0,0 +1,26 @@
+use super::{Op, ParseNode::{self, *}, Var};
+
+/// Formats an arithmetic expression using the syntax of vcontrold's `calc` element.
+///
+/// Only numbers, `$v`, `$bN`, `+`, `-`, `*`, `/` and unary `-` can be translated.
+pub fn calc(node: &ParseNode) -> Result<String, String> {
+  Ok(match node {
+    Number(n) => format!("{:?}", n),
+    Var(Var::Value) => String::from("V"),
+    Var(Var::Byte(i)) => format!("B{}", i),
+    Binary(op @ (Op::Add | Op::Sub | Op::Mul | Op::Div), lhs, rhs) => {
+      let op = match op {
+        Op::Add => '+',
+        Op::Sub => '-',
+        Op::Mul => '*',
+        _ => '/',
+      };
+
+      format!("({}{}{})", calc(lhs)?, op, calc(rhs)?)
+    },
+    Unary(Op::Sub, expr) => format!("(0-{})", calc(expr)?),
+    Binary(op, ..) | Unary(op, _) => return Err(format!("operator {:?} is not supported by vcontrold", op)),
+    Call(func, _) => return Err(format!("function {:?} is not supported by vcontrold", func)),
+    Cond(..) => return Err(String::from("conditionals are not supported by vcontrold")),
+  })
+}
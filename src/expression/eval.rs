@@ -1,40 +1,33 @@
-use super::{Number, ParseNode::{self, *}, Op::*, Var::*};
+use super::{Number, ParseNode::{self, *}, Var::*};
 
-pub fn eval(node: &ParseNode, value: i32, bytes: &[u8]) -> Result<Number, String> {
+pub fn eval(node: &ParseNode, value: Number, bytes: &[u8]) -> Result<Number, String> {
   match node {
-    Number(n) => Ok(n.clone()),
-    Var(Value) => Ok(Number::Int(value)),
+    Number(n) => Ok(*n),
+    Var(Value) => Ok(value),
     Var(Byte(i)) => if let Some(byte) = bytes.get(*i) {
-      Ok(Number::Int(*byte as i32))
+      Ok(Number::Int(i64::from(*byte)))
     } else {
-      return Err(format!("missing byte at index {}", i))
+      Err(format!("missing byte at index {}", i))
     },
     Binary(op, lhs, rhs) => {
       let lhs = eval(lhs, value, bytes)?;
       let rhs = eval(rhs, value, bytes)?;
 
-      Ok(match op {
-        Add => lhs + rhs,
-        Sub => lhs - rhs,
-        Mul => lhs * rhs,
-        Div => lhs / rhs,
-        Mod => lhs % rhs,
-        And => lhs & rhs,
-        Xor => lhs ^ rhs,
-        Or => lhs | rhs,
-        Shl => lhs << rhs,
-        Shr => lhs >> rhs,
-        _ => unreachable!(),
-      })
+      Number::binary(*op, lhs, rhs)
     },
-    Unary(op, expr) => {
-      let expr = eval(expr, value, bytes)?;
+    Unary(op, expr) => Number::unary(*op, eval(expr, value, bytes)?),
+    Call(func, args) => {
+      let args = args.iter().map(|arg| eval(arg, value, bytes)).collect::<Result<Vec<_>, _>>()?;
 
-      Ok(match op {
-        Sub => -expr,
-        Not => !expr,
-        _ => unreachable!(),
-      })
-    }
+      Number::call(*func, &args)
+    },
+    Cond(cond, then, otherwise) => {
+      // Only the chosen branch is evaluated, so the other one may e.g. divide by zero.
+      if f64::from(eval(cond, value, bytes)?) != 0.0 {
+        eval(then, value, bytes)
+      } else {
+        eval(otherwise, value, bytes)
+      }
+    },
   }
 }
@@ -0,0 +1,61 @@
+use super::{Number, Op, ParseNode::{self, *}, Var, eval};
+
+/// Returns how often `$v` occurs in an expression, failing if it uses bytes, which are not available when writing.
+fn occurrences(node: &ParseNode) -> Result<usize, String> {
+  Ok(match node {
+    Number(_) => 0,
+    Var(Var::Value) => 1,
+    Var(Var::Byte(i)) => return Err(format!("$b{} is not available when writing", i)),
+    Binary(_, lhs, rhs) => occurrences(lhs)? + occurrences(rhs)?,
+    Unary(_, expr) => occurrences(expr)?,
+    Call(_, args) => args.iter().map(occurrences).sum::<Result<usize, _>>()?,
+    Cond(cond, then, otherwise) => occurrences(cond)? + occurrences(then)? + occurrences(otherwise)?,
+  })
+}
+
+/// Derives the inverse of an expression which is affine in `$v`.
+///
+/// `$v` must occur exactly once and may only be combined with constants using `+`, `-`, `*`, `/` and unary `-`.
+pub fn invert(node: &ParseNode) -> Result<ParseNode, String> {
+  match occurrences(node)? {
+    0 => Err(String::from("$v does not occur")),
+    1 => invert_into(node, Var(Var::Value)),
+    _ => Err(String::from("$v occurs more than once")),
+  }
+}
+
+/// Applies the inverse of every operation on the path from `node` to `$v` to `target`, from the outside in.
+fn invert_into(node: &ParseNode, target: ParseNode) -> Result<ParseNode, String> {
+  match node {
+    Var(Var::Value) => Ok(target),
+    Unary(Op::Sub, expr) => invert_into(expr, Unary(Op::Sub, Box::new(target))),
+    Binary(op, lhs, rhs) => {
+      let var_on_left = occurrences(lhs)? > 0;
+      let (inner, constant) = if var_on_left { (lhs, rhs) } else { (rhs, lhs) };
+
+      let target = Box::new(target);
+      let constant = constant.clone();
+
+      let target = match (op, var_on_left) {
+        (Op::Add, _) => Binary(Op::Sub, target, constant),
+        (Op::Sub, true) => Binary(Op::Add, target, constant),
+        (Op::Sub, false) => Binary(Op::Sub, constant, target),
+        (Op::Mul, _) => {
+          if f64::from(eval(&constant, Number::Int(0), &[])?) == 0.0 {
+            return Err(format!("{:?} multiplies $v by zero", node))
+          }
+
+          Binary(Op::Div, target, constant)
+        },
+        (Op::Div, true) => Binary(Op::Mul, target, constant),
+        (op, _) => return Err(format!("operator {:?} is not invertible for $v in {:?}", op, node)),
+      };
+
+      invert_into(inner, target)
+    },
+    Unary(op, _) => Err(format!("operator {:?} is not invertible", op)),
+    Call(func, _) => Err(format!("function {:?} is not invertible", func)),
+    Cond(..) => Err(String::from("conditionals are not invertible")),
+    Number(_) | Var(Var::Byte(_)) => unreachable!("`$v` does not occur in {:?}", node),
+  }
+}
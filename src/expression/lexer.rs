@@ -1,3 +1,4 @@
+use std::convert::TryFrom;
 use std::fmt;
 use std::iter::Peekable;
 
@@ -16,6 +17,12 @@ pub enum Op {
   Xor,
   Shl,
   Shr,
+  Eq,
+  Ne,
+  Lt,
+  Le,
+  Gt,
+  Ge,
 }
 
 impl fmt::Debug for Op {
@@ -30,8 +37,70 @@ impl fmt::Debug for Op {
       Op::And => write!(f, "'&'"),
       Op::Or => write!(f, "'|'"),
       Op::Xor => write!(f, "'^'"),
-      Op::Shl => write!(f, "\"<<\""),
-      Op::Shr => write!(f, "\">>\""),
+      Op::Shl => write!(f, "'<<'"),
+      Op::Shr => write!(f, "'>>'"),
+      Op::Eq => write!(f, "'=='"),
+      Op::Ne => write!(f, "'!='"),
+      Op::Lt => write!(f, "'<'"),
+      Op::Le => write!(f, "'<='"),
+      Op::Gt => write!(f, "'>'"),
+      Op::Ge => write!(f, "'>='"),
+    }
+  }
+}
+
+/// A built-in function.
+#[derive(PartialEq, Clone, Copy)]
+pub enum Func {
+  Min,
+  Max,
+  Abs,
+  Round,
+  Floor,
+  Clamp,
+  I8,
+  I16,
+  I32,
+}
+
+impl Func {
+  fn from_name(name: &str) -> Option<Func> {
+    Some(match name {
+      "min" => Func::Min,
+      "max" => Func::Max,
+      "abs" => Func::Abs,
+      "round" => Func::Round,
+      "floor" => Func::Floor,
+      "clamp" => Func::Clamp,
+      "i8" => Func::I8,
+      "i16" => Func::I16,
+      "i32" => Func::I32,
+      _ => return None,
+    })
+  }
+
+  /// Returns the number of arguments.
+  pub fn arity(self) -> usize {
+    match self {
+      Func::Min | Func::Max => 2,
+      Func::Clamp => 3,
+      _ => 1,
+    }
+  }
+}
+
+impl fmt::Debug for Func {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Func::Min => write!(f, "min"),
+      Func::Max => write!(f, "max"),
+      Func::Abs => write!(f, "abs"),
+      Func::Round => write!(f, "round"),
+      Func::Floor => write!(f, "floor"),
+      Func::Clamp => write!(f, "clamp"),
+      Func::I8 => write!(f, "i8"),
+      Func::I16 => write!(f, "i16"),
+      Func::I32 => write!(f, "i32"),
     }
   }
 }
@@ -58,6 +127,10 @@ pub enum Tok {
   ParOpen,
   ParClose,
   Var(Var),
+  Func(Func),
+  Comma,
+  Question,
+  Colon,
 }
 
 impl fmt::Debug for Tok {
@@ -68,6 +141,10 @@ impl fmt::Debug for Tok {
       Tok::ParOpen => write!(f, "'('"),
       Tok::ParClose => write!(f, "')'"),
       Tok::Var(var) => write!(f, "{:?}", var),
+      Tok::Func(func) => write!(f, "{:?}", func),
+      Tok::Comma => write!(f, "','"),
+      Tok::Question => write!(f, "'?'"),
+      Tok::Colon => write!(f, "':'"),
     }
   }
 }
@@ -85,7 +162,7 @@ pub fn lex(input: &str) -> Result<Vec<Tok>, String> {
       ' ' => {
         it.next();
       },
-      '0'...'9' => {
+      '0'..='9' => {
         tokens.push(get_number(&mut it)?);
       },
       '+' => {
@@ -125,25 +202,59 @@ pub fn lex(input: &str) -> Result<Vec<Tok>, String> {
         tokens.push(Op(Xor));
       },
       '<' => {
+        it.next();
+
         match it.peek() {
           Some('<') => {
             it.next();
             tokens.push(Op(Shl));
           },
-          Some(c) => return Err(format!("unexpected character '{}', expected '<'", c)),
-          None => return Err(String::from("unexpected end of string")),
+          Some('=') => {
+            it.next();
+            tokens.push(Op(Le));
+          },
+          _ => tokens.push(Op(Lt)),
         }
       },
       '>' => {
+        it.next();
+
         match it.peek() {
           Some('>') => {
             it.next();
             tokens.push(Op(Shr));
           },
-          Some(c) => return Err(format!("unexpected character '{}', expected '>'", c)),
+          Some('=') => {
+            it.next();
+            tokens.push(Op(Ge));
+          },
+          _ => tokens.push(Op(Gt)),
+        }
+      },
+      '=' | '!' => {
+        let op = if it.next() == Some('=') { Eq } else { Ne };
+
+        match it.next() {
+          Some('=') => tokens.push(Op(op)),
+          Some(c) => return Err(format!("unexpected character '{}', expected '='", c)),
           None => return Err(String::from("unexpected end of string")),
         }
       },
+      ',' => {
+        it.next();
+        tokens.push(Comma);
+      },
+      '?' => {
+        it.next();
+        tokens.push(Question);
+      },
+      ':' => {
+        it.next();
+        tokens.push(Colon);
+      },
+      'a'..='z' => {
+        tokens.push(get_func(&mut it)?);
+      },
       '(' => {
         it.next();
         tokens.push(ParOpen);
@@ -171,14 +282,14 @@ fn get_number<T: Iterator<Item = char>>(it: &mut Peekable<T>) -> Result<Tok, Str
 
   while let Some(c) = it.peek() {
     match *c {
-      c @ '0' if number.len() == 0 => {
+      c @ '0' if number.is_empty() => {
         it.next();
 
         if it.peek() == Some(&'x') {
           it.next();
 
           match it.peek() {
-            Some('0'...'9') | Some('a'...'f') | Some('A'...'F' ) => (),
+            Some('0'..='9') | Some('a'..='f') | Some('A'..='F' ) => (),
             Some(c) => return Err(format!("unexpected character '{}', expected '0'...'f'", c)),
             None => return Err(String::from("unexpected end of string")),
           }
@@ -187,7 +298,7 @@ fn get_number<T: Iterator<Item = char>>(it: &mut Peekable<T>) -> Result<Tok, Str
 
           while let Some(c) = it.peek() {
             match *c {
-              c @ '0'...'9' | c @ 'a'...'f' | c @ 'A'...'F'  => {
+              c @ '0'..='9' | c @ 'a'..='f' | c @ 'A'..='F'  => {
                 it.next();
                 number.push(c);
               },
@@ -200,7 +311,7 @@ fn get_number<T: Iterator<Item = char>>(it: &mut Peekable<T>) -> Result<Tok, Str
 
         number.push(c);
       },
-      c @ '0'...'9' => {
+      c @ '0'..='9' => {
         it.next();
         number.push(c);
       }
@@ -209,14 +320,14 @@ fn get_number<T: Iterator<Item = char>>(it: &mut Peekable<T>) -> Result<Tok, Str
         number.push(c);
 
         match it.peek() {
-          Some('0'...'9') => (),
+          Some('0'..='9') => (),
           Some(c)=>  return Err(format!("unexpected character '{}', expected '0'...'9'", c)),
           None => return Err(String::from("unexpected end of string")),
         }
 
         while let Some(c) = it.peek() {
           match *c {
-            c @ '0'...'9' => {
+            c @ '0'..='9' => {
               it.next();
               number.push(c);
             },
@@ -224,16 +335,32 @@ fn get_number<T: Iterator<Item = char>>(it: &mut Peekable<T>) -> Result<Tok, Str
           }
         }
 
-        return Ok(Tok::Number(Number::Float(number.parse::<f32>().map_err(|err| err.to_string())?)))
+        return Ok(Tok::Number(Number::Float(number.parse::<f64>().map_err(|err| err.to_string())?)))
       },
       _ => break,
     }
   }
 
-  Ok(Tok::Number(Number::Int(i32::from_str_radix(&number, radix).map_err(|err| err.to_string())?)))
+  Ok(Tok::Number(Number::Int(i64::from_str_radix(&number, radix).map_err(|err| err.to_string())?)))
 }
 
-fn get_var<T: Iterator<Item = char>>(mut it: &mut Peekable<T>) -> Result<Tok, String> {
+fn get_func<T: Iterator<Item = char>>(it: &mut Peekable<T>) -> Result<Tok, String> {
+  let mut name = String::new();
+
+  while let Some(c) = it.peek() {
+    match *c {
+      c @ 'a'..='z' | c @ '0'..='9' => {
+        it.next();
+        name.push(c);
+      },
+      _ => break,
+    }
+  }
+
+  Func::from_name(&name).map(Tok::Func).ok_or_else(|| format!("unknown function '{}'", name))
+}
+
+fn get_var<T: Iterator<Item = char>>(it: &mut Peekable<T>) -> Result<Tok, String> {
   use self::Tok::*;
   use self::Var::*;
 
@@ -246,13 +373,13 @@ fn get_var<T: Iterator<Item = char>>(mut it: &mut Peekable<T>) -> Result<Tok, St
       it.next();
       if let Some(c) = it.peek() {
         match *c {
-          c @ '0'...'9' => {
-            let n = match get_number(&mut it)? {
-              Tok::Number(Number::Int(number)) => number,
+          '0'..='9' => {
+            let n = match get_number(it)? {
+              Tok::Number(self::Number::Int(number)) => number,
               n => return Err(format!("expected integer number, found '{:?}'", n)),
             };
 
-            Ok(Var(Byte(n as usize)))
+            Ok(Var(Byte(usize::try_from(n).map_err(|err| err.to_string())?)))
           },
           c => Err(format!("unexpected character '{}'", c)),
         }
@@ -271,7 +398,7 @@ mod tests {
 
   #[test]
   fn number_float() {
-    assert_eq!(lex("3.14").unwrap(), vec![Tok::Number(Number::Float(3.14))]);
+    assert_eq!(lex("2.5").unwrap(), vec![Tok::Number(Number::Float(2.5))]);
   }
 
   #[test]
@@ -284,4 +411,25 @@ mod tests {
     assert_eq!(lex("0xff").unwrap(), vec![Tok::Number(Number::Int(0xff))]);
     assert_eq!(lex("0xFF").unwrap(), vec![Tok::Number(Number::Int(0xff))]);
   }
+
+  #[test]
+  fn shift() {
+    assert_eq!(lex("$b1 << 8").unwrap(), vec![Tok::Var(Var::Byte(1)), Tok::Op(Op::Shl), Tok::Number(Number::Int(8))]);
+    assert_eq!(lex("$v >> 2").unwrap(), vec![Tok::Var(Var::Value), Tok::Op(Op::Shr), Tok::Number(Number::Int(2))]);
+  }
+
+  #[test]
+  fn comparison() {
+    assert_eq!(lex("$v<2").unwrap(), vec![Tok::Var(Var::Value), Tok::Op(Op::Lt), Tok::Number(Number::Int(2))]);
+    assert_eq!(lex("<= >= == !=").unwrap(), vec![Tok::Op(Op::Le), Tok::Op(Op::Ge), Tok::Op(Op::Eq), Tok::Op(Op::Ne)]);
+    assert!(lex("$v = 2").is_err());
+    assert!(lex("!$v").is_err());
+  }
+
+  #[test]
+  fn func() {
+    assert_eq!(lex("i16($b0)").unwrap(), vec![Tok::Func(Func::I16), Tok::ParOpen, Tok::Var(Var::Byte(0)), Tok::ParClose]);
+    assert_eq!(lex("min(1, 2)").unwrap()[3], Tok::Comma);
+    assert_eq!(lex("sqrt(2)").unwrap_err(), "unknown function 'sqrt'");
+  }
 }
@@ -0,0 +1,142 @@
+use std::str::FromStr;
+
+mod number;
+pub use self::number::*;
+
+mod lexer;
+use self::lexer::*;
+
+mod parser;
+use self::parser::*;
+
+mod eval;
+use self::eval::*;
+
+mod inverse;
+use self::inverse::*;
+
+#[cfg(feature = "vcontrold")]
+mod calc;
+
+use serde::de::{self, Deserialize, Deserializer};
+
+/// An arithmetic expression on the raw value `$v` and the bytes `$b0`, `$b1`, … of a block.
+#[derive(Debug, Clone)]
+pub struct Expression {
+  source: String,
+  node: ParseNode,
+}
+
+impl FromStr for Expression {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Expression, Self::Err> {
+    Ok(Expression { source: s.trim().to_owned(), node: ParseNode::from_str(s)? })
+  }
+}
+
+impl Expression {
+  /// Returns the expression as written.
+  pub fn source(&self) -> &str {
+    &self.source
+  }
+
+  /// Evaluates the expression for the value `$v` and the bytes `$bN`.
+  pub fn eval(&self, value: Number, bytes: &[u8]) -> Result<Number, String> {
+    eval(&self.node, value, bytes)
+  }
+
+  /// Derives the inverse of an expression which is affine in `$v`, e.g. `($v - 2) * 10` for `$v / 10 + 2`.
+  pub fn inverse(&self) -> Result<Expression, String> {
+    let node = invert(&self.node)?;
+    Ok(Expression { source: format!("{:?}", node), node })
+  }
+
+  /// Formats the expression as a vcontrold `calc` expression, e.g. `((V/10)+2)` for `$v / 10 + 2`.
+  #[cfg(feature = "vcontrold")]
+  pub fn to_calc(&self) -> Result<String, String> {
+    self::calc::calc(&self.node)
+  }
+}
+
+impl<'de> Deserialize<'de> for Expression {
+  fn deserialize<D>(deserializer: D) -> Result<Expression, D::Error>
+  where
+      D: Deserializer<'de>,
+  {
+    let s = String::deserialize(deserializer)?;
+    Expression::from_str(&s).map_err(de::Error::custom)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn eval(s: &str, value: Number, bytes: &[u8]) -> Result<Number, String> {
+    s.parse::<Expression>()?.eval(value, bytes)
+  }
+
+  #[test]
+  fn precedence() {
+    assert_eq!(eval("$b1 * 100 + $b0", Number::Int(0), &[2, 3]).unwrap(), Number::Int(302));
+    assert_eq!(eval("1 + 2 << 3", Number::Int(0), &[]).unwrap(), Number::Int(24));
+    assert_eq!(eval("~(1 & 2 | 3 ^ 4 & 5 + 10)", Number::Int(0), &[]).unwrap(), Number::Int(-8));
+    assert_eq!(eval("-$v / 2.0", Number::Int(5), &[]).unwrap(), Number::Float(-2.5));
+    assert_eq!(eval("$v * 1000", Number::Int(1 << 32), &[]).unwrap(), Number::Int(4_294_967_296_000));
+  }
+
+  #[test]
+  fn conditional() {
+    let temp = "i16($b1 << 8 | $b0) == 0x7FFF ? -1000 : i16($b1 << 8 | $b0) / 10.0";
+    assert_eq!(eval(temp, Number::Int(0), &[0x2E, 0xFF]).unwrap(), Number::Float(-21.0));
+    assert_eq!(eval(temp, Number::Int(0), &[0xFF, 0x7F]).unwrap(), Number::Int(-1000));
+
+    assert_eq!(eval("$v > 0 ? $v < 10 ? 1 : 2 : 3", Number::Int(5), &[]).unwrap(), Number::Int(1));
+    assert_eq!(eval("$v > 0 ? $v < 10 ? 1 : 2 : 3", Number::Int(50), &[]).unwrap(), Number::Int(2));
+    assert_eq!(eval("$v == 0 ? 0 : 100 / $v", Number::Int(0), &[]).unwrap(), Number::Int(0));
+    assert_eq!(eval("clamp(round($v * 2.5), 0, max(10, $b0))", Number::Int(7), &[15]).unwrap(), Number::Int(15));
+    assert_eq!(eval("1 + 2 < 2 * 2 & 7", Number::Int(0), &[]).unwrap(), Number::Int(1));
+  }
+
+  #[test]
+  fn inverse() {
+    let inverse = |s: &str| s.parse::<Expression>().unwrap().inverse().map(|inverse| inverse.source().to_owned());
+
+    assert_eq!(inverse("$v / 10 + 2").unwrap(), "(($v - 2) * 10)");
+    assert_eq!(inverse("-(100 - $v * 0.5)").unwrap(), "((100 - -($v)) / 0.5)");
+    assert_eq!(inverse("(1 << 4) * $v").unwrap(), "($v / (1 << 4))");
+
+    for (get, value) in &[("$v / 10 + 2", 215), ("-(100 - $v * 0.5)", -7), ("(1 << 4) * $v - 0.25", 3)] {
+      let get = get.parse::<Expression>().unwrap();
+      let n = get.eval(Number::Int(*value), &[]).unwrap();
+      assert_eq!(f64::from(get.inverse().unwrap().eval(n, &[]).unwrap()), *value as f64);
+    }
+
+    assert_eq!(inverse("$b1 << 8 | $b0").unwrap_err(), "$b1 is not available when writing");
+    assert_eq!(inverse("$v * $v").unwrap_err(), "$v occurs more than once");
+    assert_eq!(inverse("100 / $v").unwrap_err(), "operator '/' is not invertible for $v in (100 / $v)");
+    assert_eq!(inverse("i16($v) / 10").unwrap_err(), "function i16 is not invertible");
+    assert!(inverse("$v * (2 - 2)").unwrap_err().contains("multiplies $v by zero"));
+    assert!(inverse("$v & 0xFF").is_err());
+    assert!(inverse("$v > 0 ? $v : 0").is_err());
+    assert!(inverse("42").is_err());
+  }
+
+  #[test]
+  fn invalid() {
+    assert!("1 < 2 < 3".parse::<Expression>().is_err());
+    assert!("$v ? 1".parse::<Expression>().is_err());
+    assert!("min(1)".parse::<Expression>().is_err());
+    assert!("abs 1".parse::<Expression>().is_err());
+    assert!("min(1, 2".parse::<Expression>().is_err());
+    assert!("$v +".parse::<Expression>().is_err());
+    assert!("($v".parse::<Expression>().is_err());
+    assert!("$v 2".parse::<Expression>().is_err());
+    assert!("$x".parse::<Expression>().is_err());
+    assert!(eval("$b2", Number::Int(0), &[1, 2]).is_err());
+    assert_eq!(eval("$v / ($b0 - 1)", Number::Int(1), &[1]), Err("operator '/' divides 1 by zero".to_string()));
+    assert_eq!(eval("$v % 2", Number::Float(1.5), &[]), Ok(Number::Float(1.5)));
+    assert_eq!(eval("~$v", Number::Float(1.5), &[]), Err("operator '~' requires an integer, found 1.5".to_string()));
+  }
+}
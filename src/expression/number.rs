@@ -1,155 +1,221 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
 use std::fmt;
 
-#[derive(PartialEq, Clone)]
+use super::{Func, Op};
+
+#[derive(PartialEq, Clone, Copy)]
 pub enum Number {
-  Float(f32),
-  Int(i32),
+  Float(f64),
+  Int(i64),
 }
 
 impl fmt::Debug for Number   {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     match self {
+      // Always with a decimal point but never with an exponent, so it can be parsed again.
+      Number::Float(float) if float.is_finite() && float.fract() == 0.0 => write!(f, "{}.0", float),
       Number::Float(float) => write!(f, "{}", float),
       Number::Int(int) => write!(f, "{}", int),
     }
   }
 }
 
-impl std::ops::Add for Number {
-  type Output = Number;
-
-  fn add(self, other: Number) -> Number {
-    match (self, other) {
-      (Number::Float(lhs), Number::Float(rhs)) => Number::Float(lhs + rhs),
-      (Number::Int(lhs), Number::Int(rhs)) => Number::Int(lhs + rhs),
-      (Number::Int(lhs), Number::Float(rhs)) => Number::Float(lhs as f32 + rhs),
-      (Number::Float(lhs), Number::Int(rhs)) => Number::Float(lhs + rhs as f32),
+impl Number {
+  /// Converts a number into an `Int` if it is integral and fits, otherwise into a `Float`.
+  pub fn from_f64(n: f64) -> Number {
+    // `i64::MAX as f64` is rounded up to 2^63, which does not fit.
+    if n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64 {
+      Number::Int(n as i64)
+    } else {
+      Number::Float(n)
     }
   }
-}
 
-impl std::ops::Sub for Number {
-  type Output = Number;
+  /// Applies a binary operator.
+  ///
+  /// Integer overflow, division by zero, non-finite results and bitwise operators on floats are errors.
+  /// Dividing integers results in an integer only if the division is exact.
+  /// Comparisons return `1` if they hold and `0` otherwise.
+  pub fn binary(op: Op, lhs: Number, rhs: Number) -> Result<Number, String> {
+    use self::Number::*;
 
-  fn sub(self, other: Number) -> Number {
-    match (self, other) {
-      (Number::Float(lhs), Number::Float(rhs)) => Number::Float(lhs - rhs),
-      (Number::Int(lhs), Number::Int(rhs)) => Number::Int(lhs - rhs),
-      (Number::Int(lhs), Number::Float(rhs)) => Number::Float(lhs as f32 - rhs),
-      (Number::Float(lhs), Number::Int(rhs)) => Number::Float(lhs - rhs as f32),
+    if matches!(op, Op::Div | Op::Mod) && f64::from(rhs) == 0.0 {
+      return Err(format!("operator {:?} divides {:?} by zero", op, lhs))
     }
-  }
-}
-
-impl std::ops::Div for Number {
-  type Output = Number;
 
-  fn div(self, other: Number) -> Number {
-    match (self, other) {
-      (Number::Float(lhs), Number::Float(rhs)) => Number::Float(lhs / rhs),
-      (Number::Int(lhs), Number::Int(rhs)) => Number::Int(lhs / rhs),
-      (Number::Int(lhs), Number::Float(rhs)) => Number::Float(lhs as f32 / rhs),
-      (Number::Float(lhs), Number::Int(rhs)) => Number::Float(lhs / rhs as f32),
+    let result = match (op, lhs, rhs) {
+      (Op::Add, Int(a), Int(b)) => a.checked_add(b).map(Int),
+      (Op::Sub, Int(a), Int(b)) => a.checked_sub(b).map(Int),
+      (Op::Mul, Int(a), Int(b)) => a.checked_mul(b).map(Int),
+      (Op::Div, Int(a), Int(b)) if a.checked_rem(b) == Some(0) => a.checked_div(b).map(Int),
+      (Op::Mod, Int(a), Int(b)) => a.checked_rem(b).map(Int),
+      (Op::And, Int(a), Int(b)) => Some(Int(a & b)),
+      (Op::Or, Int(a), Int(b)) => Some(Int(a | b)),
+      (Op::Xor, Int(a), Int(b)) => Some(Int(a ^ b)),
+      (Op::Shl, Int(a), Int(b)) => u32::try_from(b).ok().and_then(|b| a.checked_shl(b).filter(|n| n >> b == a)).map(Int),
+      (Op::Shr, Int(a), Int(b)) => u32::try_from(b).ok().and_then(|b| a.checked_shr(b)).map(Int),
+      (Op::Eq | Op::Ne | Op::Lt | Op::Le | Op::Gt | Op::Ge, a, b) => {
+        let ordering = match (a, b) {
+          (Int(a), Int(b)) => Some(a.cmp(&b)),
+          (a, b) => f64::from(a).partial_cmp(&f64::from(b)),
+        };
+
+        let result = match (op, ordering) {
+          (Op::Ne, ordering) => ordering != Some(Ordering::Equal),
+          (_, None) => false,
+          (Op::Eq, Some(ordering)) => ordering == Ordering::Equal,
+          (Op::Lt, Some(ordering)) => ordering == Ordering::Less,
+          (Op::Le, Some(ordering)) => ordering != Ordering::Greater,
+          (Op::Gt, Some(ordering)) => ordering == Ordering::Greater,
+          (_, Some(ordering)) => ordering != Ordering::Less,
+        };
+
+        Some(Int(i64::from(result)))
+      },
+      (Op::Add, a, b) => Some(Float(f64::from(a) + f64::from(b))),
+      (Op::Sub, a, b) => Some(Float(f64::from(a) - f64::from(b))),
+      (Op::Mul, a, b) => Some(Float(f64::from(a) * f64::from(b))),
+      (Op::Div, a, b) => Some(Float(f64::from(a) / f64::from(b))),
+      (Op::Mod, a, b) => Some(Float(f64::from(a) % f64::from(b))),
+      (op, a, b) => return Err(format!("operator {:?} requires integers, found {:?} and {:?}", op, a, b)),
+    };
+
+    match result {
+      Some(Float(n)) if !n.is_finite() => Err(format!("operator {:?} overflows for {:?} and {:?}", op, lhs, rhs)),
+      Some(n) => Ok(n),
+      None => Err(format!("operator {:?} overflows for {:?} and {:?}", op, lhs, rhs)),
     }
   }
-}
 
-impl std::ops::Mul for Number {
-  type Output = Number;
+  /// Applies a unary operator.
+  pub fn unary(op: Op, n: Number) -> Result<Number, String> {
+    use self::Number::*;
 
-  fn mul(self, other: Number) -> Number {
-    match (self, other) {
-      (Number::Float(lhs), Number::Float(rhs)) => Number::Float(lhs * rhs),
-      (Number::Int(lhs), Number::Int(rhs)) => Number::Int(lhs * rhs),
-      (Number::Int(lhs), Number::Float(rhs)) => Number::Float(lhs as f32 * rhs),
-      (Number::Float(lhs), Number::Int(rhs)) => Number::Float(lhs * rhs as f32),
+    match (op, n) {
+      (Op::Sub, Int(n)) => n.checked_neg().map(Int).ok_or_else(|| format!("operator {:?} overflows for {}", op, n)),
+      (Op::Sub, Float(n)) => Ok(Float(-n)),
+      (Op::Not, Int(n)) => Ok(Int(!n)),
+      (op, n) => Err(format!("operator {:?} requires an integer, found {:?}", op, n)),
     }
   }
-}
-
-impl std::ops::Rem for Number {
-  type Output = Number;
 
-  fn rem(self, other: Number) -> Number {
-    match (self, other) {
-      (Number::Int(lhs), Number::Int(rhs)) => Number::Int(lhs % rhs),
-      _ => unimplemented!("% for Number::Float"),
+  /// Calls a built-in function with the number of arguments given by its arity.
+  pub fn call(func: Func, args: &[Number]) -> Result<Number, String> {
+    use self::Number::*;
+
+    match (func, args) {
+      (Func::Min, &[Int(a), Int(b)]) => Ok(Int(a.min(b))),
+      (Func::Min, &[a, b]) => Ok(Float(f64::from(a).min(f64::from(b)))),
+      (Func::Max, &[Int(a), Int(b)]) => Ok(Int(a.max(b))),
+      (Func::Max, &[a, b]) => Ok(Float(f64::from(a).max(f64::from(b)))),
+      (Func::Abs, &[Int(n)]) => n.checked_abs().map(Int).ok_or_else(|| format!("function {:?} overflows for {}", func, n)),
+      (Func::Abs, &[Float(n)]) => Ok(Float(n.abs())),
+      (Func::Round, &[Float(n)]) => Ok(Number::from_f64(n.round())),
+      (Func::Floor, &[Float(n)]) => Ok(Number::from_f64(n.floor())),
+      (Func::Round, &[n]) | (Func::Floor, &[n]) => Ok(n),
+      (Func::Clamp, &[n, min, max]) => {
+        if f64::from(min) > f64::from(max) {
+          return Err(format!("function {:?} requires min <= max, found {:?} and {:?}", func, min, max))
+        }
+
+        let n = Number::call(Func::Max, &[n, min])?;
+        Number::call(Func::Min, &[n, max])
+      },
+      (Func::I8, &[n]) | (Func::I16, &[n]) | (Func::I32, &[n]) => {
+        // Reinterprets the lower bits of a signed or unsigned integer as a signed integer.
+        let (min, max, reinterpret): (i64, i64, fn(i64) -> i64) = match func {
+          Func::I8 => (i8::MIN.into(), u8::MAX.into(), |n| i64::from(n as i8)),
+          Func::I16 => (i16::MIN.into(), u16::MAX.into(), |n| i64::from(n as i16)),
+          _ => (i32::MIN.into(), u32::MAX.into(), |n| i64::from(n as i32)),
+        };
+
+        match n {
+          Int(n) if n >= min && n <= max => Ok(Int(reinterpret(n))),
+          n => Err(format!("function {:?} requires an integer from {} to {}, found {:?}", func, min, max, n)),
+        }
+      },
+      (func, args) => Err(format!("function {:?} takes {} argument(s), found {}", func, func.arity(), args.len())),
     }
   }
 }
 
-impl std::ops::Neg for Number {
-  type Output = Number;
-
-  fn neg(self) -> Number {
-    match self {
-      Number::Float(n) => Number::Float(-n),
-      Number::Int(n) => Number::Int(-n),
+impl From<Number> for f64 {
+  fn from(n: Number) -> f64 {
+    match n {
+      Number::Float(float) => float,
+      Number::Int(int) => int as f64,
     }
   }
 }
-impl std::ops::Not for Number {
-  type Output = Number;
 
-  fn not(self) -> Number {
-    match self {
-      Number::Int(n) => Number::Int(!n),
-      _ => unimplemented!("~ for Number::Float"),
-    }
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::Number::*;
+
+  #[test]
+  fn arithmetic() {
+    assert_eq!(Number::binary(Op::Div, Int(7), Int(2)), Ok(Float(3.5)));
+    assert_eq!(Number::binary(Op::Div, Int(-8), Int(2)), Ok(Int(-4)));
+    assert_eq!(Number::binary(Op::Div, Int(7), Float(2.0)), Ok(Float(3.5)));
+    assert_eq!(Number::binary(Op::Mod, Float(7.5), Int(2)), Ok(Float(1.5)));
+    assert_eq!(Number::binary(Op::Mul, Int(1 << 40), Int(1 << 20)), Ok(Int(1 << 60)));
+    assert_eq!(Number::binary(Op::Shl, Int(-1), Int(8)), Ok(Int(-256)));
+    assert_eq!(Number::unary(Op::Sub, Float(1.5)), Ok(Float(-1.5)));
   }
-}
 
-impl std::ops::BitAnd for Number {
-  type Output = Number;
-
-  fn bitand(self, other: Number) -> Number {
-    match (self, other) {
-      (Number::Int(lhs), Number::Int(rhs)) => Number::Int(lhs & rhs),
-      _ => unimplemented!("& for Number::Float"),
-    }
+  #[test]
+  fn overflow() {
+    assert_eq!(Number::binary(Op::Add, Int(i64::MAX), Int(1)), Err(format!("operator '+' overflows for {} and 1", i64::MAX)));
+    assert!(Number::binary(Op::Mul, Int(i64::MIN), Int(-1)).is_err());
+    assert_eq!(Number::binary(Op::Div, Int(i64::MIN), Int(-1)), Ok(Float(9223372036854775808.0)));
+    assert!(Number::binary(Op::Shl, Int(1), Int(64)).is_err());
+    assert!(Number::binary(Op::Shl, Int(3), Int(62)).is_err());
+    assert!(Number::binary(Op::Shr, Int(1), Int(-1)).is_err());
+    assert!(Number::binary(Op::Mul, Float(f64::MAX), Int(2)).is_err());
+    assert!(Number::unary(Op::Sub, Int(i64::MIN)).is_err());
   }
-}
-
-impl std::ops::BitXor for Number {
-  type Output = Number;
 
-  fn bitxor(self, other: Number) -> Number {
-    match (self, other) {
-      (Number::Int(lhs), Number::Int(rhs)) => Number::Int(lhs ^ rhs),
-      _ => unimplemented!("^ for Number::Float"),
-    }
+  #[test]
+  fn division_by_zero() {
+    assert_eq!(Number::binary(Op::Div, Int(1), Int(0)), Err("operator '/' divides 1 by zero".to_string()));
+    assert!(Number::binary(Op::Mod, Int(1), Int(0)).is_err());
+    assert!(Number::binary(Op::Div, Float(1.0), Float(0.0)).is_err());
   }
-}
-
-impl std::ops::BitOr for Number {
-  type Output = Number;
 
-  fn bitor(self, other: Number) -> Number {
-    match (self, other) {
-      (Number::Int(lhs), Number::Int(rhs)) => Number::Int(lhs | rhs),
-      _ => unimplemented!("| for Number::Float"),
-    }
+  #[test]
+  fn comparison() {
+    assert_eq!(Number::binary(Op::Lt, Int(1), Float(1.5)), Ok(Int(1)));
+    assert_eq!(Number::binary(Op::Ge, Int(1), Float(1.5)), Ok(Int(0)));
+    assert_eq!(Number::binary(Op::Eq, Int(2), Float(2.0)), Ok(Int(1)));
+    assert_eq!(Number::binary(Op::Ne, Float(f64::NAN), Float(f64::NAN)), Ok(Int(1)));
+    assert_eq!(Number::binary(Op::Le, Int(i64::MAX), Int(i64::MAX - 1)), Ok(Int(0)));
   }
-}
 
-impl std::ops::Shl for Number {
-  type Output = Number;
-
-  fn shl(self, other: Number) -> Number {
-    match (self, other) {
-      (Number::Int(lhs), Number::Int(rhs)) => Number::Int(lhs << rhs),
-      _ => unimplemented!("<< for Number::Float"),
-    }
+  #[test]
+  fn functions() {
+    assert_eq!(Number::call(Func::Min, &[Int(3), Int(-2)]), Ok(Int(-2)));
+    assert_eq!(Number::call(Func::Max, &[Int(3), Float(3.5)]), Ok(Float(3.5)));
+    assert_eq!(Number::call(Func::Abs, &[Int(-3)]), Ok(Int(3)));
+    assert!(Number::call(Func::Abs, &[Int(i64::MIN)]).is_err());
+    assert_eq!(Number::call(Func::Round, &[Float(-2.5)]), Ok(Int(-3)));
+    assert_eq!(Number::call(Func::Floor, &[Float(-2.5)]), Ok(Int(-3)));
+    assert_eq!(Number::call(Func::Round, &[Int(7)]), Ok(Int(7)));
+    assert_eq!(Number::call(Func::Clamp, &[Int(12), Int(0), Int(10)]), Ok(Int(10)));
+    assert_eq!(Number::call(Func::Clamp, &[Float(-0.5), Int(0), Int(10)]), Ok(Float(0.0)));
+    assert!(Number::call(Func::Clamp, &[Int(1), Int(10), Int(0)]).is_err());
+    assert_eq!(Number::call(Func::I16, &[Int(0xFFFE)]), Ok(Int(-2)));
+    assert_eq!(Number::call(Func::I16, &[Int(0x7FFF)]), Ok(Int(0x7FFF)));
+    assert_eq!(Number::call(Func::I8, &[Int(0x80)]), Ok(Int(-128)));
+    assert_eq!(Number::call(Func::I16, &[Int(0x10000)]), Err("function i16 requires an integer from -32768 to 65535, found 65536".to_string()));
+    assert!(Number::call(Func::I16, &[Float(1.0)]).is_err());
   }
-}
-
-impl std::ops::Shr for Number {
-  type Output = Number;
 
-  fn shr(self, other: Number) -> Number {
-    match (self, other) {
-      (Number::Int(lhs), Number::Int(rhs)) => Number::Int(lhs >> rhs),
-      _ => unimplemented!(">> for Number::Float"),
-    }
+  #[test]
+  fn type_errors() {
+    assert_eq!(Number::binary(Op::And, Float(1.5), Int(2)), Err("operator '&' requires integers, found 1.5 and 2".to_string()));
+    assert_eq!(Number::binary(Op::Shl, Int(1), Float(2.0)), Err("operator '<<' requires integers, found 1 and 2.0".to_string()));
+    assert_eq!(Number::unary(Op::Not, Float(1.0)), Err("operator '~' requires an integer, found 1.0".to_string()));
   }
 }
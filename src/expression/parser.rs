@@ -1,15 +1,17 @@
 use std::fmt;
 use std::str::FromStr;
 
-use super::{Number, Tok, Op, Var, lex};
+use super::{Func, Number, Tok, Op, Var, lex};
 
+// cond   :=  cmp ? cond : cond  |  cmp
+// cmp    :=  or == or      |  or != or      |  or < or  |  or <= or  |  or > or  |  or >= or  |  or
 // or     :=  or | xor      |  xor
 // xor    :=  xor ^ and     |  and
 // and    :=  and & shift   |  shift
 // shift  :=  shift << add  |  shift >> add  |  add
 // add    :=  add - mul     |  add + mul     |  mul
 // mul    :=  mul * final   |  mul / final   |  mul % final | final
-// final  :=  number        |  var           |  ( or )
+// final  :=  number        |  var           |  ( cond )    |  func ( cond , … )
 
 #[derive(Clone)]
 pub enum ParseNode {
@@ -17,6 +19,8 @@ pub enum ParseNode {
   Unary(Op, Box<ParseNode>),
   Number(Number),
   Var(Var),
+  Call(Func, Vec<ParseNode>),
+  Cond(Box<ParseNode>, Box<ParseNode>, Box<ParseNode>),
 }
 
 impl fmt::Debug for ParseNode {
@@ -33,7 +37,13 @@ impl fmt::Debug for ParseNode {
         Op::Or  => write!(f, "({:?} | {:?})", lhs, rhs),
         Op::Shl => write!(f, "({:?} << {:?})", lhs, rhs),
         Op::Shr => write!(f, "({:?} >> {:?})", lhs, rhs),
-        _ => unreachable!(),
+        Op::Eq  => write!(f, "({:?} == {:?})", lhs, rhs),
+        Op::Ne  => write!(f, "({:?} != {:?})", lhs, rhs),
+        Op::Lt  => write!(f, "({:?} < {:?})", lhs, rhs),
+        Op::Le  => write!(f, "({:?} <= {:?})", lhs, rhs),
+        Op::Gt  => write!(f, "({:?} > {:?})", lhs, rhs),
+        Op::Ge  => write!(f, "({:?} >= {:?})", lhs, rhs),
+        Op::Not => unreachable!(),
       },
       ParseNode::Unary(op, expr) => match op {
         Op::Not => write!(f, "~({:?})", expr),
@@ -42,6 +52,19 @@ impl fmt::Debug for ParseNode {
       },
       ParseNode::Number(n) => write!(f, "{:?}", n),
       ParseNode::Var(var) => write!(f, "{:?}", var),
+      ParseNode::Call(func, args) => {
+        write!(f, "{:?}(", func)?;
+
+        for (i, arg) in args.iter().enumerate() {
+          if i > 0 {
+            write!(f, ", ")?;
+          }
+          write!(f, "{:?}", arg)?;
+        }
+
+        write!(f, ")")
+      },
+      ParseNode::Cond(cond, then, otherwise) => write!(f, "({:?} ? {:?} : {:?})", cond, then, otherwise),
     }
   }
 }
@@ -54,16 +77,53 @@ impl FromStr for ParseNode {
   }
 }
 
-fn parse_root(tokens: &Vec<Tok>) -> Result<ParseNode, String> {
-  Ok(parse_or(tokens, 0)?.0)
+fn parse_root(tokens: &[Tok]) -> Result<ParseNode, String> {
+  match parse_cond(tokens, 0)? {
+    (node, pos) if pos == tokens.len() => Ok(node),
+    (_, pos) => Err(format!("unexpected token {:?}, expected operator or end of input", tokens[pos])),
+  }
+}
+
+fn parse_cond(tokens: &[Tok], pos: usize) -> Result<(ParseNode, usize), String> {
+  let (cond, next_pos) = parse_cmp(tokens, pos)?;
+
+  match tokens.get(next_pos) {
+    Some(Tok::Question) => {
+      let (then, next_pos) = parse_cond(tokens, next_pos + 1)?;
+
+      match tokens.get(next_pos) {
+        Some(Tok::Colon) => {
+          let (otherwise, next_pos) = parse_cond(tokens, next_pos + 1)?;
+          Ok((ParseNode::Cond(Box::new(cond), Box::new(then), Box::new(otherwise)), next_pos))
+        },
+        Some(t) => Err(format!("expected ':', but found {:?}", t)),
+        None => Err(String::from("unexpected end of input, expected ':'")),
+      }
+    },
+    _ => Ok((cond, next_pos)),
+  }
+}
+
+fn parse_cmp(tokens: &[Tok], pos: usize) -> Result<(ParseNode, usize), String> {
+  let (lhs, next_pos) = parse_or(tokens, pos)?;
+
+  match tokens.get(next_pos) {
+    Some(Tok::Op(op @ Op::Eq)) | Some(Tok::Op(op @ Op::Ne)) |
+    Some(Tok::Op(op @ Op::Lt)) | Some(Tok::Op(op @ Op::Le)) |
+    Some(Tok::Op(op @ Op::Gt)) | Some(Tok::Op(op @ Op::Ge)) => {
+      let (rhs, next_pos) = parse_or(tokens, next_pos + 1)?;
+      Ok((ParseNode::Binary(*op, Box::new(lhs), Box::new(rhs)), next_pos))
+    },
+    _ => Ok((lhs, next_pos)),
+  }
 }
 
-fn parse_or(tokens: &Vec<Tok>, pos: usize) -> Result<(ParseNode, usize), String> {
+fn parse_or(tokens: &[Tok], pos: usize) -> Result<(ParseNode, usize), String> {
   let (node, next_pos) = parse_xor(tokens, pos)?;
   parse_or_rhs(node, tokens, next_pos)
 }
 
-fn parse_or_rhs(lhs: ParseNode, tokens: &Vec<Tok>, pos: usize) -> Result<(ParseNode, usize), String> {
+fn parse_or_rhs(lhs: ParseNode, tokens: &[Tok], pos: usize) -> Result<(ParseNode, usize), String> {
   match tokens.get(pos) {
     Some(Tok::Op(op @ Op::Or)) => {
       let (rhs, next_pos) = parse_xor(tokens, pos + 1)?;
@@ -78,12 +138,12 @@ fn parse_or_rhs(lhs: ParseNode, tokens: &Vec<Tok>, pos: usize) -> Result<(ParseN
   }
 }
 
-fn parse_xor(tokens: &Vec<Tok>, pos: usize) -> Result<(ParseNode, usize), String> {
+fn parse_xor(tokens: &[Tok], pos: usize) -> Result<(ParseNode, usize), String> {
   let (node, next_pos) = parse_and(tokens, pos)?;
   parse_xor_rhs(node, tokens, next_pos)
 }
 
-fn parse_xor_rhs(lhs: ParseNode, tokens: &Vec<Tok>, pos: usize) -> Result<(ParseNode, usize), String> {
+fn parse_xor_rhs(lhs: ParseNode, tokens: &[Tok], pos: usize) -> Result<(ParseNode, usize), String> {
   match tokens.get(pos) {
     Some(Tok::Op(op @ Op::Xor)) => {
       let (rhs, next_pos) = parse_and(tokens, pos + 1)?;
@@ -98,12 +158,12 @@ fn parse_xor_rhs(lhs: ParseNode, tokens: &Vec<Tok>, pos: usize) -> Result<(Parse
   }
 }
 
-fn parse_and(tokens: &Vec<Tok>, pos: usize) -> Result<(ParseNode, usize), String> {
+fn parse_and(tokens: &[Tok], pos: usize) -> Result<(ParseNode, usize), String> {
   let (node, next_pos) = parse_shift(tokens, pos)?;
   parse_and_rhs(node, tokens, next_pos)
 }
 
-fn parse_and_rhs(lhs: ParseNode, tokens: &Vec<Tok>, pos: usize) -> Result<(ParseNode, usize), String> {
+fn parse_and_rhs(lhs: ParseNode, tokens: &[Tok], pos: usize) -> Result<(ParseNode, usize), String> {
   match tokens.get(pos) {
     Some(Tok::Op(op @ Op::And)) => {
       let (rhs, next_pos) = parse_shift(tokens, pos + 1)?;
@@ -118,12 +178,12 @@ fn parse_and_rhs(lhs: ParseNode, tokens: &Vec<Tok>, pos: usize) -> Result<(Parse
   }
 }
 
-fn parse_shift(tokens: &Vec<Tok>, pos: usize) -> Result<(ParseNode, usize), String> {
+fn parse_shift(tokens: &[Tok], pos: usize) -> Result<(ParseNode, usize), String> {
   let (node, next_pos) = parse_add(tokens, pos)?;
   parse_shift_rhs(node, tokens, next_pos)
 }
 
-fn parse_shift_rhs(lhs: ParseNode, tokens: &Vec<Tok>, pos: usize) -> Result<(ParseNode, usize), String> {
+fn parse_shift_rhs(lhs: ParseNode, tokens: &[Tok], pos: usize) -> Result<(ParseNode, usize), String> {
   match tokens.get(pos) {
     Some(Tok::Op(op @ Op::Shl)) |
     Some(Tok::Op(op @ Op::Shr)) => {
@@ -139,12 +199,12 @@ fn parse_shift_rhs(lhs: ParseNode, tokens: &Vec<Tok>, pos: usize) -> Result<(Par
   }
 }
 
-fn parse_add(tokens: &Vec<Tok>, pos: usize) -> Result<(ParseNode, usize), String> {
+fn parse_add(tokens: &[Tok], pos: usize) -> Result<(ParseNode, usize), String> {
   let (lhs, next_pos) = parse_mul(tokens, pos)?;
   parse_add_rhs(lhs, tokens, next_pos)
 }
 
-fn parse_add_rhs(lhs: ParseNode, tokens: &Vec<Tok>, pos: usize) -> Result<(ParseNode, usize), String> {
+fn parse_add_rhs(lhs: ParseNode, tokens: &[Tok], pos: usize) -> Result<(ParseNode, usize), String> {
   match tokens.get(pos) {
     Some(Tok::Op(op @ Op::Add)) | Some(Tok::Op(op @ Op::Sub)) => {
       let (rhs, next_pos) = parse_mul(tokens, pos + 1)?;
@@ -159,12 +219,12 @@ fn parse_add_rhs(lhs: ParseNode, tokens: &Vec<Tok>, pos: usize) -> Result<(Parse
   }
 }
 
-fn parse_mul(tokens: &Vec<Tok>, pos: usize) -> Result<(ParseNode, usize), String> {
+fn parse_mul(tokens: &[Tok], pos: usize) -> Result<(ParseNode, usize), String> {
   let (node, next_pos) = parse_final(tokens, pos)?;
   parse_mul_rhs(node, tokens, next_pos)
 }
 
-fn parse_mul_rhs(lhs: ParseNode, tokens: &Vec<Tok>, pos: usize) -> Result<(ParseNode, usize), String> {
+fn parse_mul_rhs(lhs: ParseNode, tokens: &[Tok], pos: usize) -> Result<(ParseNode, usize), String> {
   match tokens.get(pos) {
     Some(Tok::Op(op @ Op::Mul)) |
     Some(Tok::Op(op @ Op::Div)) |
@@ -181,18 +241,19 @@ fn parse_mul_rhs(lhs: ParseNode, tokens: &Vec<Tok>, pos: usize) -> Result<(Parse
   }
 }
 
-fn parse_final(tokens: &Vec<Tok>, pos: usize) -> Result<(ParseNode, usize), String> {
-  let c = tokens.get(pos).ok_or("Unexpected end of input, expected open paren, variable or number")?;
+fn parse_final(tokens: &[Tok], pos: usize) -> Result<(ParseNode, usize), String> {
+  let c = tokens.get(pos).ok_or("unexpected end of input, expected '(', variable or number")?;
 
   match c {
     Tok::Op(op @ Op::Not) | Tok::Op(op @ Op::Sub) => {
       let (node, next_pos) = parse_final(tokens, pos + 1)?;
       Ok((ParseNode::Unary(*op, Box::new(node)), next_pos))
     },
-    Tok::Number(n) => Ok((ParseNode::Number(n.clone()), pos + 1)),
+    Tok::Number(n) => Ok((ParseNode::Number(*n), pos + 1)),
     Tok::Var(var) => Ok((ParseNode::Var(var.clone()), pos + 1)),
+    Tok::Func(func) => parse_call(*func, tokens, pos + 1),
     Tok::ParOpen => {
-      parse_or(tokens, pos + 1).and_then(|(or_node, next_pos)| {
+      parse_cond(tokens, pos + 1).and_then(|(or_node, next_pos)| {
         let c2 = tokens.get(next_pos).ok_or("unexpected end of input, expected ')'")?;
 
         match c2 {
@@ -210,3 +271,35 @@ fn parse_final(tokens: &Vec<Tok>, pos: usize) -> Result<(ParseNode, usize), Stri
     }
   }
 }
+
+fn parse_call(func: Func, tokens: &[Tok], pos: usize) -> Result<(ParseNode, usize), String> {
+  match tokens.get(pos) {
+    Some(Tok::ParOpen) => {},
+    Some(t) => return Err(format!("expected '(' after {:?}, but found {:?}", func, t)),
+    None => return Err(format!("unexpected end of input, expected '(' after {:?}", func)),
+  }
+
+  let mut args = Vec::new();
+  let mut pos = pos + 1;
+
+  loop {
+    let (arg, next_pos) = parse_cond(tokens, pos)?;
+    args.push(arg);
+
+    match tokens.get(next_pos) {
+      Some(Tok::Comma) => pos = next_pos + 1,
+      Some(Tok::ParClose) => {
+        pos = next_pos + 1;
+        break
+      },
+      Some(t) => return Err(format!("expected ',' or ')', but found {:?}", t)),
+      None => return Err(String::from("unexpected end of input, expected ')'")),
+    }
+  }
+
+  if args.len() != func.arity() {
+    return Err(format!("function {:?} takes {} argument(s), found {}", func, func.arity(), args.len()))
+  }
+
+  Ok((ParseNode::Call(func, args), pos))
+}
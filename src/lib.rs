@@ -6,18 +6,47 @@ pub use crate::error::Error;
 pub mod types;
 use crate::types::{FromBytes, ToBytes};
 
+mod expression;
+
 mod command;
-pub(crate) use crate::command::{AccessMode, Command};
+pub use crate::command::{AccessMode, Command};
+
+pub mod transport;
+pub use crate::transport::Transport;
 
 mod optolink;
 pub use crate::optolink::Optolink;
 
+#[cfg(feature = "async")]
+mod async_optolink;
+#[cfg(feature = "async")]
+pub use crate::async_optolink::{AsyncOptolink, AsyncTransport};
+
+#[cfg(any(test, feature = "simulator"))]
+mod simulator;
+#[cfg(any(test, feature = "simulator"))]
+pub use crate::simulator::Simulator;
+
 pub mod protocol;
-pub use crate::protocol::Protocol;
+pub use crate::protocol::{Protocol, ProtocolKind};
+#[cfg(feature = "async")]
+pub use crate::protocol::AsyncProtocol;
 
 pub mod device;
 pub use crate::device::Device;
 
+mod configuration;
+pub use crate::configuration::Configuration;
+
+#[cfg(feature = "vcontrold")]
+pub mod vcontrold;
+
+mod error_history;
+pub use crate::error_history::{ErrorEntry, ErrorHistory};
+
+mod weekly_schedule;
+pub use crate::weekly_schedule::WeeklySchedule;
+
 mod vcontrol;
 pub use crate::vcontrol::*;
 
@@ -25,4 +54,4 @@ mod value;
 pub use crate::value::Value;
 
 mod unit;
-pub(crate) use crate::unit::Unit;
+pub use crate::unit::Unit;
@@ -1,36 +1,43 @@
-use std::fmt;
 use std::ffi::OsStr;
 use std::io::{self, Read, Write};
 use std::net::{TcpStream, ToSocketAddrs, SocketAddr};
 use std::time::Duration;
-use std::os::unix::io::AsRawFd;
 
-use serial_core::{SerialPort, SerialPortSettings, BaudRate::Baud4800, Parity::ParityEven, StopBits::Stop2, CharSize::Bits8};
-use serial::SystemPort;
+use crate::{Transport, Protocol, ProtocolKind, protocol::{Kw2, P300}, transport::Serial};
 
-enum Device {
-  Tty(SystemPort),
-  Stream(TcpStream),
-}
-
-impl fmt::Debug for Device   {
-  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    match self {
-      Device::Tty(tty) => write!(f, "TTYPort {{ fd: {:?} }}", tty.as_raw_fd()),
-      Device::Stream(stream) => stream.fmt(f),
-    }
-  }
-}
-
-/// An Optolink connection via either a serial or TCP connection.
+/// An Optolink connection via any [`Transport`], e.g. a serial or TCP connection.
 #[derive(Debug)]
 pub struct Optolink {
-  device: Device,
+  device: Box<dyn Transport>,
   timeout: Option<Duration>,
 }
 
 impl Optolink {
   pub(crate) const TIMEOUT: Duration = Duration::from_secs(60);
+  pub(crate) const DETECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+  /// Creates an Optolink connection using the given transport.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// # #[cfg(unix)]
+  /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+  /// use std::os::unix::net::UnixStream;
+  ///
+  /// use vcontrol::Optolink;
+  ///
+  /// let mut device = Optolink::new(UnixStream::connect("/run/optolink.sock")?);
+  /// # Ok(())
+  /// # }
+  /// # #[cfg(not(unix))]
+  /// # fn main() {}
+  /// ```
+  pub fn new(transport: impl Transport + 'static) -> Optolink {
+    log::trace!("Optolink::new(…)");
+
+    Optolink { device: Box::new(transport), timeout: None }
+  }
 
   /// Opens a serial device.
   ///
@@ -39,7 +46,7 @@ impl Optolink {
   /// ```no_run
   /// use vcontrol::Optolink;
   ///
-  /// # fn main() -> Result<(), Box<std::error::Error>> {
+  /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
   /// let mut device = Optolink::open("/dev/ttyUSB0")?;
   /// # Ok(())
   /// # }
@@ -47,27 +54,7 @@ impl Optolink {
   pub fn open(port: impl AsRef<OsStr>) -> io::Result<Optolink> {
     log::trace!("Optolink::open(…)");
 
-    let mut tty = serial::open(&port)
-      .map_err(|err| {
-        let err = io::Error::from(err);
-
-        if err.kind() == io::ErrorKind::NotFound {
-          return io::Error::new(err.kind(), format!("{}: {}", err, port.as_ref().to_string_lossy()))
-        }
-
-        err
-      })?;
-
-    tty.set_timeout(Self::TIMEOUT)?;
-
-    tty.reconfigure(&|settings: &mut dyn SerialPortSettings| -> Result<(), serial_core::Error> {
-      settings.set_parity(ParityEven);
-      settings.set_stop_bits(Stop2);
-      settings.set_char_size(Bits8);
-      settings.set_baud_rate(Baud4800)
-    })?;
-
-    Ok(Optolink { device: Device::Tty(tty), timeout: None })
+    Ok(Optolink::new(Serial::open(port)?))
   }
 
   /// Connects to a device via TCP.
@@ -77,7 +64,7 @@ impl Optolink {
   /// ```no_run
   /// use vcontrol::Optolink;
   ///
-  /// # fn main() -> Result<(), Box<std::error::Error>> {
+  /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
   /// let mut device = Optolink::connect(("localhost", 1234))?;
   /// # Ok(())
   /// # }
@@ -92,37 +79,83 @@ impl Optolink {
         io::Error::new(err.kind(), format!("{}: {}", err, addrs.iter().map(|addr| addr.to_string()).collect::<Vec<String>>().join(", ")))
       })?;
     stream.set_read_timeout(Some(Self::TIMEOUT))?;
-    Ok(Optolink { device: Device::Stream(stream), timeout: None })
+    Ok(Optolink::new(stream))
+  }
+
+  /// Connects to a simulated device.
+  ///
+  /// This requires the `simulator` feature.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// # #[cfg(all(feature = "simulator", feature = "device-V200KW2_6"))]
+  /// # fn main() {
+  /// use vcontrol::{Optolink, Simulator, device::V200KW2};
+  ///
+  /// let mut device = Optolink::simulate(Simulator::new(&V200KW2));
+  /// # }
+  /// # #[cfg(not(all(feature = "simulator", feature = "device-V200KW2_6")))]
+  /// # fn main() {}
+  /// ```
+  #[cfg(any(test, feature = "simulator"))]
+  pub fn simulate(simulator: crate::Simulator) -> Optolink {
+    log::trace!("Optolink::simulate(…)");
+
+    Optolink::new(simulator)
   }
 
   /// Purge all contents of the input buffer.
   pub fn purge(&mut self) -> Result<(), io::Error> {
     log::trace!("Optolink::purge()");
 
-    match &mut self.device {
-      Device::Tty(tty) => {
-        tty.set_timeout(Duration::new(0, 0))?;
-        let _ = tty.bytes().try_for_each(|b| b.map(|_| ()));
-        tty.set_timeout(self.timeout.unwrap_or(Self::TIMEOUT))?;
-      }
-      Device::Stream(stream) => {
-        stream.set_nonblocking(true)?;
-        let _ = stream.bytes().try_for_each(|b| b.map(|_| ()));
-        stream.set_nonblocking(false)?;
-      },
-    }
-
-    Ok(())
+    self.device.purge()
   }
 
   /// Set timeout for operations on the Optolink device.
   pub fn set_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
     self.timeout = timeout;
+    self.device.set_timeout(timeout)
+  }
+
+  /// Returns the timeout for operations on the Optolink device, or the default timeout if none is set.
+  pub(crate) fn timeout(&self) -> Duration {
+    self.timeout.unwrap_or(Self::TIMEOUT)
+  }
+
+  /// Detects which protocol the device supports.
+  ///
+  /// This first tries to negotiate the `P300` protocol and
+  /// falls back to waiting for a `Kw2` synchronization byte.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use vcontrol::Optolink;
+  ///
+  /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+  /// let mut device = Optolink::open("/dev/ttyUSB0")?;
+  /// println!("Protocol: {}", device.detect_protocol()?);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn detect_protocol(&mut self) -> io::Result<ProtocolKind> {
+    log::trace!("Optolink::detect_protocol()");
 
-    match &mut self.device {
-      Device::Tty(tty) => Ok(tty.set_timeout(timeout.unwrap_or(Self::TIMEOUT))?),
-      Device::Stream(stream) => stream.set_read_timeout(timeout),
+    let timeout = self.timeout;
+
+    self.set_timeout(Some(Self::DETECT_TIMEOUT))?;
+    let p300 = P300::negotiate(self);
+    self.set_timeout(timeout)?;
+
+    match p300 {
+      Ok(()) => return Ok(ProtocolKind::P300),
+      Err(err) => log::debug!("Optolink::detect_protocol() P300 negotiation failed: {}", err),
     }
+
+    Kw2::sync(self)?;
+
+    Ok(ProtocolKind::Kw2)
   }
 }
 
@@ -130,64 +163,13 @@ impl Write for Optolink {
   fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
     log::trace!("Optolink::write(…)");
 
-    match &mut self.device {
-      Device::Tty(tty) => tty.write(buf),
-      Device::Stream(stream) => stream.write(buf),
-    }
+    self.device.write(buf)
   }
 
   fn flush(&mut self) -> Result<(), io::Error> {
     log::trace!("Optolink::flush()");
 
-    match &mut self.device {
-      // This is a workaround for `tcdrain`, which `SystemPort::flush`
-      // uses under the hood. If a device is disconnected, `tcdrain`
-      // will block forever instead of timing out, so we spawn it in a
-      // separate thread and manually create a timeout.
-      #[cfg(unix)]
-      Device::Tty(ref mut tty) => {
-        use std::mem;
-        use std::os::unix::thread::JoinHandleExt;
-        use std::sync::mpsc::channel;
-        use std::thread;
-        use std::time::Instant;
-
-        let start = Instant::now();
-
-        // Allow moving this to the helper thread. This is safe because we either
-        // join the thread in this scope or cancel it if it times out.
-        let tty: &mut SystemPort = tty;
-        let tty: &'static mut SystemPort = unsafe { mem::transmute(tty) };
-
-        let (tx, rx) = channel();
-
-        let t = thread::spawn(move || {
-          let res = tty.flush();
-          tx.send(()).unwrap();
-          res
-        });
-
-        loop {
-          log::trace!("Optolink::flush() loop");
-
-          if rx.try_recv().is_ok() {
-            return t.join().unwrap()
-          }
-
-          let stop = Instant::now();
-
-          if (stop - start) > Self::TIMEOUT {
-            assert_eq!(unsafe { libc::pthread_cancel(t.into_pthread_t() as _) }, 0);
-            return Err(io::Error::new(io::ErrorKind::TimedOut, "flush timed out"))
-          }
-
-          thread::yield_now();
-        }
-      },
-      #[cfg(not(unix))]
-      Device::Tty(tty) => tty.flush(),
-      Device::Stream(stream) => stream.flush(),
-    }
+    self.device.flush_timeout(self.timeout())
   }
 }
 
@@ -195,9 +177,6 @@ impl Read for Optolink {
   fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
     log::trace!("Optolink::read(…)");
 
-    match &mut self.device {
-      Device::Tty(tty) => tty.read(buf),
-      Device::Stream(stream) => stream.read(buf),
-    }
+    self.device.read(buf)
   }
 }
@@ -1,6 +1,97 @@
+use std::fmt;
 use std::io;
+use std::str::FromStr;
 
-use crate::Optolink;
+use serde::de::{self, Deserialize, Deserializer};
+
+use crate::{Error, Optolink};
+#[cfg(feature = "async")]
+use crate::AsyncOptolink;
+
+/// A protocol used by a device or detected by [`Optolink::detect_protocol`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolKind {
+  Kw2,
+  P300,
+}
+
+impl fmt::Display for ProtocolKind {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ProtocolKind::Kw2 => write!(f, "Kw2"),
+      ProtocolKind::P300 => write!(f, "P300"),
+    }
+  }
+}
+
+impl FromStr for ProtocolKind {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<ProtocolKind, Self::Err> {
+    match s {
+      "Kw2" => Ok(ProtocolKind::Kw2),
+      "P300" => Ok(ProtocolKind::P300),
+      _ => Err(Error::InvalidArgument(format!("unknown protocol {:?}", s))),
+    }
+  }
+}
+
+impl<'de> Deserialize<'de> for ProtocolKind {
+  fn deserialize<D>(deserializer: D) -> Result<ProtocolKind, D::Error>
+  where
+      D: Deserializer<'de>,
+  {
+    let s = String::deserialize(deserializer)?;
+    s.parse().map_err(|_| de::Error::unknown_variant(&s, &["Kw2", "P300"]))
+  }
+}
+
+impl ProtocolKind {
+  pub(crate) fn negotiate(self, o: &mut Optolink) -> Result<(), io::Error> {
+    match self {
+      ProtocolKind::Kw2 => <Kw2 as Protocol>::negotiate(o),
+      ProtocolKind::P300 => <P300 as Protocol>::negotiate(o),
+    }
+  }
+
+  pub(crate) fn get(self, o: &mut Optolink, addr: &[u8], buf: &mut [u8]) -> Result<(), io::Error> {
+    match self {
+      ProtocolKind::Kw2 => <Kw2 as Protocol>::get(o, addr, buf),
+      ProtocolKind::P300 => <P300 as Protocol>::get(o, addr, buf),
+    }
+  }
+
+  pub(crate) fn set(self, o: &mut Optolink, addr: &[u8], value: &[u8]) -> Result<(), io::Error> {
+    match self {
+      ProtocolKind::Kw2 => <Kw2 as Protocol>::set(o, addr, value),
+      ProtocolKind::P300 => <P300 as Protocol>::set(o, addr, value),
+    }
+  }
+
+  #[cfg(feature = "async")]
+  pub(crate) async fn negotiate_async(self, o: &mut AsyncOptolink) -> Result<(), io::Error> {
+    match self {
+      ProtocolKind::Kw2 => <Kw2 as AsyncProtocol>::negotiate(o).await,
+      ProtocolKind::P300 => <P300 as AsyncProtocol>::negotiate(o).await,
+    }
+  }
+
+  #[cfg(feature = "async")]
+  pub(crate) async fn get_async(self, o: &mut AsyncOptolink, addr: &[u8], buf: &mut [u8]) -> Result<(), io::Error> {
+    match self {
+      ProtocolKind::Kw2 => <Kw2 as AsyncProtocol>::get(o, addr, buf).await,
+      ProtocolKind::P300 => <P300 as AsyncProtocol>::get(o, addr, buf).await,
+    }
+  }
+
+  #[cfg(feature = "async")]
+  pub(crate) async fn set_async(self, o: &mut AsyncOptolink, addr: &[u8], value: &[u8]) -> Result<(), io::Error> {
+    match self {
+      ProtocolKind::Kw2 => <Kw2 as AsyncProtocol>::set(o, addr, value).await,
+      ProtocolKind::P300 => <P300 as AsyncProtocol>::set(o, addr, value).await,
+    }
+  }
+}
 
 pub trait Protocol {
   /// Negotiates the protocol.
@@ -13,8 +104,22 @@ pub trait Protocol {
   fn set(o: &mut Optolink, addr: &[u8], value: &[u8]) -> Result<(), io::Error>;
 }
 
-mod kw2;
+/// The asynchronous counterpart of [`Protocol`].
+#[cfg(feature = "async")]
+#[async_trait::async_trait]
+pub trait AsyncProtocol {
+  /// Negotiates the protocol.
+  async fn negotiate(o: &mut AsyncOptolink) -> Result<(), io::Error>;
+
+  /// Reads the value at the address `addr` into `buf`.
+  async fn get(o: &mut AsyncOptolink, addr: &[u8], buf: &mut [u8]) -> Result<(), io::Error>;
+
+  /// Writes the given value `value` to the the address `addr`.
+  async fn set(o: &mut AsyncOptolink, addr: &[u8], value: &[u8]) -> Result<(), io::Error>;
+}
+
+pub mod kw2;
 pub use self::kw2::Kw2;
 
-mod p300;
+pub mod p300;
 pub use self::p300::P300;
@@ -1,10 +1,13 @@
 use std::io::{self, Read, Write};
-use std::time::{Instant, Duration};
+use std::time::Instant;
 
-use crate::Optolink;
+use crate::{Error, Optolink};
 
 use super::Protocol;
 
+mod machine;
+pub use self::machine::{Action, Event, Transaction};
+
 const RESET: u8 = 0x04;
 const SYNC: u8  = 0x05;
 
@@ -12,32 +15,51 @@ const SYNC: u8  = 0x05;
 pub struct Kw2;
 
 impl Kw2 {
-  fn sync(o: &mut Optolink) -> Result<(), std::io::Error> {
+  /// Waits for a synchronization byte from the device.
+  pub(crate) fn sync(o: &mut Optolink) -> Result<(), io::Error> {
     log::trace!("Kw2::sync(…)");
 
-    let mut buf = [0xff];
-
-    let start = Instant::now();
+    Self::run(o, Transaction::sync(o.timeout(), Instant::now()))?;
 
-    // Reset the Optolink connection to get a faster SYNC (`0x05`).
-    Self::negotiate(o)?;
-
-    loop {
-      log::trace!("Kw2::sync(…) loop");
+    Ok(())
+  }
 
-      if o.read_exact(&mut buf).is_ok() && buf == [SYNC] {
-        o.purge()?;
-        return Ok(())
-      }
+  fn invalid_input(err: Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, err)
+  }
 
-      let stop = Instant::now();
+  fn run(o: &mut Optolink, mut transaction: Transaction) -> Result<Vec<u8>, io::Error> {
+    let mut action = transaction.start();
 
-      if (stop - start) > Optolink::TIMEOUT {
-        break
-      }
+    loop {
+      let event = match action {
+        Action::Purge => {
+          o.purge()?;
+          Event::Completed(Instant::now())
+        },
+        Action::Write(bytes) => {
+          o.write_all(&bytes)?;
+          o.flush()?;
+          Event::Completed(Instant::now())
+        },
+        Action::Read(len) => {
+          let mut buf = vec![0; len];
+          o.read_exact(&mut buf)?;
+          Event::Received(buf, Instant::now())
+        },
+        Action::TryRead(len) => {
+          let mut buf = vec![0; len];
+          match o.read_exact(&mut buf) {
+            Ok(()) => Event::Received(buf, Instant::now()),
+            Err(_) => Event::ReadFailed(Instant::now()),
+          }
+        },
+        Action::Done(buf) => return Ok(buf),
+        Action::Fail(kind, description) => return Err(io::Error::new(kind, description)),
+      };
+
+      action = transaction.step(event);
     }
-
-    Err(io::Error::new(io::ErrorKind::TimedOut, "sync timed out"))
   }
 }
 
@@ -55,85 +77,103 @@ impl Protocol for Kw2 {
   fn get(o: &mut Optolink, addr: &[u8], buf: &mut [u8]) -> Result<(), io::Error> {
     log::trace!("Kw2::get(…)");
 
-    let mut vec = Vec::new();
-    vec.extend(&[0x01, 0xf7]);
-    vec.extend(addr);
-    vec.extend(&[buf.len() as u8]);
-
-    let start = Instant::now();
+    let transaction = Transaction::get(addr, buf.len(), o.timeout(), Instant::now()).map_err(Self::invalid_input)?;
+    buf.copy_from_slice(&Self::run(o, transaction)?);
 
-    Self::sync(o)?;
+    Ok(())
+  }
 
-    loop {
-      log::trace!("Kw2::get(…) loop");
+  fn set(o: &mut Optolink, addr: &[u8], value: &[u8]) -> Result<(), io::Error> {
+    log::trace!("Kw2::set(…)");
 
-      o.write_all(&vec)?;
-      o.flush()?;
+    let transaction = Transaction::set(addr, value, o.timeout(), Instant::now()).map_err(Self::invalid_input)?;
+    Self::run(o, transaction)?;
 
-      let read_start = Instant::now();
+    Ok(())
+  }
+}
 
-      o.read_exact(buf)?;
+#[cfg(feature = "async")]
+mod r#async {
+  use super::*;
 
-      let stop = Instant::now();
+  use async_trait::async_trait;
 
-      // Retry if the response contains `SYNC` (`0x05`),
-      // since these could be synchronization bytes.
-      if buf.iter().any(|byte| *byte == SYNC) {
-        let read_time = stop - read_start;
+  use crate::{AsyncOptolink, AsyncProtocol};
 
-        log::debug!("Kw2::get(…) buf = {}", buf.iter().map(|b| format!("{:02X}", b)).collect::<Vec<String>>().join(" "));
-        log::debug!("Kw2::get(…) read_time = {:?}", read_time);
+  impl Kw2 {
+    /// Waits for a synchronization byte from the device.
+    pub(crate) async fn sync_async(o: &mut AsyncOptolink) -> Result<(), io::Error> {
+      log::trace!("Kw2::sync_async(…)");
 
-        // Return `Ok` if the response was received in a short amount of time,
-        // since then they most likely are not synchronization bytes.
-        if read_time < Duration::from_millis(500 * buf.len() as u64) {
-          return Ok(())
-        }
+      Self::run_async(o, Transaction::sync(o.timeout(), Instant::now())).await?;
 
-        o.purge()?;
-      } else {
-        return Ok(())
-      }
+      Ok(())
+    }
 
-      if (stop - start) > Optolink::TIMEOUT {
-        break
+    async fn run_async(o: &mut AsyncOptolink, mut transaction: Transaction) -> Result<Vec<u8>, io::Error> {
+      let mut action = transaction.start();
+
+      loop {
+        let event = match action {
+          Action::Purge => {
+            o.purge().await?;
+            Event::Completed(Instant::now())
+          },
+          Action::Write(bytes) => {
+            o.write_all(&bytes).await?;
+            o.flush().await?;
+            Event::Completed(Instant::now())
+          },
+          Action::Read(len) => {
+            let mut buf = vec![0; len];
+            o.read_exact(&mut buf).await?;
+            Event::Received(buf, Instant::now())
+          },
+          Action::TryRead(len) => {
+            let mut buf = vec![0; len];
+            match o.read_exact(&mut buf).await {
+              Ok(()) => Event::Received(buf, Instant::now()),
+              Err(_) => Event::ReadFailed(Instant::now()),
+            }
+          },
+          Action::Done(buf) => return Ok(buf),
+          Action::Fail(kind, description) => return Err(io::Error::new(kind, description)),
+        };
+
+        action = transaction.step(event);
       }
     }
-
-    Err(io::Error::new(io::ErrorKind::TimedOut, "get timed out"))
   }
 
-  fn set(o: &mut Optolink, addr: &[u8], value: &[u8]) -> Result<(), io::Error> {
-    log::trace!("Kw2::set(…)");
+  #[async_trait]
+  impl AsyncProtocol for Kw2 {
+    async fn negotiate(o: &mut AsyncOptolink) -> Result<(), io::Error> {
+      log::trace!("Kw2::negotiate(…)");
 
-    let mut vec = Vec::new();
-    vec.extend(&[0x01, 0xf4]);
-    vec.extend(addr);
-    vec.extend(&[value.len() as u8]);
-    vec.extend(value);
+      o.purge().await?;
+      o.write_all(&[RESET]).await?;
+      o.flush().await?;
 
-    let start = Instant::now();
+      Ok(())
+    }
 
-    Self::sync(o)?;
+    async fn get(o: &mut AsyncOptolink, addr: &[u8], buf: &mut [u8]) -> Result<(), io::Error> {
+      log::trace!("Kw2::get(…)");
 
-    loop {
-      o.write_all(&vec)?;
-      o.flush()?;
+      let transaction = Transaction::get(addr, buf.len(), o.timeout(), Instant::now()).map_err(Self::invalid_input)?;
+      buf.copy_from_slice(&Self::run_async(o, transaction).await?);
 
-      let mut buf = [0xff];
-      o.read_exact(&mut buf)?;
+      Ok(())
+    }
 
-      let stop = Instant::now();
+    async fn set(o: &mut AsyncOptolink, addr: &[u8], value: &[u8]) -> Result<(), io::Error> {
+      log::trace!("Kw2::set(…)");
 
-      if buf == [0x00] {
-        return Ok(())
-      }
+      let transaction = Transaction::set(addr, value, o.timeout(), Instant::now()).map_err(Self::invalid_input)?;
+      Self::run_async(o, transaction).await?;
 
-      if (stop - start) > Optolink::TIMEOUT {
-        break
-      }
+      Ok(())
     }
-
-    Err(io::Error::new(io::ErrorKind::TimedOut, "set timed out"))
   }
 }
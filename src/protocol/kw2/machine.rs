@@ -0,0 +1,385 @@
+use std::convert::TryFrom;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::Error;
+
+use super::{RESET, SYNC};
+
+/// An action a KW2 driver has to perform for a [`Transaction`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+  /// Purge the input buffer and report [`Event::Completed`].
+  Purge,
+  /// Write and flush the given bytes and report [`Event::Completed`].
+  Write(Vec<u8>),
+  /// Read exactly the given number of bytes and report [`Event::Received`].
+  /// If reading fails, the error should be returned.
+  Read(usize),
+  /// Read exactly the given number of bytes and report [`Event::Received`].
+  /// If reading fails, report [`Event::ReadFailed`] instead.
+  TryRead(usize),
+  /// The transaction is complete, with the data read from the device.
+  Done(Vec<u8>),
+  /// The transaction failed.
+  Fail(io::ErrorKind, &'static str),
+}
+
+/// An event reported by a KW2 driver to a [`Transaction`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+  /// A `Purge` or `Write` action completed at the given time.
+  Completed(Instant),
+  /// The bytes for a `Read` or `TryRead` action were received at the given time.
+  Received(Vec<u8>, Instant),
+  /// A `TryRead` action failed at the given time.
+  ReadFailed(Instant),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+  Sync,
+  Get,
+  Set,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+  Start,
+  Purging,
+  Resetting,
+  Syncing,
+  Synced,
+  Requesting,
+  Responding,
+  Retrying,
+  Done,
+}
+
+/// A sans-IO state machine for a single KW2 synchronization, read or write request.
+///
+/// The transaction is driven by calling [`Transaction::start`] and then
+/// repeatedly performing the returned [`Action`] and passing the resulting
+/// [`Event`] to [`Transaction::step`] until it returns `Done` or `Fail`.
+#[derive(Debug, Clone)]
+pub struct Transaction {
+  kind: Kind,
+  request: Vec<u8>,
+  response_len: usize,
+  timeout: Duration,
+  state: State,
+  start: Instant,
+  read_start: Instant,
+}
+
+impl Transaction {
+  /// Creates a transaction which only waits for synchronization.
+  pub fn sync(timeout: Duration, now: Instant) -> Transaction {
+    Transaction::new(Kind::Sync, Vec::new(), 0, timeout, now)
+  }
+
+  /// Creates a transaction reading `len` bytes at address `addr`.
+  ///
+  /// Fails if `len` does not fit into the single length byte of a request.
+  pub fn get(addr: &[u8], len: usize, timeout: Duration, now: Instant) -> Result<Transaction, Error> {
+    let mut request = Vec::new();
+    request.extend(&[0x01, 0xf7]);
+    request.extend(addr);
+    request.extend(&[Self::len_byte(len)?]);
+
+    Ok(Transaction::new(Kind::Get, request, len, timeout, now))
+  }
+
+  /// Creates a transaction writing `value` to address `addr`.
+  ///
+  /// Fails if the length of `value` does not fit into the single length byte of a request.
+  pub fn set(addr: &[u8], value: &[u8], timeout: Duration, now: Instant) -> Result<Transaction, Error> {
+    let mut request = Vec::new();
+    request.extend(&[0x01, 0xf4]);
+    request.extend(addr);
+    request.extend(&[Self::len_byte(value.len())?]);
+    request.extend(value);
+
+    Ok(Transaction::new(Kind::Set, request, 1, timeout, now))
+  }
+
+  fn len_byte(len: usize) -> Result<u8, Error> {
+    u8::try_from(len).map_err(|_| Error::InvalidArgument(format!("data length {} exceeds the maximum of {} bytes", len, u8::MAX)))
+  }
+
+  fn new(kind: Kind, request: Vec<u8>, response_len: usize, timeout: Duration, now: Instant) -> Transaction {
+    Transaction { kind, request, response_len, timeout, state: State::Start, start: now, read_start: now }
+  }
+
+  /// Returns the first action of this transaction.
+  ///
+  /// Fails if the transaction was already started.
+  pub fn start(&mut self) -> Action {
+    if self.state != State::Start {
+      return self.fail(io::ErrorKind::InvalidInput, "transaction already started")
+    }
+
+    // Reset the Optolink connection to get a faster SYNC (`0x05`).
+    self.state = State::Purging;
+    Action::Purge
+  }
+
+  /// Advances this transaction with the result of the previous action.
+  ///
+  /// Fails if `event` does not match the previous action.
+  pub fn step(&mut self, event: Event) -> Action {
+    log::trace!("Transaction::step({:?}) in {:?}", event, self.state);
+
+    match (self.state, event) {
+      (State::Purging, Event::Completed(_)) => {
+        self.state = State::Resetting;
+        Action::Write(vec![RESET])
+      },
+      (State::Resetting, Event::Completed(_)) => {
+        self.state = State::Syncing;
+        Action::TryRead(1)
+      },
+      (State::Syncing, Event::Received(ref buf, _)) if buf[..] == [SYNC] => {
+        self.state = State::Synced;
+        Action::Purge
+      },
+      (State::Syncing, Event::Received(_, now)) | (State::Syncing, Event::ReadFailed(now)) => {
+        if self.timed_out(now) {
+          return self.fail(io::ErrorKind::TimedOut, "sync timed out")
+        }
+
+        Action::TryRead(1)
+      },
+      (State::Synced, Event::Completed(_)) if self.kind == Kind::Sync => {
+        self.state = State::Done;
+        Action::Done(Vec::new())
+      },
+      (State::Synced, Event::Completed(_)) | (State::Retrying, Event::Completed(_)) => {
+        self.state = State::Requesting;
+        Action::Write(self.request.clone())
+      },
+      (State::Requesting, Event::Completed(now)) => {
+        self.read_start = now;
+        self.state = State::Responding;
+        Action::Read(self.response_len)
+      },
+      (State::Responding, Event::Received(buf, now)) => self.response(buf, now),
+      (state, event) => {
+        log::debug!("Transaction::step() unexpected event {:?} in state {:?}", event, state);
+        self.fail(io::ErrorKind::InvalidInput, "unexpected event")
+      },
+    }
+  }
+
+  fn response(&mut self, buf: Vec<u8>, now: Instant) -> Action {
+    match self.kind {
+      Kind::Get => {
+        if !Self::is_sync_response(&buf, now - self.read_start) {
+          self.state = State::Done;
+          return Action::Done(buf)
+        }
+
+        if self.timed_out(now) {
+          return self.fail(io::ErrorKind::TimedOut, "get timed out")
+        }
+
+        self.state = State::Retrying;
+        Action::Purge
+      },
+      Kind::Sync => self.fail(io::ErrorKind::InvalidInput, "unexpected response"),
+      Kind::Set => {
+        if buf[..] == [0x00] {
+          self.state = State::Done;
+          return Action::Done(Vec::new())
+        }
+
+        if self.timed_out(now) {
+          return self.fail(io::ErrorKind::TimedOut, "set timed out")
+        }
+
+        self.state = State::Requesting;
+        Action::Write(self.request.clone())
+      },
+    }
+  }
+
+  fn timed_out(&self, now: Instant) -> bool {
+    now - self.start > self.timeout
+  }
+
+  fn fail(&mut self, kind: io::ErrorKind, description: &'static str) -> Action {
+    self.state = State::Done;
+    Action::Fail(kind, description)
+  }
+
+  /// Checks whether a response which took `read_time` to be received
+  /// possibly contains synchronization bytes instead of actual data.
+  fn is_sync_response(buf: &[u8], read_time: Duration) -> bool {
+    // Retry if the response contains `SYNC` (`0x05`),
+    // since these could be synchronization bytes.
+    if buf.contains(&SYNC) {
+      log::debug!("Transaction::is_sync_response(…) buf = {}", buf.iter().map(|b| format!("{:02X}", b)).collect::<Vec<String>>().join(" "));
+      log::debug!("Transaction::is_sync_response(…) read_time = {:?}", read_time);
+
+      // They most likely are not synchronization bytes if the
+      // response was received in a short amount of time.
+      return read_time >= Duration::from_millis(500 * buf.len() as u64)
+    }
+
+    false
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const TIMEOUT: Duration = Duration::from_secs(60);
+
+  fn ms(t0: Instant, ms: u64) -> Instant {
+    t0 + Duration::from_millis(ms)
+  }
+
+  fn sync(transaction: &mut Transaction, t0: Instant) {
+    assert_eq!(transaction.start(), Action::Purge);
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 1))), Action::Write(vec![RESET]));
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 2))), Action::TryRead(1));
+    assert_eq!(transaction.step(Event::Received(vec![SYNC], ms(t0, 10))), Action::Purge);
+  }
+
+  #[test]
+  fn sync_only() {
+    let t0 = Instant::now();
+    let mut transaction = Transaction::sync(TIMEOUT, t0);
+
+    sync(&mut transaction, t0);
+
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 11))), Action::Done(vec![]));
+  }
+
+  #[test]
+  fn get() {
+    let t0 = Instant::now();
+    let mut transaction = Transaction::get(&[0x08, 0x02], 2, TIMEOUT, t0).unwrap();
+
+    sync(&mut transaction, t0);
+
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 11))), Action::Write(vec![0x01, 0xf7, 0x08, 0x02, 0x02]));
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 20))), Action::Read(2));
+    assert_eq!(transaction.step(Event::Received(vec![0xD7, 0x00], ms(t0, 1500))), Action::Done(vec![0xD7, 0x00]));
+  }
+
+  #[test]
+  fn too_long() {
+    let t0 = Instant::now();
+
+    assert!(Transaction::get(&[0x08, 0x02], 255, TIMEOUT, t0).is_ok());
+    assert!(matches!(Transaction::get(&[0x08, 0x02], 256, TIMEOUT, t0), Err(Error::InvalidArgument(_))));
+    assert!(matches!(Transaction::set(&[0x63, 0x00], &[0; 256], TIMEOUT, t0), Err(Error::InvalidArgument(_))));
+  }
+
+  #[test]
+  fn get_fast_sync_byte_is_data() {
+    let t0 = Instant::now();
+    let mut transaction = Transaction::get(&[0x63, 0x00], 1, TIMEOUT, t0).unwrap();
+
+    sync(&mut transaction, t0);
+
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 11))), Action::Write(vec![0x01, 0xf7, 0x63, 0x00, 0x01]));
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 20))), Action::Read(1));
+    assert_eq!(transaction.step(Event::Received(vec![SYNC], ms(t0, 519))), Action::Done(vec![SYNC]));
+  }
+
+  #[test]
+  fn get_slow_sync_byte_is_retried() {
+    let t0 = Instant::now();
+    let mut transaction = Transaction::get(&[0x08, 0x02], 2, TIMEOUT, t0).unwrap();
+
+    sync(&mut transaction, t0);
+
+    let request = vec![0x01, 0xf7, 0x08, 0x02, 0x02];
+
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 11))), Action::Write(request.clone()));
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 20))), Action::Read(2));
+    assert_eq!(transaction.step(Event::Received(vec![SYNC, 0x00], ms(t0, 1020))), Action::Purge);
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 1021))), Action::Write(request));
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 1030))), Action::Read(2));
+    assert_eq!(transaction.step(Event::Received(vec![0x05, 0x01], ms(t0, 1100))), Action::Done(vec![0x05, 0x01]));
+  }
+
+  #[test]
+  fn get_timeout() {
+    let t0 = Instant::now();
+    let mut transaction = Transaction::get(&[0x08, 0x02], 1, TIMEOUT, t0).unwrap();
+
+    sync(&mut transaction, t0);
+
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 11))), Action::Write(vec![0x01, 0xf7, 0x08, 0x02, 0x01]));
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 59_000))), Action::Read(1));
+    assert_eq!(transaction.step(Event::Received(vec![SYNC], ms(t0, 61_000))), Action::Fail(io::ErrorKind::TimedOut, "get timed out"));
+  }
+
+  #[test]
+  fn sync_retries_until_sync_byte() {
+    let t0 = Instant::now();
+    let mut transaction = Transaction::set(&[0x63, 0x00], &[0x30], TIMEOUT, t0).unwrap();
+
+    assert_eq!(transaction.start(), Action::Purge);
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 1))), Action::Write(vec![RESET]));
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 2))), Action::TryRead(1));
+    assert_eq!(transaction.step(Event::Received(vec![0xFF], ms(t0, 100))), Action::TryRead(1));
+    assert_eq!(transaction.step(Event::ReadFailed(ms(t0, 2000))), Action::TryRead(1));
+    assert_eq!(transaction.step(Event::Received(vec![SYNC], ms(t0, 2100))), Action::Purge);
+  }
+
+  #[test]
+  fn sync_timeout() {
+    let t0 = Instant::now();
+    let mut transaction = Transaction::get(&[0x08, 0x02], 2, TIMEOUT, t0).unwrap();
+
+    assert_eq!(transaction.start(), Action::Purge);
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 1))), Action::Write(vec![RESET]));
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 2))), Action::TryRead(1));
+    assert_eq!(transaction.step(Event::ReadFailed(ms(t0, 30_000))), Action::TryRead(1));
+    assert_eq!(transaction.step(Event::ReadFailed(ms(t0, 60_001))), Action::Fail(io::ErrorKind::TimedOut, "sync timed out"));
+  }
+
+  #[test]
+  fn set() {
+    let t0 = Instant::now();
+    let mut transaction = Transaction::set(&[0x63, 0x00], &[0x30], TIMEOUT, t0).unwrap();
+
+    sync(&mut transaction, t0);
+
+    let request = vec![0x01, 0xf4, 0x63, 0x00, 0x01, 0x30];
+
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 11))), Action::Write(request.clone()));
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 20))), Action::Read(1));
+    assert_eq!(transaction.step(Event::Received(vec![0x01], ms(t0, 100))), Action::Write(request));
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 110))), Action::Read(1));
+    assert_eq!(transaction.step(Event::Received(vec![0x00], ms(t0, 200))), Action::Done(vec![]));
+  }
+
+  #[test]
+  fn set_timeout() {
+    let t0 = Instant::now();
+    let mut transaction = Transaction::set(&[0x63, 0x00], &[0x30], TIMEOUT, t0).unwrap();
+
+    sync(&mut transaction, t0);
+
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 11))), Action::Write(vec![0x01, 0xf4, 0x63, 0x00, 0x01, 0x30]));
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 20))), Action::Read(1));
+    assert_eq!(transaction.step(Event::Received(vec![0x01], ms(t0, 60_100))), Action::Fail(io::ErrorKind::TimedOut, "set timed out"));
+  }
+
+  #[test]
+  fn unexpected_event() {
+    let t0 = Instant::now();
+    let mut transaction = Transaction::get(&[0x08, 0x02], 2, TIMEOUT, t0).unwrap();
+
+    transaction.start();
+    assert_eq!(transaction.step(Event::Received(vec![SYNC], t0)), Action::Fail(io::ErrorKind::InvalidInput, "unexpected event"));
+    assert_eq!(transaction.step(Event::Completed(t0)), Action::Fail(io::ErrorKind::InvalidInput, "unexpected event"));
+    assert_eq!(transaction.start(), Action::Fail(io::ErrorKind::InvalidInput, "transaction already started"));
+  }
+}
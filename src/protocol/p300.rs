@@ -1,3 +1,4 @@
+use std::convert::TryFrom;
 use std::io::{self, Read, Write};
 use std::time::Instant;
 
@@ -5,98 +6,47 @@ use crate::Optolink;
 
 use super::Protocol;
 
-const LEADIN: u8    = 0x41;
+mod codec;
+pub use self::codec::*;
 
-const RESET: u8     = 0x04;
-const SYNC: u8      = 0x05;
-const ACK: u8       = 0x06;
-const NACK: u8      = 0x15;
-
-const REQUEST: u8   = 0x00;
-const RESPONSE: u8  = 0x01;
-
-const READDATA: u8  = 0x01;
-const WRITEDATA: u8 = 0x02;
+mod machine;
+pub use self::machine::{Action, Event, Transaction};
 
 #[derive(Debug)]
 pub struct P300;
 
 impl P300 {
-  fn write_telegram(o: &mut Optolink, message: &[u8]) -> Result<(), std::io::Error> {
-    log::trace!("P300::write_telegram(…)");
-
-    let message_length = message.len() as u8;
-    let checksum: u8 = message.iter().sum();
-    let checksum = checksum + message_length;
-
-    let start = Instant::now();
-
-    loop {
-      o.write_all(&[LEADIN])?;
-      o.write_all(&[message_length])?;
-      o.write_all(&message)?;
-      o.write_all(&[checksum])?;
-      o.flush()?;
-
-      let mut status = [0xff];
-      o.read_exact(&mut status)?;
-      match status {
-        [ACK] => return Ok(()),
-        [NACK] => {}
-        [_] => return Err(io::Error::new(io::ErrorKind::InvalidData, "send telegram failed")),
-      }
-
-      let stop = Instant::now();
-
-      if (stop - start) > Optolink::TIMEOUT {
-        break;
-      }
-    }
-
-    Err(io::Error::new(io::ErrorKind::TimedOut, "send telegram timed out"))
+  fn addr(addr: &[u8]) -> Result<u16, io::Error> {
+    <[u8; 2]>::try_from(addr).map(u16::from_be_bytes).map_err(|_| {
+      io::Error::new(io::ErrorKind::InvalidInput, format!("expected a 2-byte address, found {} byte(s)", addr.len()))
+    })
   }
 
-  fn read_telegram(o: &mut Optolink) -> Result<Vec<u8>, std::io::Error> {
-    log::trace!("P300::read_telegram(…)");
-
-    let mut buf = [0xff];
+  fn invalid_input(err: TelegramError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, err)
+  }
 
-    let start = Instant::now();
+  fn run(o: &mut Optolink, mut transaction: Transaction) -> Result<Vec<u8>, io::Error> {
+    let mut action = transaction.start();
 
     loop {
-      o.read_exact(&mut buf)?;
-      if buf != [LEADIN] {
-        return Err(io::Error::new(io::ErrorKind::InvalidData, "telegram leadin expected"))
-      }
-
-      o.read_exact(&mut buf)?;
-      let message_length: usize = buf[0] as usize;
-
-      let mut message = vec![0; message_length];
-      o.read_exact(&mut message)?;
-      let message = message;
-
-      let checksum: u8 = message.iter().sum();
-      let checksum = checksum + message_length as u8;
-
-      o.read_exact(&mut buf)?;
-      if checksum == buf[0] {
-        o.write_all(&[ACK])?;
-        o.flush()?;
-        return Ok(message)
-      }
-
-      o.write_all(&[NACK])?;
-      o.flush()?;
-
-      let stop = Instant::now();
-
-      if (stop - start) > Optolink::TIMEOUT {
-        break;
-      }
+      let event = match action {
+        Action::Write(bytes) => {
+          o.write_all(&bytes)?;
+          o.flush()?;
+          Event::Completed(Instant::now())
+        },
+        Action::Read(len) => {
+          let mut buf = vec![0; len];
+          o.read_exact(&mut buf)?;
+          Event::Received(buf, Instant::now())
+        },
+        Action::Done(buf) => return Ok(buf),
+        Action::Fail(kind, description) => return Err(io::Error::new(kind, description)),
+      };
+
+      action = transaction.step(event);
     }
-
-    Err(io::Error::new(io::ErrorKind::TimedOut, "send telegram timed out"))
   }
 }
 
@@ -104,98 +54,104 @@ impl Protocol for P300 {
   fn negotiate(o: &mut Optolink) -> Result<(), io::Error> {
     log::trace!("P300::negotiate(…)");
 
-    o.write_all(&[RESET])?;
-    o.flush()?;
+    Self::run(o, Transaction::negotiate(o.timeout(), Instant::now()))?;
 
-    let mut status = [0xff];
+    Ok(())
+  }
 
-    let start = Instant::now();
+  fn get(o: &mut Optolink, addr: &[u8], buf: &mut [u8]) -> Result<(), io::Error> {
+    log::trace!("P300::get(…)");
 
-    loop {
-      let stop = Instant::now();
+    let request = Telegram::read_request(Self::addr(addr)?, buf.len()).map_err(Self::invalid_input)?;
+    buf.copy_from_slice(&Self::run(o, Transaction::request(request, o.timeout(), Instant::now()))?);
 
-      if (stop - start) > Optolink::TIMEOUT {
-        break;
-      }
+    Ok(())
+  }
 
-      o.read_exact(&mut status)?;
-      match status {
-        [SYNC] => {},
-        _ => continue,
-      }
+  fn set(o: &mut Optolink, addr: &[u8], value: &[u8]) -> Result<(), io::Error> {
+    log::trace!("P300::set(…)");
+
+    let request = Telegram::write_request(Self::addr(addr)?, value).map_err(Self::invalid_input)?;
+    Self::run(o, Transaction::request(request, o.timeout(), Instant::now()))?;
 
-      o.write_all(&[0x16, 0x00, 0x00])?;
-      o.flush()?;
+    Ok(())
+  }
+}
 
-      o.read_exact(&mut status)?;
-      match status {
-        [ACK] => return Ok(()),
-        [NACK] => {}
-        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "protocol negotiation failed")),
+#[cfg(feature = "async")]
+mod r#async {
+  use super::*;
+
+  use async_trait::async_trait;
+
+  use crate::{AsyncOptolink, AsyncProtocol};
+
+  impl P300 {
+    async fn run_async(o: &mut AsyncOptolink, mut transaction: Transaction) -> Result<Vec<u8>, io::Error> {
+      let mut action = transaction.start();
+
+      loop {
+        let event = match action {
+          Action::Write(bytes) => {
+            o.write_all(&bytes).await?;
+            o.flush().await?;
+            Event::Completed(Instant::now())
+          },
+          Action::Read(len) => {
+            let mut buf = vec![0; len];
+            o.read_exact(&mut buf).await?;
+            Event::Received(buf, Instant::now())
+          },
+          Action::Done(buf) => return Ok(buf),
+          Action::Fail(kind, description) => return Err(io::Error::new(kind, description)),
+        };
+
+        action = transaction.step(event);
       }
     }
-
-    Err(io::Error::new(io::ErrorKind::TimedOut, "negotiate timed out"))
   }
 
-  fn get(o: &mut Optolink, addr: &[u8], buf: &mut [u8]) -> Result<(), io::Error> {
-    log::trace!("P300::get(…)");
-
-    let mut read_request = Vec::new();
-    read_request.extend(&[REQUEST, READDATA]);
-    read_request.extend(addr);
-    read_request.extend(&[buf.len() as u8]);
-
-    Self::write_telegram(o, &read_request)?;
+  #[async_trait]
+  impl AsyncProtocol for P300 {
+    async fn negotiate(o: &mut AsyncOptolink) -> Result<(), io::Error> {
+      log::trace!("P300::negotiate(…)");
 
-    let read_response = Self::read_telegram(o)?;
+      Self::run_async(o, Transaction::negotiate(o.timeout(), Instant::now())).await?;
 
-    let expected_len: usize = 5 + buf.len();
-    if expected_len != read_response.len() {
-      return Err(io::Error::new(io::ErrorKind::InvalidData, "unexpected response length"))
-    }
-    if read_response[0..2] != [RESPONSE, READDATA] {
-      return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid read data response"))
-    }
-    if read_response[2..4] != *addr {
-      return Err(io::Error::new(io::ErrorKind::InvalidData, "wrong address"))
+      Ok(())
     }
-    if read_response[4] != buf.len() as u8 {
-      return Err(io::Error::new(io::ErrorKind::InvalidData, "wrong data length"))
-    }
-
-    buf.clone_from_slice(&read_response[5..(5 + buf.len())]);
 
-    Ok(())
-  }
+    async fn get(o: &mut AsyncOptolink, addr: &[u8], buf: &mut [u8]) -> Result<(), io::Error> {
+      log::trace!("P300::get(…)");
 
-  fn set(o: &mut Optolink, addr: &[u8], value: &[u8]) -> Result<(), io::Error> {
-    log::trace!("P300::set(…)");
+      let request = Telegram::read_request(Self::addr(addr)?, buf.len()).map_err(Self::invalid_input)?;
+      buf.copy_from_slice(&Self::run_async(o, Transaction::request(request, o.timeout(), Instant::now())).await?);
 
-    let mut write_request = Vec::new();
-    write_request.extend(&[REQUEST, WRITEDATA]);
-    write_request.extend(addr);
-    write_request.extend(&[value.len() as u8]);
-    write_request.extend(value);
+      Ok(())
+    }
 
-    Self::write_telegram(o, &write_request)?;
+    async fn set(o: &mut AsyncOptolink, addr: &[u8], value: &[u8]) -> Result<(), io::Error> {
+      log::trace!("P300::set(…)");
 
-    let write_response = Self::read_telegram(o)?;
+      let request = Telegram::write_request(Self::addr(addr)?, value).map_err(Self::invalid_input)?;
+      Self::run_async(o, Transaction::request(request, o.timeout(), Instant::now())).await?;
 
-    let expected_len: usize = 5;
-    if expected_len != write_response.len() {
-      return Err(io::Error::new(io::ErrorKind::InvalidData, "unexpected response length"))
-    }
-    if write_response[0..2] != [RESPONSE, WRITEDATA] {
-      return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid write data response"))
-    }
-    if write_response[2..4] != *addr {
-      return Err(io::Error::new(io::ErrorKind::InvalidData, "wrong address"))
-    }
-    if write_response[4] != value.len() as u8 {
-      return Err(io::Error::new(io::ErrorKind::InvalidData, "could not write data"))
+      Ok(())
     }
+  }
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn addr() {
+    assert_eq!(P300::addr(&[0x08, 0x02]).unwrap(), 0x0802);
+
+    let err = P300::addr(&[0x08]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(err.to_string(), "expected a 2-byte address, found 1 byte(s)");
+    assert!(P300::addr(&[0x00, 0x08, 0x02]).is_err());
   }
 }
@@ -0,0 +1,461 @@
+use std::error;
+use std::fmt;
+
+pub(crate) const LEADIN: u8 = 0x41;
+
+pub(crate) const RESET: u8  = 0x04;
+pub(crate) const SYNC: u8   = 0x05;
+pub(crate) const ACK: u8    = 0x06;
+pub(crate) const NACK: u8   = 0x15;
+
+pub(crate) const START: [u8; 3] = [0x16, 0x00, 0x00];
+
+/// The maximum number of data bytes a single telegram can contain.
+pub const MAX_DATA_LEN: usize = u8::MAX as usize - 5;
+
+/// The type of a P300 telegram.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+  Request,
+  Response,
+  Unacknowledged,
+  Error,
+}
+
+impl MessageType {
+  fn from_byte(byte: u8) -> Option<MessageType> {
+    Some(match byte {
+      0x00 => MessageType::Request,
+      0x01 => MessageType::Response,
+      0x02 => MessageType::Unacknowledged,
+      0x03 => MessageType::Error,
+      _ => return None,
+    })
+  }
+
+  fn to_byte(self) -> u8 {
+    match self {
+      MessageType::Request => 0x00,
+      MessageType::Response => 0x01,
+      MessageType::Unacknowledged => 0x02,
+      MessageType::Error => 0x03,
+    }
+  }
+}
+
+/// The function of a P300 telegram.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+  ReadData,
+  WriteData,
+  FunctionCall,
+}
+
+impl Function {
+  fn from_byte(byte: u8) -> Option<Function> {
+    Some(match byte {
+      0x01 => Function::ReadData,
+      0x02 => Function::WriteData,
+      0x07 => Function::FunctionCall,
+      _ => return None,
+    })
+  }
+
+  fn to_byte(self) -> u8 {
+    match self {
+      Function::ReadData => 0x01,
+      Function::WriteData => 0x02,
+      Function::FunctionCall => 0x07,
+    }
+  }
+}
+
+/// A P300 telegram.
+///
+/// The data length of a telegram is the number of data bytes it refers to,
+/// which is either the length of its data or, if the data is empty, e.g. for
+/// read requests or write responses, the number of bytes requested or written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Telegram {
+  message_type: MessageType,
+  function: Function,
+  addr: u16,
+  len: u8,
+  data: Vec<u8>,
+}
+
+impl Telegram {
+  /// Creates a new telegram.
+  ///
+  /// Fails if `data` is longer than [`MAX_DATA_LEN`], if `len` does not fit into
+  /// a single byte or if `data` is not empty and its length is not `len`.
+  pub fn new(message_type: MessageType, function: Function, addr: u16, len: usize, data: Vec<u8>) -> Result<Telegram, TelegramError> {
+    if data.len() > MAX_DATA_LEN {
+      return Err(TelegramError::DataTooLong(data.len()))
+    }
+    if len > u8::MAX as usize {
+      return Err(TelegramError::DataTooLong(len))
+    }
+    if !data.is_empty() && data.len() != len {
+      return Err(TelegramError::DataLength { expected: len, actual: data.len() })
+    }
+
+    Ok(Telegram { message_type, function, addr, len: len as u8, data })
+  }
+
+  /// Creates a request to read `len` bytes at address `addr`.
+  ///
+  /// Fails if `len` is greater than [`MAX_DATA_LEN`], since the response could not contain the data.
+  pub fn read_request(addr: u16, len: usize) -> Result<Telegram, TelegramError> {
+    if len > MAX_DATA_LEN {
+      return Err(TelegramError::DataTooLong(len))
+    }
+
+    Telegram::new(MessageType::Request, Function::ReadData, addr, len, Vec::new())
+  }
+
+  /// Creates a response to a read request containing `data`.
+  pub fn read_response(addr: u16, data: &[u8]) -> Result<Telegram, TelegramError> {
+    Telegram::new(MessageType::Response, Function::ReadData, addr, data.len(), data.to_vec())
+  }
+
+  /// Creates a request to write `data` at address `addr`.
+  pub fn write_request(addr: u16, data: &[u8]) -> Result<Telegram, TelegramError> {
+    Telegram::new(MessageType::Request, Function::WriteData, addr, data.len(), data.to_vec())
+  }
+
+  /// Creates a response to a write request of `len` bytes.
+  pub fn write_response(addr: u16, len: usize) -> Result<Telegram, TelegramError> {
+    Telegram::new(MessageType::Response, Function::WriteData, addr, len, Vec::new())
+  }
+
+  /// Creates a request to call the function at address `addr` with `args`.
+  pub fn function_call(addr: u16, args: &[u8]) -> Result<Telegram, TelegramError> {
+    Telegram::new(MessageType::Request, Function::FunctionCall, addr, args.len(), args.to_vec())
+  }
+
+  pub fn message_type(&self) -> MessageType {
+    self.message_type
+  }
+
+  pub fn function(&self) -> Function {
+    self.function
+  }
+
+  pub fn addr(&self) -> u16 {
+    self.addr
+  }
+
+  pub fn data_len(&self) -> u8 {
+    self.len
+  }
+
+  pub fn data(&self) -> &[u8] {
+    &self.data
+  }
+
+  /// Returns the message contained in this telegram, i.e. without leadin, length and checksum.
+  fn message(&self) -> Vec<u8> {
+    let mut message = Vec::with_capacity(5 + self.data.len());
+    message.push(self.message_type.to_byte());
+    message.push(self.function.to_byte());
+    message.extend(&self.addr.to_be_bytes());
+    message.push(self.len);
+    message.extend(&self.data);
+    message
+  }
+
+  /// Encodes this telegram including leadin, length and checksum.
+  pub fn encode(&self) -> Vec<u8> {
+    let message = self.message();
+
+    let mut telegram = Vec::with_capacity(message.len() + 3);
+    telegram.push(LEADIN);
+    telegram.push(message.len() as u8);
+    telegram.extend(&message);
+    telegram.push(checksum(&message));
+    telegram
+  }
+}
+
+/// An error which occurred while creating a telegram.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TelegramError {
+  DataTooLong(usize),
+  DataLength { expected: usize, actual: usize },
+}
+
+impl fmt::Display for TelegramError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      TelegramError::DataTooLong(len) => write!(f, "data length {} exceeds the maximum of {} bytes", len, MAX_DATA_LEN),
+      TelegramError::DataLength { expected, actual } => write!(f, "data length mismatch, expected {} bytes, found {}", expected, actual),
+    }
+  }
+}
+
+impl error::Error for TelegramError {}
+
+/// Calculates the checksum of a telegram message.
+pub fn checksum(message: &[u8]) -> u8 {
+  message.iter().fold(message.len() as u8, |acc, byte| acc.wrapping_add(*byte))
+}
+
+/// A frame sent over a P300 connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+  Reset,
+  Sync,
+  Start,
+  Ack,
+  Nack,
+  Telegram(Telegram),
+}
+
+impl Frame {
+  pub fn encode(&self) -> Vec<u8> {
+    match self {
+      Frame::Reset => vec![RESET],
+      Frame::Sync => vec![SYNC],
+      Frame::Start => START.to_vec(),
+      Frame::Ack => vec![ACK],
+      Frame::Nack => vec![NACK],
+      Frame::Telegram(telegram) => telegram.encode(),
+    }
+  }
+}
+
+/// An error which occurred while decoding a frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+  UnexpectedByte(u8),
+  Checksum { expected: u8, actual: u8 },
+  MessageLength(usize),
+  MessageType(u8),
+  Function(u8),
+  DataLength { expected: usize, actual: usize },
+}
+
+impl fmt::Display for DecodeError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      DecodeError::UnexpectedByte(byte) => write!(f, "unexpected byte 0x{:02X}", byte),
+      DecodeError::Checksum { expected, actual } => write!(f, "checksum mismatch, expected 0x{:02X}, found 0x{:02X}", expected, actual),
+      DecodeError::MessageLength(len) => write!(f, "message length {} is too short", len),
+      DecodeError::MessageType(byte) => write!(f, "unknown message type 0x{:02X}", byte),
+      DecodeError::Function(byte) => write!(f, "unknown function 0x{:02X}", byte),
+      DecodeError::DataLength { expected, actual } => write!(f, "data length mismatch, expected {} bytes, found {}", expected, actual),
+    }
+  }
+}
+
+impl error::Error for DecodeError {}
+
+/// The result of decoding a frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decoded {
+  /// At least the given number of additional bytes is needed to decode a frame.
+  Incomplete(usize),
+  /// A frame, or an error for an invalid frame, and the number of bytes it spans.
+  Complete(Result<Frame, DecodeError>, usize),
+}
+
+/// Decodes a single frame from the start of `buf`.
+pub fn decode(buf: &[u8]) -> Decoded {
+  let first = match buf.first() {
+    Some(&byte) => byte,
+    None => return Decoded::Incomplete(1),
+  };
+
+  match first {
+    RESET => Decoded::Complete(Ok(Frame::Reset), 1),
+    SYNC => Decoded::Complete(Ok(Frame::Sync), 1),
+    ACK => Decoded::Complete(Ok(Frame::Ack), 1),
+    NACK => Decoded::Complete(Ok(Frame::Nack), 1),
+    byte if byte == START[0] => {
+      if buf.len() < START.len() {
+        return Decoded::Incomplete(START.len() - buf.len())
+      }
+
+      match buf[1..START.len()].iter().find(|&&byte| byte != 0x00) {
+        Some(&byte) => Decoded::Complete(Err(DecodeError::UnexpectedByte(byte)), START.len()),
+        None => Decoded::Complete(Ok(Frame::Start), START.len()),
+      }
+    },
+    LEADIN => {
+      let message_length = match buf.get(1) {
+        Some(&len) => len as usize,
+        None => return Decoded::Incomplete(1),
+      };
+
+      let frame_length = message_length + 3;
+
+      if buf.len() < frame_length {
+        return Decoded::Incomplete(frame_length - buf.len())
+      }
+
+      let message = &buf[2..(2 + message_length)];
+
+      Decoded::Complete(decode_message(message, buf[2 + message_length]).map(Frame::Telegram), frame_length)
+    },
+    byte => Decoded::Complete(Err(DecodeError::UnexpectedByte(byte)), 1),
+  }
+}
+
+fn decode_message(message: &[u8], actual_checksum: u8) -> Result<Telegram, DecodeError> {
+  let expected_checksum = checksum(message);
+
+  if expected_checksum != actual_checksum {
+    return Err(DecodeError::Checksum { expected: expected_checksum, actual: actual_checksum })
+  }
+
+  if message.len() < 5 {
+    return Err(DecodeError::MessageLength(message.len()))
+  }
+
+  let message_type = MessageType::from_byte(message[0]).ok_or(DecodeError::MessageType(message[0]))?;
+  let function = Function::from_byte(message[1]).ok_or(DecodeError::Function(message[1]))?;
+  let addr = u16::from_be_bytes([message[2], message[3]]);
+  let len = message[4];
+  let data = message[5..].to_vec();
+
+  if !data.is_empty() && data.len() != len as usize {
+    return Err(DecodeError::DataLength { expected: len as usize, actual: data.len() })
+  }
+
+  Ok(Telegram { message_type, function, addr, len, data })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const MESSAGE_TYPES: [MessageType; 4] = [MessageType::Request, MessageType::Response, MessageType::Unacknowledged, MessageType::Error];
+  const FUNCTIONS: [Function; 3] = [Function::ReadData, Function::WriteData, Function::FunctionCall];
+
+  fn assert_round_trip(frame: Frame) {
+    let bytes = frame.encode();
+    assert_eq!(decode(&bytes), Decoded::Complete(Ok(frame.clone()), bytes.len()), "{:?}", frame);
+
+    for i in 0..bytes.len() {
+      match decode(&bytes[..i]) {
+        Decoded::Incomplete(missing) => assert!(missing > 0 && i + missing <= bytes.len()),
+        decoded => panic!("expected incomplete frame for {:?}, found {:?}", &bytes[..i], decoded),
+      }
+    }
+  }
+
+  #[test]
+  fn round_trip_control_frames() {
+    for frame in [Frame::Reset, Frame::Sync, Frame::Start, Frame::Ack, Frame::Nack] {
+      assert_round_trip(frame);
+    }
+  }
+
+  #[test]
+  fn round_trip_telegrams() {
+    for &message_type in &MESSAGE_TYPES {
+      for &function in &FUNCTIONS {
+        for &addr in &[0x0000, 0x00F8, 0x0802, 0x7FFF, 0xFFFF] {
+          for len in 0..=MAX_DATA_LEN {
+            assert_round_trip(Frame::Telegram(Telegram::new(message_type, function, addr, len, Vec::new()).unwrap()));
+
+            let data = (0..len).map(|i| (i as u8).wrapping_mul(37).wrapping_add(addr as u8)).collect();
+            assert_round_trip(Frame::Telegram(Telegram::new(message_type, function, addr, len, data).unwrap()));
+          }
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn round_trip_data_bytes() {
+    for byte in 0..=u8::MAX {
+      assert_round_trip(Frame::Telegram(Telegram::read_response(0x0802, &[byte]).unwrap()));
+      assert_round_trip(Frame::Telegram(Telegram::write_request(0x6300, &[byte, !byte]).unwrap()));
+    }
+  }
+
+  #[test]
+  fn encode_read_request() {
+    assert_eq!(Telegram::read_request(0x0802, 2).unwrap().encode(), [0x41, 0x05, 0x00, 0x01, 0x08, 0x02, 0x02, 0x12]);
+  }
+
+  #[test]
+  fn encode_write_request() {
+    assert_eq!(Telegram::write_request(0x6300, &[0x30]).unwrap().encode(), [0x41, 0x06, 0x00, 0x02, 0x63, 0x00, 0x01, 0x30, 0x9C]);
+  }
+
+  #[test]
+  fn encode_function_call() {
+    assert_eq!(Telegram::function_call(0x1234, &[]).unwrap().encode(), [0x41, 0x05, 0x00, 0x07, 0x12, 0x34, 0x00, 0x52]);
+  }
+
+  #[test]
+  fn invalid_telegrams() {
+    assert_eq!(Telegram::read_request(0x0802, MAX_DATA_LEN + 1), Err(TelegramError::DataTooLong(MAX_DATA_LEN + 1)));
+    assert_eq!(Telegram::read_request(0x0802, 256), Err(TelegramError::DataTooLong(256)));
+    assert_eq!(Telegram::write_request(0x6300, &[0; MAX_DATA_LEN + 1]), Err(TelegramError::DataTooLong(MAX_DATA_LEN + 1)));
+    assert_eq!(Telegram::write_response(0x6300, 256), Err(TelegramError::DataTooLong(256)));
+    assert_eq!(
+      Telegram::new(MessageType::Request, Function::WriteData, 0x6300, 2, vec![0x30]),
+      Err(TelegramError::DataLength { expected: 2, actual: 1 }),
+    );
+  }
+
+  #[test]
+  fn decode_wrapping_checksum() {
+    let telegram = Telegram::read_response(0xFFFF, &[0xFF, 0xFF, 0xFF]).unwrap();
+    let bytes = telegram.encode();
+    assert_eq!(bytes[bytes.len() - 1], checksum(&bytes[2..(bytes.len() - 1)]));
+    assert_eq!(decode(&bytes), Decoded::Complete(Ok(Frame::Telegram(telegram)), bytes.len()));
+  }
+
+  #[test]
+  fn decode_checksum_mismatch() {
+    let mut bytes = Telegram::read_request(0x0802, 2).unwrap().encode();
+    *bytes.last_mut().unwrap() ^= 0xFF;
+
+    assert_eq!(decode(&bytes), Decoded::Complete(Err(DecodeError::Checksum { expected: 0x12, actual: 0xED }), bytes.len()));
+  }
+
+  #[test]
+  fn decode_invalid_message() {
+    assert_eq!(decode(&[0x41, 0x00, 0x00]), Decoded::Complete(Err(DecodeError::MessageLength(0)), 3));
+    assert_eq!(decode(&[0x41, 0x02, 0x00, 0x01, 0x03]), Decoded::Complete(Err(DecodeError::MessageLength(2)), 5));
+
+    let message = [0x04, 0x01, 0x08, 0x02, 0x02];
+    let mut bytes = vec![0x41, 0x05];
+    bytes.extend(&message);
+    bytes.push(checksum(&message));
+    assert_eq!(decode(&bytes), Decoded::Complete(Err(DecodeError::MessageType(0x04)), bytes.len()));
+
+    let message = [0x00, 0x03, 0x08, 0x02, 0x02];
+    let mut bytes = vec![0x41, 0x05];
+    bytes.extend(&message);
+    bytes.push(checksum(&message));
+    assert_eq!(decode(&bytes), Decoded::Complete(Err(DecodeError::Function(0x03)), bytes.len()));
+
+    let message = [0x01, 0x01, 0x08, 0x02, 0x02, 0xFF];
+    let mut bytes = vec![0x41, 0x06];
+    bytes.extend(&message);
+    bytes.push(checksum(&message));
+    assert_eq!(decode(&bytes), Decoded::Complete(Err(DecodeError::DataLength { expected: 2, actual: 1 }), bytes.len()));
+  }
+
+  #[test]
+  fn decode_unexpected_byte() {
+    assert_eq!(decode(&[0x16, 0x00, 0x01]), Decoded::Complete(Err(DecodeError::UnexpectedByte(0x01)), 3));
+    assert_eq!(decode(&[0xFF]), Decoded::Complete(Err(DecodeError::UnexpectedByte(0xFF)), 1));
+  }
+
+  #[test]
+  fn decode_trailing_bytes() {
+    let mut bytes = Telegram::write_response(0x6300, 1).unwrap().encode();
+    let len = bytes.len();
+    bytes.push(ACK);
+
+    assert_eq!(decode(&bytes), Decoded::Complete(Ok(Frame::Telegram(Telegram::write_response(0x6300, 1).unwrap())), len));
+    assert_eq!(decode(&bytes[len..]), Decoded::Complete(Ok(Frame::Ack), 1));
+  }
+}
@@ -0,0 +1,328 @@
+use std::io;
+use std::time::{Duration, Instant};
+
+use super::codec::*;
+
+/// An action a P300 driver has to perform for a [`Transaction`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+  /// Write and flush the given bytes and report [`Event::Completed`].
+  Write(Vec<u8>),
+  /// Read exactly the given number of bytes and report [`Event::Received`].
+  /// If reading fails, the error should be returned.
+  Read(usize),
+  /// The transaction is complete, with the data contained in the response.
+  Done(Vec<u8>),
+  /// The transaction failed.
+  Fail(io::ErrorKind, String),
+}
+
+/// An event reported by a P300 driver to a [`Transaction`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+  /// A `Write` action completed at the given time.
+  Completed(Instant),
+  /// The bytes for a `Read` action were received at the given time.
+  Received(Vec<u8>, Instant),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum State {
+  Start,
+  Resetting,
+  Syncing,
+  Starting,
+  Negotiating,
+  Requesting,
+  Sending,
+  Receiving(Vec<u8>),
+  Acknowledging(Result<Vec<u8>, String>),
+  Rejecting,
+  Done,
+}
+
+/// A sans-IO state machine for a P300 protocol negotiation or a single request.
+///
+/// The transaction is driven by calling [`Transaction::start`] and then
+/// repeatedly performing the returned [`Action`] and passing the resulting
+/// [`Event`] to [`Transaction::step`] until it returns `Done` or `Fail`.
+#[derive(Debug, Clone)]
+pub struct Transaction {
+  request: Option<Telegram>,
+  timeout: Duration,
+  state: State,
+  start: Instant,
+}
+
+impl Transaction {
+  /// Creates a transaction which negotiates the P300 protocol.
+  pub fn negotiate(timeout: Duration, now: Instant) -> Transaction {
+    Transaction { request: None, timeout, state: State::Start, start: now }
+  }
+
+  /// Creates a transaction which sends `request` and receives the response.
+  pub fn request(request: Telegram, timeout: Duration, now: Instant) -> Transaction {
+    Transaction { request: Some(request), timeout, state: State::Start, start: now }
+  }
+
+  /// Returns the first action of this transaction.
+  ///
+  /// Fails if the transaction was already started.
+  pub fn start(&mut self) -> Action {
+    if self.state != State::Start {
+      return self.fail(io::ErrorKind::InvalidInput, "transaction already started".into())
+    }
+
+    match &self.request {
+      Some(request) => {
+        self.state = State::Requesting;
+        Action::Write(request.encode())
+      },
+      None => {
+        self.state = State::Resetting;
+        Action::Write(Frame::Reset.encode())
+      },
+    }
+  }
+
+  /// Advances this transaction with the result of the previous action.
+  ///
+  /// Fails if `event` does not match the previous action.
+  pub fn step(&mut self, event: Event) -> Action {
+    log::trace!("Transaction::step({:?}) in {:?}", event, self.state);
+
+    match (std::mem::replace(&mut self.state, State::Done), event) {
+      (State::Resetting, Event::Completed(_)) => {
+        self.state = State::Syncing;
+        Action::Read(1)
+      },
+      (State::Starting, Event::Completed(_)) => {
+        self.state = State::Negotiating;
+        Action::Read(1)
+      },
+      (State::Syncing, Event::Received(buf, now)) => {
+        if buf[..] == [SYNC] {
+          self.state = State::Starting;
+          return Action::Write(Frame::Start.encode())
+        }
+
+        self.retry(State::Syncing, now, "negotiate timed out", Action::Read(1))
+      },
+      (State::Negotiating, Event::Received(buf, now)) => match buf[..] {
+        [ACK] => Action::Done(Vec::new()),
+        [NACK] => self.retry(State::Syncing, now, "negotiate timed out", Action::Read(1)),
+        _ => self.fail(io::ErrorKind::InvalidData, "protocol negotiation failed".into()),
+      },
+      (State::Requesting, Event::Completed(_)) => {
+        self.state = State::Sending;
+        Action::Read(1)
+      },
+      (State::Sending, Event::Received(buf, now)) => match decode(&buf) {
+        Decoded::Complete(Ok(Frame::Ack), _) => {
+          self.start = now;
+          self.receive(Vec::new())
+        },
+        Decoded::Complete(Ok(Frame::Nack), _) => {
+          let request = self.request.as_ref().map(Telegram::encode).unwrap_or_default();
+          self.retry(State::Requesting, now, "send telegram timed out", Action::Write(request))
+        },
+        _ => self.fail(io::ErrorKind::InvalidData, "send telegram failed".into()),
+      },
+      (State::Receiving(mut buf), Event::Received(bytes, _)) => {
+        buf.extend(bytes);
+        self.receive(buf)
+      },
+      (State::Acknowledging(response), Event::Completed(_)) => match response {
+        Ok(data) => Action::Done(data),
+        Err(description) => self.fail(io::ErrorKind::InvalidData, description),
+      },
+      (State::Rejecting, Event::Completed(now)) => {
+        self.retry(State::Receiving(Vec::new()), now, "receive telegram timed out", Action::Read(1))
+      },
+      (state, event) => self.fail(io::ErrorKind::InvalidInput, format!("unexpected event {:?} in state {:?}", event, state)),
+    }
+  }
+
+  fn receive(&mut self, buf: Vec<u8>) -> Action {
+    match decode(&buf) {
+      Decoded::Incomplete(missing) => {
+        self.state = State::Receiving(buf);
+        Action::Read(missing)
+      },
+      Decoded::Complete(Ok(Frame::Telegram(response)), _) => {
+        self.state = State::Acknowledging(self.response(&response));
+        Action::Write(Frame::Ack.encode())
+      },
+      Decoded::Complete(Err(DecodeError::Checksum { .. }), _) => {
+        self.state = State::Rejecting;
+        Action::Write(Frame::Nack.encode())
+      },
+      Decoded::Complete(Ok(_), _) | Decoded::Complete(Err(DecodeError::UnexpectedByte(_)), _) => {
+        self.fail(io::ErrorKind::InvalidData, "telegram leadin expected".into())
+      },
+      Decoded::Complete(Err(err), _) => self.fail(io::ErrorKind::InvalidData, err.to_string()),
+    }
+  }
+
+  /// Checks that `response` answers the request and returns the data it contains.
+  fn response(&self, response: &Telegram) -> Result<Vec<u8>, String> {
+    let request = match &self.request {
+      Some(request) => request,
+      None => return Err("unexpected telegram".into()),
+    };
+
+    if response.message_type() != MessageType::Response || response.function() != request.function() {
+      return Err(match request.function() {
+        Function::ReadData => "invalid read data response",
+        Function::WriteData => "invalid write data response",
+        Function::FunctionCall => "invalid function call response",
+      }.into())
+    }
+    if response.addr() != request.addr() {
+      return Err("wrong address".into())
+    }
+
+    match request.function() {
+      Function::ReadData if response.data_len() != request.data_len() || response.data().len() != request.data_len() as usize => {
+        Err("wrong data length".into())
+      },
+      Function::WriteData if response.data_len() != request.data_len() || !response.data().is_empty() => {
+        Err("could not write data".into())
+      },
+      _ => Ok(response.data().to_vec()),
+    }
+  }
+
+  fn retry(&mut self, state: State, now: Instant, description: &str, action: Action) -> Action {
+    if now - self.start > self.timeout {
+      return self.fail(io::ErrorKind::TimedOut, description.into())
+    }
+
+    self.state = state;
+    action
+  }
+
+  fn fail(&mut self, kind: io::ErrorKind, description: String) -> Action {
+    self.state = State::Done;
+    Action::Fail(kind, description)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const TIMEOUT: Duration = Duration::from_secs(60);
+
+  fn ms(t0: Instant, ms: u64) -> Instant {
+    t0 + Duration::from_millis(ms)
+  }
+
+  fn receive(transaction: &mut Transaction, bytes: &[u8], now: Instant) -> Action {
+    let mut action = transaction.step(Event::Received(bytes[..1].to_vec(), now));
+    let mut pos = 1;
+
+    while let Action::Read(len) = action {
+      action = transaction.step(Event::Received(bytes[pos..(pos + len)].to_vec(), now));
+      pos += len;
+    }
+
+    assert_eq!(pos, bytes.len());
+    action
+  }
+
+  #[test]
+  fn negotiate() {
+    let t0 = Instant::now();
+    let mut transaction = Transaction::negotiate(TIMEOUT, t0);
+
+    assert_eq!(transaction.start(), Action::Write(vec![RESET]));
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 1))), Action::Read(1));
+    assert_eq!(transaction.step(Event::Received(vec![0xFF], ms(t0, 10))), Action::Read(1));
+    assert_eq!(transaction.step(Event::Received(vec![SYNC], ms(t0, 20))), Action::Write(START.to_vec()));
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 21))), Action::Read(1));
+    assert_eq!(transaction.step(Event::Received(vec![NACK], ms(t0, 30))), Action::Read(1));
+    assert_eq!(transaction.step(Event::Received(vec![SYNC], ms(t0, 40))), Action::Write(START.to_vec()));
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 41))), Action::Read(1));
+    assert_eq!(transaction.step(Event::Received(vec![ACK], ms(t0, 50))), Action::Done(vec![]));
+  }
+
+  #[test]
+  fn negotiate_timeout() {
+    let t0 = Instant::now();
+    let mut transaction = Transaction::negotiate(Duration::from_secs(5), t0);
+
+    assert_eq!(transaction.start(), Action::Write(vec![RESET]));
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 1))), Action::Read(1));
+    assert_eq!(transaction.step(Event::Received(vec![0xFF], ms(t0, 5_001))), Action::Fail(io::ErrorKind::TimedOut, "negotiate timed out".into()));
+  }
+
+  #[test]
+  fn get() {
+    let t0 = Instant::now();
+    let request = Telegram::read_request(0x0802, 2).unwrap();
+    let mut transaction = Transaction::request(request.clone(), TIMEOUT, t0);
+
+    assert_eq!(transaction.start(), Action::Write(request.encode()));
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 1))), Action::Read(1));
+    assert_eq!(transaction.step(Event::Received(vec![NACK], ms(t0, 10))), Action::Write(request.encode()));
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 11))), Action::Read(1));
+    assert_eq!(transaction.step(Event::Received(vec![ACK], ms(t0, 20))), Action::Read(1));
+
+    let response = Telegram::read_response(0x0802, &[0xD7, 0x00]).unwrap().encode();
+    let mut corrupted = response.clone();
+    *corrupted.last_mut().unwrap() ^= 0xFF;
+
+    assert_eq!(receive(&mut transaction, &corrupted, ms(t0, 30)), Action::Write(vec![NACK]));
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 31))), Action::Read(1));
+    assert_eq!(receive(&mut transaction, &response, ms(t0, 40)), Action::Write(vec![ACK]));
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 41))), Action::Done(vec![0xD7, 0x00]));
+  }
+
+  #[test]
+  fn set() {
+    let t0 = Instant::now();
+    let request = Telegram::write_request(0x6300, &[0x30]).unwrap();
+    let mut transaction = Transaction::request(request.clone(), TIMEOUT, t0);
+
+    assert_eq!(transaction.start(), Action::Write(request.encode()));
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 1))), Action::Read(1));
+    assert_eq!(transaction.step(Event::Received(vec![ACK], ms(t0, 10))), Action::Read(1));
+    assert_eq!(receive(&mut transaction, &Telegram::write_response(0x6300, 1).unwrap().encode(), ms(t0, 20)), Action::Write(vec![ACK]));
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 21))), Action::Done(vec![]));
+  }
+
+  #[test]
+  fn invalid_response() {
+    let t0 = Instant::now();
+    let request = Telegram::read_request(0x0802, 2).unwrap();
+    let mut transaction = Transaction::request(request, TIMEOUT, t0);
+
+    transaction.start();
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 1))), Action::Read(1));
+    assert_eq!(transaction.step(Event::Received(vec![ACK], ms(t0, 10))), Action::Read(1));
+    assert_eq!(receive(&mut transaction, &Telegram::read_response(0x0804, &[0xD7, 0x00]).unwrap().encode(), ms(t0, 20)), Action::Write(vec![ACK]));
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 21))), Action::Fail(io::ErrorKind::InvalidData, "wrong address".into()));
+  }
+
+  #[test]
+  fn send_timeout() {
+    let t0 = Instant::now();
+    let request = Telegram::write_request(0x6300, &[0x30]).unwrap();
+    let mut transaction = Transaction::request(request, TIMEOUT, t0);
+
+    transaction.start();
+    assert_eq!(transaction.step(Event::Completed(ms(t0, 1))), Action::Read(1));
+    assert_eq!(transaction.step(Event::Received(vec![NACK], ms(t0, 60_001))), Action::Fail(io::ErrorKind::TimedOut, "send telegram timed out".into()));
+  }
+
+  #[test]
+  fn unexpected_event() {
+    let t0 = Instant::now();
+    let mut transaction = Transaction::negotiate(TIMEOUT, t0);
+
+    transaction.start();
+    assert!(matches!(transaction.step(Event::Received(vec![SYNC], t0)), Action::Fail(io::ErrorKind::InvalidInput, _)));
+    assert!(matches!(transaction.start(), Action::Fail(io::ErrorKind::InvalidInput, _)));
+  }
+}
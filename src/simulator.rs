@@ -0,0 +1,600 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+#[cfg(feature = "async")]
+use std::pin::Pin;
+#[cfg(feature = "async")]
+use std::task::{Context, Poll};
+
+use crate::{Device, Transport};
+use crate::protocol::p300::{self, Decoded, DecodeError, Frame, Function, MessageType, Telegram, TelegramError};
+
+const KW2_START: u8 = 0x01;
+const KW2_READ: u8  = 0xf7;
+const KW2_WRITE: u8 = 0xf4;
+
+const MEMORY_SIZE: usize = 0x10000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+  Kw2,
+  P300,
+}
+
+/// An in-memory simulation of a heating controller.
+///
+/// The simulator keeps a byte-addressable memory image and answers requests
+/// using either `Kw2` or `P300` framing, depending on how the connection was
+/// negotiated. It can be used in place of a real device with [`Optolink::simulate`].
+///
+/// This requires the `simulator` feature.
+///
+/// [`Optolink::simulate`]: crate::Optolink::simulate
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(feature = "device-V200KW2_6")]
+/// # fn main() -> Result<(), vcontrol::Error> {
+/// use vcontrol::{Optolink, Simulator, VControl, Value, device::V200KW2};
+///
+/// let mut simulator = Simulator::new(&V200KW2);
+/// simulator.write_memory(0x6300, &[48]);
+///
+/// let mut vcontrol = VControl::<V200KW2>::connect(Optolink::simulate(simulator))?;
+///
+/// match vcontrol.get("hotwater_temp_desired")? {
+///   Value::Decimal(decimal) => assert_eq!(decimal.to_string(), "48"),
+///   _ => unreachable!(),
+/// }
+/// # Ok(())
+/// # }
+/// # #[cfg(not(feature = "device-V200KW2_6"))]
+/// # fn main() {}
+/// ```
+pub struct Simulator {
+  memory: Vec<u8>,
+  p300: bool,
+  mode: Mode,
+  input: Vec<u8>,
+  output: VecDeque<u8>,
+}
+
+impl fmt::Debug for Simulator {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("Simulator")
+      .field("p300", &self.p300)
+      .field("mode", &self.mode)
+      .field("input", &self.input)
+      .field("output", &self.output)
+      .finish()
+  }
+}
+
+impl Simulator {
+  /// Creates a simulator for the given device.
+  ///
+  /// The memory is zero-initialized, except for commands with a mapping
+  /// which has no entry for zero, which are initialized to their first
+  /// mapped value so that every command can be read successfully.
+  pub fn new<D: Device + ?Sized>(device: &D) -> Simulator {
+    let mut simulator = Simulator {
+      memory: vec![0; MEMORY_SIZE],
+      p300: true,
+      mode: Mode::Kw2,
+      input: Vec::new(),
+      output: VecDeque::new(),
+    };
+
+    for command in device.commands().into_iter().filter_map(|name| device.command(name)) {
+      if command.bit_pos.is_some() {
+        continue
+      }
+
+      if let Some(mapping) = &command.mapping {
+        let zero = vec![0; command.byte_len];
+
+        let mut keys = mapping.keys();
+        keys.sort();
+
+        if let Some(key) = keys.first() {
+          if !keys.contains(&zero) {
+            simulator.write_memory(command.addr.wrapping_add(command.byte_pos as u16), key);
+          }
+        }
+      }
+    }
+
+    simulator
+  }
+
+  /// Sets whether the simulator answers `P300` negotiation requests.
+  ///
+  /// If disabled, the simulator only speaks `Kw2`.
+  pub fn set_p300_support(&mut self, enabled: bool) {
+    self.p300 = enabled;
+  }
+
+  /// Reads the memory at address `addr` into `buf`.
+  pub fn read_memory(&self, addr: u16, buf: &mut [u8]) {
+    for (i, byte) in buf.iter_mut().enumerate() {
+      *byte = self.memory[(addr as usize + i) % MEMORY_SIZE];
+    }
+  }
+
+  /// Writes `bytes` to the memory at address `addr`.
+  pub fn write_memory(&mut self, addr: u16, bytes: &[u8]) {
+    for (i, byte) in bytes.iter().enumerate() {
+      self.memory[(addr as usize + i) % MEMORY_SIZE] = *byte;
+    }
+  }
+
+  fn process(&mut self) {
+    while let Some(&byte) = self.input.first() {
+      let consumed = if byte == KW2_START && self.mode == Mode::Kw2 {
+        match self.kw2_request() {
+          Some(consumed) => consumed,
+          None => return,
+        }
+      } else {
+        match p300::decode(&self.input) {
+          Decoded::Incomplete(_) => return,
+          Decoded::Complete(frame, consumed) => {
+            self.p300_frame(frame);
+            consumed
+          },
+        }
+      };
+
+      self.input.drain(..consumed);
+    }
+  }
+
+  fn kw2_request(&mut self) -> Option<usize> {
+    if self.input.len() < 5 {
+      return None
+    }
+
+    let addr = u16::from_be_bytes([self.input[2], self.input[3]]);
+    let len = self.input[4] as usize;
+
+    match self.input[1] {
+      KW2_READ => {
+        let mut buf = vec![0; len];
+        self.read_memory(addr, &mut buf);
+        self.output.extend(buf);
+        Some(5)
+      },
+      KW2_WRITE => {
+        if self.input.len() < 5 + len {
+          return None
+        }
+
+        let value = self.input[5..(5 + len)].to_vec();
+        self.write_memory(addr, &value);
+        self.output.push_back(0x00);
+        Some(5 + len)
+      },
+      _ => Some(1),
+    }
+  }
+
+  fn p300_frame(&mut self, frame: Result<Frame, DecodeError>) {
+    let response = match (frame, self.mode) {
+      (Ok(Frame::Reset), _) => {
+        self.mode = Mode::Kw2;
+        Frame::Sync
+      },
+      (Ok(Frame::Start), _) if self.p300 => {
+        self.mode = Mode::P300;
+        Frame::Ack
+      },
+      (Ok(Frame::Telegram(telegram)), Mode::P300) => {
+        self.output.extend(Frame::Ack.encode());
+
+        match self.p300_response(telegram) {
+          Ok(response) => Frame::Telegram(response),
+          Err(err) => {
+            log::debug!("Simulator::process() cannot respond: {}", err);
+            return
+          },
+        }
+      },
+      (Err(DecodeError::Checksum { .. }), Mode::P300) => Frame::Nack,
+      (frame, mode) => {
+        log::debug!("Simulator::process() ignoring {:?} in {:?} mode", frame, mode);
+        return
+      },
+    };
+
+    self.output.extend(response.encode());
+  }
+
+  fn p300_response(&mut self, request: Telegram) -> Result<Telegram, TelegramError> {
+    match (request.message_type(), request.function()) {
+      (MessageType::Request, Function::ReadData) => {
+        let mut buf = vec![0; request.data_len() as usize];
+        self.read_memory(request.addr(), &mut buf);
+        Telegram::read_response(request.addr(), &buf)
+      },
+      (MessageType::Request, Function::WriteData) if request.data().len() == request.data_len() as usize => {
+        self.write_memory(request.addr(), request.data());
+        Telegram::write_response(request.addr(), request.data_len() as usize)
+      },
+      _ => Telegram::new(MessageType::Error, request.function(), request.addr(), request.data_len() as usize, request.data().to_vec()),
+    }
+  }
+}
+
+impl Transport for Simulator {
+  fn purge(&mut self) -> io::Result<()> {
+    self.output.clear();
+    Ok(())
+  }
+
+  fn set_timeout(&mut self, _timeout: Option<Duration>) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+impl io::Write for Simulator {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.input.extend(buf);
+    self.process();
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+impl io::Read for Simulator {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    if self.output.is_empty() && !buf.is_empty() {
+      return Err(io::Error::new(io::ErrorKind::TimedOut, "simulator has no pending output"))
+    }
+
+    let n = buf.len().min(self.output.len());
+
+    for (byte, output) in buf.iter_mut().zip(self.output.drain(..n)) {
+      *byte = output;
+    }
+
+    Ok(n)
+  }
+}
+
+#[cfg(feature = "async")]
+impl tokio::io::AsyncWrite for Simulator {
+  fn poll_write(self: Pin<&mut Self>, _cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+    Poll::Ready(io::Write::write(self.get_mut(), buf))
+  }
+
+  fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+    Poll::Ready(Ok(()))
+  }
+
+  fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+    Poll::Ready(Ok(()))
+  }
+}
+
+#[cfg(feature = "async")]
+impl tokio::io::AsyncRead for Simulator {
+  fn poll_read(self: Pin<&mut Self>, _cx: &mut Context, buf: &mut tokio::io::ReadBuf) -> Poll<io::Result<()>> {
+    let n = io::Read::read(self.get_mut(), buf.initialize_unfilled())?;
+    buf.advance(n);
+    Poll::Ready(Ok(()))
+  }
+}
+
+#[cfg(all(test, feature = "device-V200KW2_6"))]
+mod tests {
+  use super::*;
+
+  use chrono::Weekday;
+
+  use crate::{Command, Error, Optolink, ProtocolKind, VControl, Value, WeeklySchedule, device::V200KW2};
+
+  #[derive(Debug, Default)]
+  struct V200KW2P300;
+
+  impl Device for V200KW2P300 {
+    fn protocol(&self) -> ProtocolKind {
+      ProtocolKind::P300
+    }
+
+    fn command(&self, name: &str) -> Option<&Command> {
+      V200KW2.command(name)
+    }
+
+    fn commands(&self) -> Vec<&str> {
+      V200KW2.commands()
+    }
+  }
+
+  fn get_number<D: Device>(vcontrol: &mut VControl<D>, command: &str) -> f64 {
+    match vcontrol.get(command).unwrap() {
+      Value::Number(n) => n,
+      Value::Decimal(decimal) => decimal.to_f64(),
+      value => panic!("expected number, found {:?}", value),
+    }
+  }
+
+  #[test]
+  fn new_seeds_mappings() {
+    let simulator = Simulator::new(&V200KW2);
+
+    let mut buf = [0; 2];
+    simulator.read_memory(0x00F8, &mut buf);
+    assert_ne!(buf, [0x00, 0x00]);
+  }
+
+  #[test]
+  fn kw2() {
+    let mut simulator = Simulator::new(&V200KW2);
+    simulator.write_memory(0x0802, &215i16.to_le_bytes());
+
+    let mut vcontrol = VControl::<V200KW2>::connect(Optolink::simulate(simulator)).unwrap();
+
+    assert_eq!(get_number(&mut vcontrol, "boiler_temp_actual"), 21.5);
+
+    vcontrol.set("hotwater_temp_desired", &Value::Number(55.0)).unwrap();
+    assert_eq!(get_number(&mut vcontrol, "hotwater_temp_desired"), 55.0);
+
+    vcontrol.set("operatingmode", &Value::String("RED".into())).unwrap();
+    match vcontrol.get("operatingmode").unwrap() {
+      Value::String(s) => assert_eq!(s, "RED"),
+      value => panic!("expected string, found {:?}", value),
+    }
+  }
+
+  #[test]
+  fn set_out_of_range() {
+    let mut vcontrol = VControl::<V200KW2>::connect(Optolink::simulate(Simulator::new(&V200KW2))).unwrap();
+
+    vcontrol.set("hotwater_temp_desired", &Value::Number(50.0)).unwrap();
+
+    match vcontrol.set("hotwater_temp_desired", &Value::Number(300.0)) {
+      Err(Error::InvalidArgument(message)) => assert_eq!(message, "value 300 is outside of the range 10 to 60"),
+      result => panic!("expected invalid argument, found {:?}", result),
+    }
+
+    match vcontrol.set("hotwater_temp_desired", &Value::Number(45.5)) {
+      Err(Error::InvalidArgument(message)) => assert_eq!(message, "value 45.5 is not a multiple of the step 1 from 10"),
+      result => panic!("expected invalid argument, found {:?}", result),
+    }
+
+    assert_eq!(get_number(&mut vcontrol, "hotwater_temp_desired"), 50.0);
+  }
+
+  #[test]
+  fn decimal_round_trip() {
+    let mut simulator = Simulator::new(&V200KW2);
+    simulator.write_memory(0x3305, &[13]);
+
+    let mut vcontrol = VControl::<V200KW2>::connect(Optolink::simulate(simulator)).unwrap();
+
+    let incline = vcontrol.get("heatingcurve_incline").unwrap();
+    assert_eq!(incline.to_string(), "1.3");
+    assert_eq!(serde_json::to_string(&incline).unwrap(), "1.3");
+
+    vcontrol.set("heatingcurve_incline", &"1.7".parse().unwrap()).unwrap();
+    assert_eq!(vcontrol.get("heatingcurve_incline").unwrap().to_string(), "1.7");
+
+    vcontrol.set("heatingcurve_incline", &serde_json::from_str("0.3").unwrap()).unwrap();
+    assert_eq!(vcontrol.get("heatingcurve_incline").unwrap().to_string(), "0.3");
+  }
+
+  #[test]
+  fn set_cycletime() {
+    let mut vcontrol = VControl::<V200KW2>::connect(Optolink::simulate(Simulator::new(&V200KW2))).unwrap();
+
+    let timer = "06:00 – 08:00,16:30 – 22:00,--:-- – --:--,--:-- – --:--";
+    vcontrol.set("hotwater_timer_mon", &timer.parse().unwrap()).unwrap();
+
+    match vcontrol.get("hotwater_timer_mon").unwrap() {
+      Value::CycleTime(cycletime) => assert_eq!(cycletime.to_string(), format!("{:?}", timer)),
+      value => panic!("expected cycle time, found {:?}", value),
+    }
+  }
+
+  #[test]
+  fn schedule() {
+    let mut vcontrol = VControl::<V200KW2>::connect(Optolink::simulate(Simulator::new(&V200KW2))).unwrap();
+
+    let mut schedule = WeeklySchedule::default();
+    schedule.set_day(Weekday::Mon, "06:00 – 08:00,16:30 – 22:00".parse().unwrap());
+    schedule.copy_day(Weekday::Mon, &[Weekday::Tue, Weekday::Wed]);
+
+    vcontrol.set_schedule("heatingcircuit", &schedule).unwrap();
+    assert_eq!(vcontrol.get_schedule("heatingcircuit").unwrap(), schedule);
+    assert_ne!(vcontrol.get_schedule("hotwater").unwrap(), schedule);
+
+    assert!(vcontrol.get_schedule("unknown").is_err());
+  }
+
+  #[test]
+  fn error_history() {
+    let mut simulator = Simulator::new(&V200KW2);
+    simulator.write_memory(0x7507, &[0x10, 0x20, 0x18, 0x12, 0x23, 0x07, 0x17, 0x49, 0x31]);
+    simulator.write_memory(0x7510, &[0x00; 9]);
+
+    let mut vcontrol = VControl::<V200KW2>::connect(Optolink::simulate(simulator)).unwrap();
+
+    let history = vcontrol.get_error_history().unwrap();
+    assert_eq!(history.entries().len(), 1);
+    assert_eq!(history.entries()[0].description(), Some("Notbetrieb-Außentemp"));
+    assert_eq!(
+      serde_json::to_string(&history).unwrap(),
+      r#"[{"code":16,"description":"Notbetrieb-Außentemp","time":"2018-12-23T17:49:31"}]"#,
+    );
+  }
+
+  #[test]
+  fn kw2_sync_byte_in_response() {
+    let mut simulator = Simulator::new(&V200KW2);
+    simulator.write_memory(0x6300, &[0x05]);
+
+    let mut vcontrol = VControl::<V200KW2>::connect(Optolink::simulate(simulator)).unwrap();
+
+    assert_eq!(get_number(&mut vcontrol, "hotwater_temp_desired"), 5.0);
+  }
+
+  #[test]
+  fn p300() {
+    let mut simulator = Simulator::new(&V200KW2P300);
+    simulator.write_memory(0x0802, &(-42i16).to_le_bytes());
+
+    let mut vcontrol = VControl::<V200KW2P300>::connect(Optolink::simulate(simulator)).unwrap();
+
+    assert_eq!(get_number(&mut vcontrol, "boiler_temp_actual"), -4.2);
+
+    vcontrol.set("hotwater_temp_desired", &Value::Number(48.0)).unwrap();
+    assert_eq!(get_number(&mut vcontrol, "hotwater_temp_desired"), 48.0);
+  }
+
+  #[test]
+  fn p300_nack_on_bad_checksum() {
+    use std::io::{Read, Write};
+
+    let mut simulator = Simulator::new(&V200KW2P300);
+    simulator.write_all(&[0x04, 0x16, 0x00, 0x00]).unwrap();
+    simulator.write_all(&[0x41, 0x05, 0x00, 0x01, 0x63, 0x00, 0x01, 0x00]).unwrap();
+
+    let mut buf = [0; 3];
+    simulator.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [0x05, 0x06, 0x15]);
+  }
+
+  #[cfg(feature = "async")]
+  #[tokio::test]
+  async fn kw2_async() {
+    use crate::{AsyncOptolink, AsyncVControl};
+
+    let mut simulator = Simulator::new(&V200KW2);
+    simulator.write_memory(0x0802, &215i16.to_le_bytes());
+
+    let mut vcontrol = AsyncVControl::<V200KW2>::connect(AsyncOptolink::new(simulator)).await.unwrap();
+
+    match vcontrol.get("boiler_temp_actual").await.unwrap() {
+      Value::Decimal(decimal) => assert_eq!(decimal.to_string(), "21.5"),
+      value => panic!("expected number, found {:?}", value),
+    }
+
+    vcontrol.set("hotwater_temp_desired", &Value::Number(55.0)).await.unwrap();
+    match vcontrol.get("hotwater_temp_desired").await.unwrap() {
+      Value::Decimal(decimal) => assert_eq!(decimal.to_string(), "55"),
+      value => panic!("expected decimal, found {:?}", value),
+    }
+  }
+
+  #[cfg(feature = "async")]
+  #[tokio::test]
+  async fn p300_async() {
+    use crate::{AsyncOptolink, AsyncVControl};
+
+    let mut simulator = Simulator::new(&V200KW2P300);
+    simulator.write_memory(0x0802, &(-42i16).to_le_bytes());
+
+    let mut vcontrol = AsyncVControl::<V200KW2P300>::connect(AsyncOptolink::new(simulator)).await.unwrap();
+
+    match vcontrol.get("boiler_temp_actual").await.unwrap() {
+      Value::Decimal(decimal) => assert_eq!(decimal.to_string(), "-4.2"),
+      value => panic!("expected number, found {:?}", value),
+    }
+
+    vcontrol.set("hotwater_temp_desired", &Value::Number(48.0)).await.unwrap();
+    match vcontrol.get("hotwater_temp_desired").await.unwrap() {
+      Value::Decimal(decimal) => assert_eq!(decimal.to_string(), "48"),
+      value => panic!("expected decimal, found {:?}", value),
+    }
+  }
+
+  #[test]
+  fn detect_protocol() {
+    let mut device = Optolink::simulate(Simulator::new(&V200KW2));
+    assert_eq!(device.detect_protocol().unwrap(), ProtocolKind::P300);
+
+    let mut simulator = Simulator::new(&V200KW2);
+    simulator.set_p300_support(false);
+
+    let mut device = Optolink::simulate(simulator);
+    assert_eq!(device.detect_protocol().unwrap(), ProtocolKind::Kw2);
+  }
+
+  #[test]
+  fn connect_identifies_device() {
+    let mut simulator = Simulator::new(&V200KW2);
+    simulator.write_memory(0x00F8, &[0x20, 0x98]);
+    simulator.write_memory(0x0802, &215i16.to_le_bytes());
+
+    let mut vcontrol = crate::connect(Optolink::simulate(simulator)).unwrap();
+    assert!(vcontrol.commands().contains(&"boiler_temp_actual"));
+
+    match vcontrol.get("boiler_temp_actual").unwrap() {
+      Value::Decimal(decimal) => assert_eq!(decimal.to_string(), "21.5"),
+      value => panic!("expected number, found {:?}", value),
+    }
+  }
+
+  #[test]
+  fn connect_with_protocol() {
+    let mut simulator = Simulator::new(&V200KW2);
+    simulator.set_p300_support(false);
+    simulator.write_memory(0x00F8, &[0x20, 0x98]);
+
+    let vcontrol = crate::connect_with_protocol(Optolink::simulate(simulator), ProtocolKind::Kw2).unwrap();
+    assert_eq!(vcontrol.device().protocol(), ProtocolKind::Kw2);
+  }
+
+  #[test]
+  fn connect_unknown_device() {
+    let mut simulator = Simulator::new(&V200KW2);
+    simulator.write_memory(0x00F8, &[0x20, 0x99]);
+
+    let err = crate::connect(Optolink::simulate(simulator)).unwrap_err();
+    assert_eq!(err.to_string(), "no device found for ID [0x20, 0x99]");
+  }
+
+  #[cfg(feature = "async")]
+  #[tokio::test]
+  async fn detect_protocol_async() {
+    use crate::AsyncOptolink;
+
+    let mut device = AsyncOptolink::new(Simulator::new(&V200KW2));
+    assert_eq!(device.detect_protocol().await.unwrap(), ProtocolKind::P300);
+
+    let mut simulator = Simulator::new(&V200KW2);
+    simulator.set_p300_support(false);
+
+    let mut device = AsyncOptolink::new(simulator);
+    assert_eq!(device.detect_protocol().await.unwrap(), ProtocolKind::Kw2);
+  }
+
+  #[cfg(feature = "async")]
+  #[tokio::test]
+  async fn connect_async_identifies_device() {
+    use crate::AsyncOptolink;
+
+    let mut simulator = Simulator::new(&V200KW2);
+    simulator.write_memory(0x00F8, &[0x20, 0x98]);
+    simulator.write_memory(0x0802, &215i16.to_le_bytes());
+
+    let mut vcontrol = crate::connect_async(AsyncOptolink::new(simulator)).await.unwrap();
+
+    match vcontrol.get("boiler_temp_actual").await.unwrap() {
+      Value::Decimal(decimal) => assert_eq!(decimal.to_string(), "21.5"),
+      value => panic!("expected number, found {:?}", value),
+    }
+
+    let mut simulator = Simulator::new(&V200KW2);
+    simulator.set_p300_support(false);
+    simulator.write_memory(0x00F8, &[0x20, 0x99]);
+
+    let err = crate::connect_async_with_protocol(AsyncOptolink::new(simulator), ProtocolKind::Kw2).await.unwrap_err();
+    assert_eq!(err.to_string(), "no device found for ID [0x20, 0x99]");
+  }
+}
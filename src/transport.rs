@@ -0,0 +1,39 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+/// A byte stream over which an Optolink connection can be established.
+///
+/// Implement this trait to use an [`Optolink`](crate::Optolink) with a transport
+/// other than the built-in serial and TCP transports.
+pub trait Transport: Read + Write + Send + fmt::Debug {
+  /// Purge all contents of the input buffer.
+  fn purge(&mut self) -> io::Result<()>;
+
+  /// Flush the output buffer, failing with `TimedOut` if this takes longer than `timeout`.
+  fn flush_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+    let _ = timeout;
+    self.flush()
+  }
+
+  /// Set timeout for read operations.
+  ///
+  /// `None` makes reads block indefinitely, or selects a default timeout for
+  /// transports which cannot block indefinitely, e.g. a serial port.
+  fn set_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+/// Reads from `reader` until it returns an error or end of file.
+pub(crate) fn drain(reader: &mut impl Read) {
+  let mut buf = [0; 64];
+  while let Ok(n) = reader.read(&mut buf) {
+    if n == 0 {
+      break
+    }
+  }
+}
+
+mod serial;
+pub use self::serial::Serial;
+
+mod stream;
@@ -0,0 +1,135 @@
+use std::fmt;
+use std::ffi::OsStr;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+
+use serial_core::{SerialPort, SerialPortSettings, BaudRate::Baud4800, Parity::ParityEven, StopBits::Stop2, CharSize::Bits8};
+use serial::SystemPort;
+
+use crate::Optolink;
+
+use super::{Transport, drain};
+
+/// A serial port configured for Optolink communication.
+pub struct Serial {
+  tty: SystemPort,
+  timeout: Option<Duration>,
+}
+
+impl fmt::Debug for Serial {
+  #[cfg(unix)]
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "TTYPort {{ fd: {:?} }}", self.tty.as_raw_fd())
+  }
+
+  #[cfg(not(unix))]
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "TTYPort")
+  }
+}
+
+impl Serial {
+  /// Opens and configures a serial device.
+  pub fn open(port: impl AsRef<OsStr>) -> io::Result<Serial> {
+    log::trace!("Serial::open(…)");
+
+    let mut tty = serial::open(&port)
+      .map_err(|err| {
+        let err = io::Error::from(err);
+
+        if err.kind() == io::ErrorKind::NotFound {
+          return io::Error::new(err.kind(), format!("{}: {}", err, port.as_ref().to_string_lossy()))
+        }
+
+        err
+      })?;
+
+    tty.set_timeout(Optolink::TIMEOUT)?;
+
+    tty.reconfigure(&|settings: &mut dyn SerialPortSettings| -> Result<(), serial_core::Error> {
+      settings.set_parity(ParityEven);
+      settings.set_stop_bits(Stop2);
+      settings.set_char_size(Bits8);
+      settings.set_baud_rate(Baud4800)
+    })?;
+
+    Ok(Serial { tty, timeout: None })
+  }
+}
+
+impl Transport for Serial {
+  fn purge(&mut self) -> io::Result<()> {
+    self.tty.set_timeout(Duration::new(0, 0))?;
+    drain(&mut self.tty);
+    self.tty.set_timeout(self.timeout.unwrap_or(Optolink::TIMEOUT))?;
+    Ok(())
+  }
+
+  // This is a workaround for `tcdrain`, which `SystemPort::flush`
+  // uses under the hood. If a device is disconnected, `tcdrain`
+  // will block forever instead of timing out, so we spawn it in a
+  // separate thread and manually create a timeout.
+  #[cfg(unix)]
+  fn flush_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+    use std::mem;
+    use std::os::unix::thread::JoinHandleExt;
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::Instant;
+
+    let start = Instant::now();
+
+    // Allow moving this to the helper thread. This is safe because we either
+    // join the thread in this scope or cancel it if it times out.
+    let tty: &mut SystemPort = &mut self.tty;
+    let tty: &'static mut SystemPort = unsafe { mem::transmute(tty) };
+
+    let (tx, rx) = channel();
+
+    let t = thread::spawn(move || {
+      let res = tty.flush();
+      tx.send(()).unwrap();
+      res
+    });
+
+    loop {
+      log::trace!("Serial::flush_timeout(…) loop");
+
+      if rx.try_recv().is_ok() {
+        return t.join().unwrap()
+      }
+
+      let stop = Instant::now();
+
+      if (stop - start) > timeout {
+        assert_eq!(unsafe { libc::pthread_cancel(t.into_pthread_t() as _) }, 0);
+        return Err(io::Error::new(io::ErrorKind::TimedOut, "flush timed out"))
+      }
+
+      thread::yield_now();
+    }
+  }
+
+  fn set_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+    self.timeout = timeout;
+    Ok(self.tty.set_timeout(timeout.unwrap_or(Optolink::TIMEOUT))?)
+  }
+}
+
+impl Write for Serial {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.tty.write(buf)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.tty.flush()
+  }
+}
+
+impl Read for Serial {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    self.tty.read(buf)
+  }
+}
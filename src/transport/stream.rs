@@ -0,0 +1,79 @@
+use std::io;
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+use super::{Transport, drain};
+
+impl Transport for TcpStream {
+  fn purge(&mut self) -> io::Result<()> {
+    self.set_nonblocking(true)?;
+    drain(self);
+    self.set_nonblocking(false)
+  }
+
+  fn set_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+    self.set_read_timeout(timeout)
+  }
+}
+
+#[cfg(unix)]
+impl Transport for UnixStream {
+  fn purge(&mut self) -> io::Result<()> {
+    self.set_nonblocking(true)?;
+    drain(self);
+    self.set_nonblocking(false)
+  }
+
+  fn set_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+    self.set_read_timeout(timeout)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Write;
+  use std::net::TcpListener;
+
+  use super::*;
+
+  fn check(mut stream: impl Transport, mut peer: impl Write) {
+    peer.write_all(&[1, 2, 3]).unwrap();
+    peer.flush().unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+    stream.purge().unwrap();
+
+    stream.set_timeout(Some(Duration::from_millis(10))).unwrap();
+    let mut buf = [0; 1];
+    let err = stream.read(&mut buf).unwrap_err();
+    assert!(err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut);
+
+    peer.write_all(&[4]).unwrap();
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [4]);
+  }
+
+  #[test]
+  fn tcp_stream() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (peer, _) = listener.accept().unwrap();
+
+    stream.set_timeout(None).unwrap();
+    assert_eq!(stream.read_timeout().unwrap(), None);
+
+    check(stream, peer);
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn unix_stream() {
+    let (mut stream, peer) = UnixStream::pair().unwrap();
+
+    stream.set_timeout(None).unwrap();
+    assert_eq!(stream.read_timeout().unwrap(), None);
+
+    check(stream, peer);
+  }
+}
@@ -1,61 +1,184 @@
+use std::convert::TryFrom;
 use std::fmt;
 use std::str::FromStr;
 
+use chrono::{NaiveTime, Timelike};
 use serde::ser::{Serialize, Serializer};
 use serde::de::{self, Deserialize, Deserializer};
 use serde_derive::*;
 
+/// An optional `(hour, minute)` tuple, where `None` is an unset time.
+type HourMinute = Option<(u8, u8)>;
+
 byte_type!(CycleTime, 8);
 
 impl CycleTime {
-  fn byte_to_time(&self, i: usize) -> Option<(u8, u8)> {
+  /// The maximum number of switching periods per cycle time.
+  pub const MAX_PERIODS: usize = 4;
+
+  /// Creates a cycle time from up to four `(on, off)` switching periods.
+  ///
+  /// Times must be multiples of 10 minutes without seconds and periods must be in ascending
+  /// order without overlapping. An `off` time of `00:00` denotes the end of the day.
+  pub fn new(periods: &[(NaiveTime, NaiveTime)]) -> Result<CycleTime, crate::Error> {
+    let time = |time: NaiveTime, end: bool| {
+      if time.second() != 0 || time.nanosecond() != 0 {
+        return Err(format!("invalid time {}, seconds must be zero", time))
+      }
+
+      Ok(match (time.hour() as u8, time.minute() as u8) {
+        (0, 0) if end => Some((24, 0)),
+        hh_mm => Some(hh_mm),
+      })
+    };
+
+    let times = periods.iter()
+      .map(|&(from, to)| Ok((time(from, false)?, time(to, true)?)))
+      .collect::<Result<Vec<_>, String>>()
+      .map_err(crate::Error::InvalidArgument)?;
+
+    CycleTime::from_times(&times).map_err(crate::Error::InvalidArgument)
+  }
+
+  /// Creates a cycle time without any switching periods.
+  pub fn empty() -> CycleTime {
+    CycleTime([0xff; 8])
+  }
+
+  /// Returns the switching period in the given slot, if it is set.
+  ///
+  /// An `off` time of `00:00` denotes the end of the day.
+  pub fn period(&self, i: usize) -> Option<(NaiveTime, NaiveTime)> {
+    if i >= Self::MAX_PERIODS {
+      return None
+    }
+
+    let time = |time: HourMinute| time.and_then(|(hh, mm)| NaiveTime::from_hms_opt(u32::from(hh % 24), u32::from(mm), 0));
+
+    match (self.byte_to_time(i * 2), self.byte_to_time(i * 2 + 1)) {
+      (from @ Some(_), to @ Some(_)) => Some((time(from)?, time(to)?)),
+      _ => None,
+    }
+  }
+
+  /// Returns all switching periods which are set.
+  pub fn periods(&self) -> Vec<(NaiveTime, NaiveTime)> {
+    (0..Self::MAX_PERIODS).filter_map(|i| self.period(i)).collect()
+  }
+
+  fn byte_to_time(&self, i: usize) -> HourMinute {
     match self.0[i] {
       0xff => None,
       byte => Some((byte >> 3, (byte & 0b111) * 10)),
     }
   }
 
-  fn times(&self) -> [TimeSpan; 4] {
+  fn time_to_byte(time: HourMinute) -> u8 {
+    match time {
+      Some((hh, mm)) => (hh << 3) | (mm / 10),
+      None => 0xff,
+    }
+  }
+
+  /// Creates a cycle time from up to four `(from, to)` pairs of `(hour, minute)` tuples.
+  fn from_times(times: &[(HourMinute, HourMinute)]) -> Result<CycleTime, String> {
+    if times.len() > Self::MAX_PERIODS {
+      return Err(format!("expected at most {} time spans, found {}", Self::MAX_PERIODS, times.len()))
+    }
+
+    let mut bytes = [0xff; 8];
+    let mut previous_end = None;
+
+    for (i, (from, to)) in times.iter().enumerate() {
+      for &(hh, mm) in from.iter().chain(to.iter()) {
+        if hh > 24 || mm >= 60 || (hh == 24 && mm != 0) {
+          return Err(format!("invalid time {:02}:{:02}", hh, mm))
+        }
+
+        if mm % 10 != 0 {
+          return Err(format!("time {:02}:{:02} is not a multiple of 10 minutes", hh, mm))
+        }
+      }
+
+      match (from, to) {
+        (Some(from), Some(to)) => {
+          if from >= to {
+            return Err(format!("time span {:02}:{:02} – {:02}:{:02} ends before it starts", from.0, from.1, to.0, to.1))
+          }
+
+          if let Some(end) = previous_end {
+            if *from < end {
+              return Err(format!("time span {:02}:{:02} – {:02}:{:02} overlaps the previous one", from.0, from.1, to.0, to.1))
+            }
+          }
+
+          previous_end = Some(*to);
+        },
+        (None, None) => {},
+        _ => return Err("time span must either have a start and an end or neither".into()),
+      }
+
+      bytes[i * 2] = Self::time_to_byte(*from);
+      bytes[i * 2 + 1] = Self::time_to_byte(*to);
+    }
+
+    Ok(CycleTime(bytes))
+  }
+
+  fn times(&self) -> [(Time, Time); 4] {
+    let time = |i| Time(self.byte_to_time(i));
+
     [
-      TimeSpan { from: self.byte_to_time(0).into(), to: self.byte_to_time(1).into() },
-      TimeSpan { from: self.byte_to_time(2).into(), to: self.byte_to_time(3).into() },
-      TimeSpan { from: self.byte_to_time(4).into(), to: self.byte_to_time(5).into() },
-      TimeSpan { from: self.byte_to_time(6).into(), to: self.byte_to_time(7).into() },
+      (time(0), time(1)),
+      (time(2), time(3)),
+      (time(4), time(5)),
+      (time(6), time(7)),
     ]
   }
 }
 
-#[derive(Serialize, Clone)]
-struct TimeSpan {
-  from: Time,
-  to: Time,
+impl Default for CycleTime {
+  fn default() -> CycleTime {
+    CycleTime::empty()
+  }
 }
 
-impl fmt::Display for TimeSpan {
-  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(f, "{} – {}", self.from, self.to)
+/// Decodes and validates the 8-byte encoding used by the device.
+impl TryFrom<[u8; 8]> for CycleTime {
+  type Error = crate::Error;
+
+  fn try_from(bytes: [u8; 8]) -> Result<CycleTime, Self::Error> {
+    let cycletime = CycleTime(bytes);
+
+    let times = (0..CycleTime::MAX_PERIODS)
+      .map(|i| (cycletime.byte_to_time(i * 2), cycletime.byte_to_time(i * 2 + 1)))
+      .collect::<Vec<_>>();
+
+    CycleTime::from_times(&times).map_err(crate::Error::InvalidArgument)
   }
 }
 
-#[derive(Serialize, Clone)]
-struct Time {
-  hh: String,
-  mm: String,
+impl From<CycleTime> for [u8; 8] {
+  fn from(cycletime: CycleTime) -> [u8; 8] {
+    cycletime.0
+  }
 }
 
-impl fmt::Display for Time {
-  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(f, "{}:{}", self.hh, self.mm)
+struct Time(HourMinute);
+
+impl Time {
+  fn hh(&self) -> String {
+    self.0.map(|(hh, _)| format!("{:02}", hh)).unwrap_or_else(|| "--".into())
+  }
+
+  fn mm(&self) -> String {
+    self.0.map(|(_, mm)| format!("{:02}", mm)).unwrap_or_else(|| "--".into())
   }
 }
 
-impl From<Option<(u8, u8)>> for Time {
-  fn from(tuple: Option<(u8, u8)>) -> Self {
-    if let Some((hh, mm)) = tuple {
-      Time { hh: format!("{:02}", hh), mm: format!("{:02}", mm) }
-    } else {
-      Time { hh: "--".into(), mm: "--".into() }
-    }
+impl fmt::Display for Time {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}:{}", self.hh(), self.mm())
   }
 }
 
@@ -75,31 +198,71 @@ impl Serialize for CycleTime {
       mm: String,
     }
 
+    impl From<&Time> for TimeFull {
+      fn from(time: &Time) -> TimeFull {
+        TimeFull { full: time.to_string(), hh: time.hh(), mm: time.mm() }
+      }
+    }
+
     self.times().iter()
-      .map(|ts|
+      .map(|(from, to)|
         TimeSpanFull {
-          full: ts.to_string(),
-          from: TimeFull {
-            full: ts.from.to_string(),
-            hh: ts.from.hh.to_owned(),
-            mm: ts.from.mm.to_owned(),
-          },
-          to: TimeFull {
-            full: ts.to.to_string(),
-            hh: ts.to.hh.to_owned(),
-            mm: ts.to.mm.to_owned(),
-          },
+          full: format!("{} – {}", from, to),
+          from: from.into(),
+          to: to.into(),
       })
       .collect::<Vec<TimeSpanFull>>()
       .serialize(serializer)
   }
 }
 
+fn parse_time(hh: &str, mm: &str) -> Result<HourMinute, String> {
+  match (hh.trim(), mm.trim()) {
+    ("--", "--") => Ok(None),
+    (hh, mm) => match (hh.parse(), mm.parse()) {
+      (Ok(hh), Ok(mm)) => Ok(Some((hh, mm))),
+      _ => Err(format!("invalid time {}:{}", hh, mm)),
+    },
+  }
+}
+
+fn parse_time_of_day(time: &str) -> Result<HourMinute, String> {
+  let mut parts = time.splitn(2, ':');
+
+  match (parts.next(), parts.next()) {
+    (Some(hh), Some(mm)) => parse_time(hh, mm),
+    _ => Err(format!("invalid time {}", time.trim())),
+  }
+}
+
+fn parse_cycletime(s: &str) -> Result<CycleTime, String> {
+  let s = s.trim();
+  let s = if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') { &s[1..(s.len() - 1)] } else { s };
+
+  let times = s.split(',').map(|timespan| {
+    let (from, to) = match timespan.find('–') {
+      Some(i) => (&timespan[..i], &timespan[(i + '–'.len_utf8())..]),
+      // An ASCII `-` is ambiguous with unset times, so use the first one separating two valid times.
+      None => timespan.match_indices('-')
+        .map(|(i, _)| (&timespan[..i], &timespan[(i + 1)..]))
+        .find(|(from, to)| parse_time_of_day(from).is_ok() && parse_time_of_day(to).is_ok())
+        .ok_or_else(|| format!("invalid time span {}", timespan.trim()))?,
+    };
+
+    Ok((parse_time_of_day(from)?, parse_time_of_day(to)?))
+  }).collect::<Result<Vec<_>, String>>()?;
+
+  CycleTime::from_times(&times)
+}
+
+/// Parses the format written by `Display`, e.g. `"06:00 – 22:00,--:-- – --:--"`.
+///
+/// Time spans may also be separated by an ASCII `-`, e.g. `"06:00-22:00"`.
 impl FromStr for CycleTime {
-  type Err = String;
+  type Err = crate::Error;
 
   fn from_str(s: &str) -> Result<CycleTime, Self::Err> {
-    Err(format!("could not parse {}, from_str is not implemented for CycleTime", s))
+    parse_cycletime(s).map_err(crate::Error::InvalidArgument)
   }
 }
 
@@ -108,15 +271,125 @@ impl<'de> Deserialize<'de> for CycleTime {
   where
       D: Deserializer<'de>,
   {
-    let string = String::deserialize(deserializer)?;
-    CycleTime::from_str(&string).map_err(de::Error::custom)
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum CycleTimeRepr {
+      String(String),
+      TimeSpans(Vec<TimeSpanRepr>),
+    }
+
+    #[derive(Deserialize)]
+    struct TimeSpanRepr {
+      from: TimeRepr,
+      to: TimeRepr,
+    }
+
+    #[derive(Deserialize)]
+    struct TimeRepr {
+      hh: String,
+      mm: String,
+    }
+
+    match CycleTimeRepr::deserialize(deserializer)? {
+      CycleTimeRepr::String(string) => parse_cycletime(&string),
+      CycleTimeRepr::TimeSpans(timespans) => {
+        timespans.iter()
+          .map(|ts| Ok((parse_time(&ts.from.hh, &ts.from.mm)?, parse_time(&ts.to.hh, &ts.to.mm)?)))
+          .collect::<Result<Vec<_>, String>>()
+          .and_then(|times| CycleTime::from_times(&times))
+      },
+    }.map_err(de::Error::custom)
   }
 }
 
 impl fmt::Display for CycleTime {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     write!(f, "{:?}",
-      self.times().iter().map(|timespan| timespan.to_string()).collect::<Vec<String>>().join(","),
+      self.times().iter().map(|(from, to)| format!("{} – {}", from, to)).collect::<Vec<String>>().join(","),
     )
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::types::{FromBytes, ToBytes};
+
+  const BYTES: [u8; 8] = [0x30, 0xb0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+  const STRING: &str = "\"06:00 – 22:00,--:-- – --:--,--:-- – --:--,--:-- – --:--\"";
+
+  #[test]
+  fn from_str() {
+    let cycletime = CycleTime::from_bytes(&BYTES);
+    assert_eq!(cycletime.to_string(), STRING);
+    assert_eq!(STRING.parse::<CycleTime>().unwrap().to_bytes(), BYTES);
+
+    let cycletime = "05:30 – 08:00,16:00 – 24:00".parse::<CycleTime>().unwrap();
+    assert_eq!(cycletime.to_bytes(), [0x2b, 0x40, 0x80, 0xc0, 0xff, 0xff, 0xff, 0xff]);
+
+    let cycletime = "05:30-08:00, 16:00 - 24:00, --:-- - --:--,--:-----:--".parse::<CycleTime>().unwrap();
+    assert_eq!(cycletime.to_bytes(), [0x2b, 0x40, 0x80, 0xc0, 0xff, 0xff, 0xff, 0xff]);
+  }
+
+  #[test]
+  fn from_str_invalid() {
+    let message = |s: &str| match s.parse::<CycleTime>() {
+      Err(crate::Error::InvalidArgument(message)) => message,
+      result => panic!("expected invalid argument, found {:?}", result),
+    };
+
+    assert!(message("06:05 – 22:00").contains("multiple of 10 minutes"));
+    assert!(message("22:00 – 06:00").contains("ends before it starts"));
+    assert!(message("06:00 – 12:00,11:00 – 13:00").contains("overlaps"));
+    assert!("06:00 – --:--".parse::<CycleTime>().is_err());
+    assert!("25:00 – 26:00".parse::<CycleTime>().is_err());
+    assert!("06:00".parse::<CycleTime>().is_err());
+    assert!(message("06:00 - 22:0x").contains("invalid time span"));
+    assert!("a,b,c,d,e".parse::<CycleTime>().is_err());
+  }
+
+  #[test]
+  fn periods() {
+    let hm = |hh, mm| NaiveTime::from_hms_opt(hh, mm, 0).unwrap();
+
+    let cycletime = CycleTime::new(&[(hm(5, 30), hm(8, 0)), (hm(16, 0), hm(0, 0))]).unwrap();
+    assert_eq!(<[u8; 8]>::from(cycletime.clone()), [0x2b, 0x40, 0x80, 0xc0, 0xff, 0xff, 0xff, 0xff]);
+    assert_eq!(cycletime.periods(), [(hm(5, 30), hm(8, 0)), (hm(16, 0), hm(0, 0))]);
+    assert_eq!(cycletime.period(1), Some((hm(16, 0), hm(0, 0))));
+    assert_eq!(cycletime.period(2), None);
+
+    match CycleTime::new(&[(hm(5, 35), hm(8, 0))]) {
+      Err(crate::Error::InvalidArgument(message)) => assert!(message.contains("multiple of 10 minutes")),
+      result => panic!("expected invalid argument, found {:?}", result),
+    }
+    assert!(CycleTime::new(&[(hm(5, 0), hm(8, 0)), (hm(7, 0), hm(9, 0))]).is_err());
+    assert!(CycleTime::new(&[(hm(0, 0), hm(1, 0)); 5]).is_err());
+    match CycleTime::new(&[(NaiveTime::from_hms_opt(5, 0, 30).unwrap(), hm(8, 0))]) {
+      Err(crate::Error::InvalidArgument(message)) => assert_eq!(message, "invalid time 05:00:30, seconds must be zero"),
+      result => panic!("expected invalid argument, found {:?}", result),
+    }
+
+    assert!(CycleTime::empty().periods().is_empty());
+  }
+
+  #[test]
+  fn bytes() {
+    let bytes = [0xff, 0xff, 0x30, 0xb0, 0xff, 0xff, 0xff, 0xff];
+    assert_eq!(<[u8; 8]>::from(CycleTime::try_from(bytes).unwrap()), bytes);
+
+    assert!(matches!(CycleTime::try_from([0xb0, 0x30, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]), Err(crate::Error::InvalidArgument(_))));
+    assert!(CycleTime::try_from([0x30, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).is_err());
+  }
+
+  #[test]
+  fn json() {
+    let cycletime = CycleTime::from_bytes(&BYTES);
+
+    let json = serde_json::to_string(&cycletime).unwrap();
+    assert_eq!(serde_json::from_str::<CycleTime>(&json).unwrap().to_bytes(), BYTES);
+
+    let json = serde_json::to_string(&cycletime.to_string().trim_matches('"')).unwrap();
+    assert_eq!(serde_json::from_str::<CycleTime>(&json).unwrap().to_bytes(), BYTES);
+  }
+}
@@ -0,0 +1,245 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::ser::{Serialize, Serializer};
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+
+/// An exact decimal number, stored as an integer `mantissa` scaled by `10^-scale`.
+///
+/// Values read from commands with a `factor` of 10, 100, … are represented this way,
+/// so that e.g. a raw value of `213` with factor `10` is exactly `21.3`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decimal {
+  mantissa: i64,
+  scale: u32,
+}
+
+impl Decimal {
+  /// Creates the decimal `mantissa * 10^-scale`.
+  pub fn new(mantissa: i64, scale: u32) -> Decimal {
+    Decimal { mantissa, scale }
+  }
+
+  /// Returns the unscaled integer value.
+  pub fn mantissa(self) -> i64 {
+    self.mantissa
+  }
+
+  /// Returns the number of decimal places.
+  pub fn scale(self) -> u32 {
+    self.scale
+  }
+
+  /// Returns the nearest `f64`.
+  pub fn to_f64(self) -> f64 {
+    self.to_string().parse().unwrap()
+  }
+
+  /// Returns the decimal equal to `n` with the fewest decimal places, up to `max_scale`.
+  ///
+  /// Differences of a few units in the last place, e.g. `46.8 - 20.0 = 26.799999999999997`, are rounding
+  /// errors and ignored. Returns `None` if `n` is not a decimal with at most `max_scale` places, e.g. for `1.0 / 3.0`.
+  pub(crate) fn from_f64(n: f64, max_scale: u32) -> Option<Decimal> {
+    (0..=max_scale).find_map(|scale| {
+      let pow = 10f64.powi(scale as i32);
+      let mantissa = (n * pow).round();
+
+      // Beyond 2^53, not every integer is representable as an `f64`.
+      if mantissa.abs() < 9_007_199_254_740_992.0 && (mantissa / pow - n).abs() <= n.abs() * 4.0 * f64::EPSILON {
+        Some(Decimal::new(mantissa as i64, scale))
+      } else {
+        None
+      }
+    })
+  }
+
+  /// Converts a raw value into a decimal by dividing it by `factor`.
+  ///
+  /// For a factor of `10^k`, the result is exact with `k` decimal places, e.g. `213` with factor `10` is `21.3`.
+  /// For any other factor, the result is rounded to the resolution of the factor, i.e. as many decimal places
+  /// as the factor has integer digits, without trailing zeros, e.g. `9000` with factor `3600` is `2.5`.
+  pub(crate) fn from_raw(raw: i64, factor: f64) -> Decimal {
+    if let Some(scale) = Decimal::scale_of(factor) {
+      return Decimal::new(raw, scale)
+    }
+
+    let scale = (factor.abs().log10().ceil().max(0.0) as u32).min(9);
+    let mut decimal = Decimal::new((raw as f64 / factor * 10f64.powi(scale as i32)).round() as i64, scale);
+
+    while decimal.scale > 0 && decimal.mantissa % 10 == 0 {
+      decimal = Decimal::new(decimal.mantissa / 10, decimal.scale - 1);
+    }
+
+    decimal
+  }
+
+  /// Returns the scale `k` if `factor` is `10^k`.
+  pub(crate) fn scale_of(factor: f64) -> Option<u32> {
+    (0..=9).find(|&k| 10f64.powi(k as i32) == factor)
+  }
+
+  /// Returns the mantissa for the given scale, rounding half away from zero if decimals are dropped.
+  ///
+  /// Returns `None` on overflow.
+  pub(crate) fn rescale(self, scale: u32) -> Option<i64> {
+    if scale >= self.scale {
+      10i64.checked_pow(scale - self.scale).and_then(|f| self.mantissa.checked_mul(f))
+    } else {
+      let divisor = 10i64.checked_pow(self.scale - scale)?;
+      let (quotient, remainder) = (self.mantissa / divisor, self.mantissa % divisor);
+
+      Some(if remainder.abs() * 2 >= divisor { quotient + self.mantissa.signum() } else { quotient })
+    }
+  }
+}
+
+impl fmt::Display for Decimal {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if self.scale == 0 {
+      return write!(f, "{}", self.mantissa)
+    }
+
+    let digits = format!("{:0width$}", self.mantissa.unsigned_abs(), width = self.scale as usize + 1);
+    let (integer, fraction) = digits.split_at(digits.len() - self.scale as usize);
+
+    write!(f, "{}{}.{}", if self.mantissa < 0 { "-" } else { "" }, integer, fraction)
+  }
+}
+
+/// Parses a plain decimal number like `-21.30`, keeping the number of decimal places.
+impl FromStr for Decimal {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Decimal, Self::Err> {
+    let invalid = || format!("invalid decimal number {:?}", s);
+
+    let (negative, digits) = match s.as_bytes().first() {
+      Some(b'-') => (true, &s[1..]),
+      Some(b'+') => (false, &s[1..]),
+      _ => (false, s),
+    };
+
+    let (integer, fraction) = match digits.find('.') {
+      Some(i) => (&digits[..i], &digits[(i + 1)..]),
+      None => (digits, ""),
+    };
+
+    if integer.is_empty() || !integer.bytes().chain(fraction.bytes()).all(|b| b.is_ascii_digit()) || (digits.contains('.') && fraction.is_empty()) {
+      return Err(invalid())
+    }
+
+    let mantissa = format!("{}{}", integer, fraction).parse::<i64>().map_err(|_| invalid())?;
+
+    Ok(Decimal::new(if negative { -mantissa } else { mantissa }, fraction.len() as u32))
+  }
+}
+
+impl Serialize for Decimal {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    if self.scale == 0 {
+      serializer.serialize_i64(self.mantissa)
+    } else {
+      serializer.serialize_f64(self.to_f64())
+    }
+  }
+}
+
+impl<'de> Deserialize<'de> for Decimal {
+  fn deserialize<D>(deserializer: D) -> Result<Decimal, D::Error>
+  where
+      D: Deserializer<'de>,
+  {
+    struct DecimalVisitor;
+
+    impl<'de> Visitor<'de> for DecimalVisitor {
+      type Value = Decimal;
+
+      fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a decimal number")
+      }
+
+      fn visit_i64<E: de::Error>(self, v: i64) -> Result<Decimal, E> {
+        Ok(Decimal::new(v, 0))
+      }
+
+      fn visit_u64<E: de::Error>(self, v: u64) -> Result<Decimal, E> {
+        i64::try_from(v).map(|v| Decimal::new(v, 0)).map_err(E::custom)
+      }
+
+      fn visit_f64<E: de::Error>(self, v: f64) -> Result<Decimal, E> {
+        // The shortest representation which round-trips is the decimal the `f64` was parsed from.
+        v.to_string().parse().map_err(E::custom)
+      }
+
+      fn visit_str<E: de::Error>(self, v: &str) -> Result<Decimal, E> {
+        v.parse().map_err(E::custom)
+      }
+    }
+
+    deserializer.deserialize_any(DecimalVisitor)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn display() {
+    assert_eq!(Decimal::new(213, 1).to_string(), "21.3");
+    assert_eq!(Decimal::new(-42, 1).to_string(), "-4.2");
+    assert_eq!(Decimal::new(-5, 2).to_string(), "-0.05");
+    assert_eq!(Decimal::new(210, 1).to_string(), "21.0");
+    assert_eq!(Decimal::new(7, 0).to_string(), "7");
+  }
+
+  #[test]
+  fn from_str() {
+    assert_eq!("21.3".parse(), Ok(Decimal::new(213, 1)));
+    assert_eq!("-0.05".parse(), Ok(Decimal::new(-5, 2)));
+    assert_eq!("+7".parse(), Ok(Decimal::new(7, 0)));
+    assert!("21.".parse::<Decimal>().is_err());
+    assert!(".5".parse::<Decimal>().is_err());
+    assert!("1e3".parse::<Decimal>().is_err());
+    assert!("NaN".parse::<Decimal>().is_err());
+  }
+
+  #[test]
+  fn rescale() {
+    assert_eq!(Decimal::new(213, 1).rescale(1), Some(213));
+    assert_eq!(Decimal::new(213, 1).rescale(2), Some(2130));
+    assert_eq!(Decimal::new(2135, 2).rescale(1), Some(214));
+    assert_eq!(Decimal::new(-2135, 2).rescale(1), Some(-214));
+    assert_eq!(Decimal::new(2134, 2).rescale(1), Some(213));
+    assert_eq!(Decimal::new(i64::MAX, 0).rescale(1), None);
+  }
+
+  #[test]
+  fn from_raw() {
+    assert_eq!(Decimal::from_raw(213, 10.0), Decimal::new(213, 1));
+    assert_eq!(Decimal::from_raw(210, 10.0), Decimal::new(210, 1));
+    assert_eq!(Decimal::from_raw(7, 1.0), Decimal::new(7, 0));
+    assert_eq!(Decimal::from_raw(9000, 3600.0), Decimal::new(25, 1));
+    assert_eq!(Decimal::from_raw(100, 3600.0), Decimal::new(278, 4));
+    assert_eq!(Decimal::from_raw(-3, 0.5), Decimal::new(-6, 0));
+  }
+
+  #[test]
+  fn from_f64() {
+    assert_eq!(Decimal::from_f64(23.5, 9), Some(Decimal::new(235, 1)));
+    assert_eq!(Decimal::from_f64(21.0, 9), Some(Decimal::new(21, 0)));
+    assert_eq!(Decimal::from_f64(-0.05, 9), Some(Decimal::new(-5, 2)));
+    assert_eq!(Decimal::from_f64(46.8 - 20.0, 9), Some(Decimal::new(268, 1)));
+    assert_eq!(Decimal::from_f64(1.0 / 3.0, 9), None);
+    assert_eq!(Decimal::from_f64(f64::NAN, 9), None);
+  }
+
+  #[test]
+  fn json() {
+    assert_eq!(serde_json::to_string(&Decimal::new(213, 1)).unwrap(), "21.3");
+    assert_eq!(serde_json::to_string(&Decimal::new(-42, 0)).unwrap(), "-42");
+    assert_eq!(serde_json::from_str::<Decimal>("21.3").unwrap(), Decimal::new(213, 1));
+    assert_eq!(serde_json::from_str::<Decimal>("\"21.30\"").unwrap(), Decimal::new(2130, 2));
+  }
+}
@@ -46,7 +46,7 @@ macro_rules! to_bytes_le {
   ($t:ty, $n:ty) => {
     impl ToBytes for $t {
       fn to_bytes(&self) -> Vec<u8> {
-        unsafe { std::mem::transmute::<$t, $n>(self.to_le()) }.to_vec()
+        self.to_le_bytes().to_vec()
       }
     }
   };
@@ -64,7 +64,7 @@ to_bytes_le!(u32, [u8; 4]);
 
 macro_rules! byte_type {
   ($t:ident, $len:expr) => {
-    #[derive(Debug, Clone)]
+    #[derive(Debug, Clone, PartialEq, Eq)]
     pub struct $t([u8; $len]);
 
     impl $crate::FromBytes for $t {
@@ -89,6 +89,9 @@ pub use self::cycletime::CycleTime;
 mod systime;
 pub use self::systime::SysTime;
 
+mod decimal;
+pub use self::decimal::Decimal;
+
 #[derive(PartialEq, Eq, Hash)]
 pub(crate) enum Bytes {
   One([u8; 1]),
@@ -19,7 +19,10 @@ byte_type!(SysTime, 8);
 
 impl SysTime {
   pub fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> SysTime {
-    NaiveDate::from_ymd(year.into(), month.into(), day.into()).and_hms(hour.into(), minute.into(), second.into()).into()
+    NaiveDate::from_ymd_opt(year.into(), month.into(), day.into())
+      .and_then(|date| date.and_hms_opt(hour.into(), minute.into(), second.into()))
+      .expect("invalid date or time")
+      .into()
   }
 
   pub fn year(&self) -> u16 {
@@ -49,19 +52,26 @@ impl SysTime {
   pub fn second(&self) -> u8 {
     byte_to_dec(self.0[7])
   }
+
+  /// Returns whether the bytes represent a valid date and time.
+  pub(crate) fn is_valid(&self) -> bool {
+    NaiveDate::from_ymd_opt(self.year().into(), self.month().into(), self.day().into())
+      .and_then(|date| date.and_hms_opt(self.hour().into(), self.minute().into(), self.second().into()))
+      .is_some()
+  }
 }
 
 impl From<SysTime> for NaiveDateTime {
   fn from(systime: SysTime) -> NaiveDateTime {
-    NaiveDate::from_ymd(
+    NaiveDate::from_ymd_opt(
       systime.year().into(),
       systime.month().into(),
       systime.day().into(),
-    ).and_hms(
+    ).and_then(|date| date.and_hms_opt(
       systime.hour().into(),
       systime.minute().into(),
       systime.second().into(),
-    )
+    )).expect("invalid date or time")
   }
 }
 
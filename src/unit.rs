@@ -1,10 +1,12 @@
-use phf;
+use std::fmt;
+
 use serde::de::{self, Deserialize, Deserializer};
 
-use crate::{Error, Value, FromBytes, ToBytes, types::{Bytes, SysTime, CycleTime}};
+use crate::{Error, Value, FromBytes, ToBytes, command::Mapping, types::{CycleTime, Decimal, SysTime}};
 
-#[derive(Debug, Clone)]
-pub(crate) enum Unit {
+/// The type of a command's raw value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Unit {
   I8,
   I16,
   I32,
@@ -13,6 +15,27 @@ pub(crate) enum Unit {
   U32,
   SysTime,
   CycleTime,
+  /// A list of values, decoded from the `fields` of a command.
+  Array,
+  /// Named values, decoded from the `fields` of a command.
+  Map,
+}
+
+impl fmt::Display for Unit {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Unit::I8 => write!(f, "i8"),
+      Unit::I16 => write!(f, "i16"),
+      Unit::I32 => write!(f, "i32"),
+      Unit::U8 => write!(f, "u8"),
+      Unit::U16 => write!(f, "u16"),
+      Unit::U32 => write!(f, "u32"),
+      Unit::SysTime => write!(f, "systime"),
+      Unit::CycleTime => write!(f, "cycletime"),
+      Unit::Array => write!(f, "array"),
+      Unit::Map => write!(f, "map"),
+    }
+  }
 }
 
 impl<'de> Deserialize<'de> for Unit {
@@ -29,16 +52,35 @@ impl<'de> Deserialize<'de> for Unit {
       "u32" => Ok(Unit::U32),
       "systime" => Ok(Unit::SysTime),
       "cycletime" => Ok(Unit::CycleTime),
-      variant => Err(de::Error::unknown_variant(&variant, &["i8", "i16", "i32", "u8", "u16", "u32", "systime", "cycletime"])),
+      "array" => Ok(Unit::Array),
+      "map" => Ok(Unit::Map),
+      variant => Err(de::Error::unknown_variant(variant, &["i8", "i16", "i32", "u8", "u16", "u32", "systime", "cycletime", "array", "map"])),
     }
   }
 }
 
 impl Unit {
-  pub fn bytes_to_output(&self, bytes: &[u8], factor: f64, mapping: &Option<phf::map::Map<Bytes, &'static str>>) -> Result<Value, Error> {
+  /// Returns the size of the raw value in bytes.
+  ///
+  /// This is zero for `Array` and `Map`, whose size is determined by their fields.
+  pub fn size(&self) -> usize {
+    match self {
+      Unit::I8 => std::mem::size_of::<i8>(),
+      Unit::I16 => std::mem::size_of::<i16>(),
+      Unit::I32 => std::mem::size_of::<i32>(),
+      Unit::U8 => std::mem::size_of::<u8>(),
+      Unit::U16 => std::mem::size_of::<u16>(),
+      Unit::U32 => std::mem::size_of::<u32>(),
+      Unit::SysTime => std::mem::size_of::<SysTime>(),
+      Unit::CycleTime => std::mem::size_of::<CycleTime>(),
+      Unit::Array | Unit::Map => 0,
+    }
+  }
+
+  pub(crate) fn bytes_to_output(&self, bytes: &[u8], factor: f64, mapping: &Option<Mapping>) -> Result<Value, Error> {
     if let Some(mapping) = mapping {
-      if let Some(text) = mapping.get(&Bytes::from_bytes(bytes)) {
-        return Ok(Value::String((*text).to_string()))
+      if let Some(text) = mapping.get(bytes) {
+        return Ok(Value::String(text.to_string()))
       }
 
       return Err(Error::UnknownEnumVariant(format!("No enum mapping found for [{}].", bytes.iter().map(|byte| format!("0x{:02X}", byte)).collect::<Vec<String>>().join(", "))))
@@ -47,22 +89,17 @@ impl Unit {
     let n = match self {
       Unit::SysTime => return Ok(Value::SysTime(SysTime::from_bytes(bytes))),
       Unit::CycleTime => return Ok(Value::CycleTime(CycleTime::from_bytes(bytes))),
-      Unit::I8 => i64::from(i8::from_bytes(bytes).to_le()),
-      Unit::I16 => i64::from(i16::from_bytes(bytes).to_le()),
-      Unit::I32 => i64::from(i32::from_bytes(bytes).to_le()),
-      Unit::U8 => i64::from(u8::from_bytes(bytes).to_le()),
-      Unit::U16 => i64::from(u16::from_bytes(bytes).to_le()),
-      Unit::U32 => i64::from(u32::from_bytes(bytes).to_le()),
+      Unit::Array | Unit::Map => return Err(Error::InvalidArgument(format!("unit {} requires fields", self))),
+      _ => self.raw_value(bytes).unwrap(),
     };
 
-    Ok(Value::Number(n as f64 / factor))
+    Ok(Value::Decimal(Decimal::from_raw(n, factor)))
   }
 
-  pub fn input_to_bytes(&self, input: &Value, factor: f64, mapping: &Option<phf::map::Map<Bytes, &'static str>>) -> Result<Vec<u8>, Error> {
+  pub(crate) fn input_to_bytes(&self, input: &Value, factor: f64, mapping: &Option<Mapping>) -> Result<Vec<u8>, Error> {
     if let Some(mapping) = mapping {
       if let Value::String(s) = input {
-        return mapping.entries()
-                 .find_map(|(key, value)| if value == s { Some(key.to_bytes()) } else { None })
+        return mapping.key(s)
                  .ok_or_else(|| Error::InvalidArgument(format!("no mapping found for {:?}", s)))
       } else {
         return Err(Error::InvalidArgument(format!("expected string, found {:?}", input)))
@@ -70,37 +107,152 @@ impl Unit {
     }
 
     Ok(match self {
-      Unit::SysTime => {
-        if let Value::SysTime(systime) = input {
-          systime.to_bytes()
-        } else {
-          return Err(Error::InvalidArgument(format!("expected systime, found {:?}", input)))
-        }
+      Unit::SysTime => match input {
+        Value::SysTime(systime) => systime.to_bytes(),
+        Value::String(s) => s.parse::<SysTime>().map_err(|err| Error::InvalidArgument(format!("invalid systime {:?}: {}", s, err)))?.to_bytes(),
+        _ => return Err(Error::InvalidArgument(format!("expected systime, found {:?}", input))),
       },
-      Unit::CycleTime => {
-        if let Value::CycleTime(cycletime) = input {
-          cycletime.to_bytes()
-        } else {
-          return Err(Error::InvalidArgument(format!("expected cycletime, found {:?}", input)))
-        }
+      Unit::CycleTime => match input {
+        Value::CycleTime(cycletime) => cycletime.to_bytes(),
+        Value::String(s) => s.parse::<CycleTime>()?.to_bytes(),
+        _ => return Err(Error::InvalidArgument(format!("expected cycletime, found {:?}", input))),
       },
+      Unit::Array | Unit::Map => return Err(Error::InvalidArgument(format!("unit {} requires fields", self))),
       _ => {
-        if let Value::Number(n) = input {
-          let n = n * factor;
-
-          match self {
-            Unit::I8  => (n as i8).to_bytes(),
-            Unit::I16 => (n as i16).to_bytes(),
-            Unit::I32 => (n as i32).to_bytes(),
-            Unit::U8  => (n as u8).to_bytes(),
-            Unit::U16 => (n as u16).to_bytes(),
-            Unit::U32 => (n as u32).to_bytes(),
-            _ => unreachable!(),
-          }
-        } else {
-          return Err(Error::InvalidArgument(format!("expected number, found {:?}", input)))
+        let raw = match *input {
+          Value::Number(n) => self.checked_raw(n, factor)?,
+          Value::Decimal(decimal) => match Decimal::scale_of(factor) {
+            Some(scale) => self.checked_decimal_raw(decimal, scale, factor)?,
+            None => self.checked_raw(decimal.to_f64(), factor)?,
+          },
+          _ => return Err(Error::InvalidArgument(format!("expected number, found {:?}", input))),
+        };
+
+        match self {
+          Unit::I8  => (raw as i8).to_bytes(),
+          Unit::I16 => (raw as i16).to_bytes(),
+          Unit::I32 => (raw as i32).to_bytes(),
+          Unit::U8  => (raw as u8).to_bytes(),
+          Unit::U16 => (raw as u16).to_bytes(),
+          Unit::U32 => (raw as u32).to_bytes(),
+          _ => unreachable!(),
         }
       },
     })
   }
+
+  /// Returns the raw value of a numeric unit.
+  pub(crate) fn raw_value(&self, bytes: &[u8]) -> Option<i64> {
+    Some(match self {
+      Unit::I8 => i64::from(i8::from_bytes(bytes).to_le()),
+      Unit::I16 => i64::from(i16::from_bytes(bytes).to_le()),
+      Unit::I32 => i64::from(i32::from_bytes(bytes).to_le()),
+      Unit::U8 => i64::from(u8::from_bytes(bytes).to_le()),
+      Unit::U16 => i64::from(u16::from_bytes(bytes).to_le()),
+      Unit::U32 => i64::from(u32::from_bytes(bytes).to_le()),
+      _ => return None,
+    })
+  }
+
+  /// Returns the smallest and largest raw value of a numeric unit.
+  fn raw_range(&self) -> Option<(i64, i64)> {
+    Some(match self {
+      Unit::I8  => (i8::MIN.into(), i8::MAX.into()),
+      Unit::I16 => (i16::MIN.into(), i16::MAX.into()),
+      Unit::I32 => (i32::MIN.into(), i32::MAX.into()),
+      Unit::U8  => (u8::MIN.into(), u8::MAX.into()),
+      Unit::U16 => (u16::MIN.into(), u16::MAX.into()),
+      Unit::U32 => (u32::MIN.into(), u32::MAX.into()),
+      _ => return None,
+    })
+  }
+
+  /// Converts a number into its raw value by applying the factor and rounding half away from zero.
+  ///
+  /// Non-finite numbers and numbers which do not fit into the unit are rejected.
+  fn checked_raw(&self, n: f64, factor: f64) -> Result<i64, Error> {
+    let (min, max) = self.raw_range().ok_or_else(|| Error::InvalidArgument(format!("unit {} is not numeric", self)))?;
+
+    let raw = (n * factor).round();
+
+    if !raw.is_finite() {
+      return Err(Error::InvalidArgument(format!("value {} is not a finite number", n)))
+    }
+
+    if raw < min as f64 || raw > max as f64 {
+      let (lower, upper) = (min as f64 / factor, max as f64 / factor);
+      let (lower, upper) = if lower <= upper { (lower, upper) } else { (upper, lower) };
+
+      return Err(Error::InvalidArgument(format!("value {} is outside of the range {} to {} for unit {}", n, lower, upper, self)))
+    }
+
+    Ok(raw as i64)
+  }
+
+  /// Converts a decimal into its raw value for a factor of `10^scale` without going through `f64`.
+  fn checked_decimal_raw(&self, decimal: Decimal, scale: u32, factor: f64) -> Result<i64, Error> {
+    let (min, max) = self.raw_range().ok_or_else(|| Error::InvalidArgument(format!("unit {} is not numeric", self)))?;
+
+    match decimal.rescale(scale) {
+      Some(raw) if raw >= min && raw <= max => Ok(raw),
+      _ => Err(Error::InvalidArgument(format!(
+        "value {} is outside of the range {} to {} for unit {}", decimal, min as f64 / factor, max as f64 / factor, self,
+      ))),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn encode(unit: Unit, n: f64, factor: f64) -> Result<Vec<u8>, Error> {
+    unit.input_to_bytes(&Value::Number(n), factor, &None)
+  }
+
+  fn encode_decimal(unit: Unit, s: &str, factor: f64) -> Result<Vec<u8>, Error> {
+    unit.input_to_bytes(&Value::Decimal(s.parse().unwrap()), factor, &None)
+  }
+
+  #[test]
+  fn rounding() {
+    assert_eq!(encode(Unit::U8, 21.5, 1.0).unwrap(), [22]);
+    assert_eq!(encode(Unit::U8, 21.49, 1.0).unwrap(), [21]);
+    assert_eq!(encode(Unit::I16, -21.25, 10.0).unwrap(), (-213i16).to_le_bytes());
+    assert_eq!(encode(Unit::I16, 0.1 * 3.0, 10.0).unwrap(), 3i16.to_le_bytes());
+    assert_eq!(encode(Unit::U8, -0.4, 1.0).unwrap(), [0]);
+  }
+
+  #[test]
+  fn decimal() {
+    let bytes = encode_decimal(Unit::I16, "21.3", 10.0).unwrap();
+    assert_eq!(bytes, 213i16.to_le_bytes());
+
+    match Unit::I16.bytes_to_output(&bytes, 10.0, &None).unwrap() {
+      Value::Decimal(decimal) => assert_eq!(decimal.to_string(), "21.3"),
+      value => panic!("expected decimal, found {:?}", value),
+    }
+
+    assert_eq!(encode_decimal(Unit::I16, "21.35", 10.0).unwrap(), 214i16.to_le_bytes());
+    assert_eq!(encode_decimal(Unit::U32, "2.5", 3600.0).unwrap(), 9000u32.to_le_bytes());
+    assert!(encode_decimal(Unit::I16, "3276.8", 10.0).is_err());
+    assert!(matches!(Unit::U8.bytes_to_output(&[7], 1.0, &None).unwrap(), Value::Decimal(d) if d == Decimal::new(7, 0)));
+    assert!(matches!(Unit::U32.bytes_to_output(&9000u32.to_le_bytes(), 3600.0, &None).unwrap(), Value::Decimal(d) if d == Decimal::new(25, 1)));
+  }
+
+  #[test]
+  fn out_of_range() {
+    match encode(Unit::U8, 300.0, 1.0) {
+      Err(Error::InvalidArgument(message)) => assert_eq!(message, "value 300 is outside of the range 0 to 255 for unit u8"),
+      result => panic!("expected invalid argument, found {:?}", result),
+    }
+
+    assert!(encode(Unit::U8, -5.0, 1.0).is_err());
+    assert!(encode(Unit::I8, 128.0, 1.0).is_err());
+    assert!(encode(Unit::I16, 3276.8, 10.0).is_err());
+    assert!(encode(Unit::U32, 4294967295.0, 1.0).is_ok());
+    assert!(encode(Unit::U32, 4294967296.0, 1.0).is_err());
+    assert!(encode(Unit::I32, f64::NAN, 1.0).is_err());
+    assert!(encode(Unit::I32, f64::INFINITY, 1.0).is_err());
+  }
 }
@@ -1,25 +1,43 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
 use std::str::FromStr;
 
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
 use serde_derive::*;
 
-use crate::types::{SysTime, CycleTime};
+use crate::types::{CycleTime, Decimal, SysTime};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize)]
 #[serde(untagged)]
 pub enum Value {
   Number(f64),
+  /// An exact decimal number, e.g. read from a command with a `factor` of 10.
+  Decimal(Decimal),
   SysTime(SysTime),
   CycleTime(CycleTime),
   String(String),
+  /// A list of values, e.g. read from a command with unit `array`.
+  Array(Vec<Value>),
+  /// Named values, e.g. read from a command with unit `map`.
+  Map(BTreeMap<String, Value>),
 }
 
 #[derive(Debug)]
 pub enum Never {}
 
+/// Parses a decimal number like `21` or `21.3` exactly, any other number,
+/// a `SysTime`, a `CycleTime`, an `Array` or a `Map`, falling back to a `String`.
+///
+/// Arrays and maps use JSON syntax, e.g. `[1, "ON"]` or `{"code": 16}`.
 impl FromStr for Value {
   type Err = Never;
 
   fn from_str(s: &str) -> Result<Value, Self::Err> {
+    if let Ok(decimal) = s.parse::<Decimal>() {
+      return Ok(Value::Decimal(decimal))
+    }
+
     if let Ok(number) = s.parse::<f64>() {
       return Ok(Value::Number(number))
     }
@@ -32,6 +50,244 @@ impl FromStr for Value {
       return Ok(Value::CycleTime(cycletime))
     }
 
+    if s.starts_with('[') || s.starts_with('{') {
+      if let Ok(value @ Value::Array(_)) | Ok(value @ Value::Map(_)) = serde_yaml::from_str(s) {
+        return Ok(value)
+      }
+    }
+
     Ok(Value::String(s.to_owned()))
   }
 }
+
+/// Deserializes numbers as a `Decimal`, or a `Number` if they cannot be represented exactly,
+/// strings as a `SysTime` or `CycleTime` if they are one and as a `String` otherwise, lists as
+/// an `Array` and maps as a `Map`.
+///
+/// Lists of time spans in the format serialized for a `CycleTime` are deserialized as a `CycleTime`,
+/// so the output of reading a command can be written again.
+impl<'de> Deserialize<'de> for Value {
+  fn deserialize<D>(deserializer: D) -> Result<Value, D::Error>
+  where
+      D: Deserializer<'de>,
+  {
+    struct ValueVisitor;
+
+    impl<'de> Visitor<'de> for ValueVisitor {
+      type Value = Value;
+
+      fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a number, a string, a list or a map")
+      }
+
+      fn visit_i64<E: de::Error>(self, v: i64) -> Result<Value, E> {
+        Ok(Value::Decimal(Decimal::new(v, 0)))
+      }
+
+      fn visit_u64<E: de::Error>(self, v: u64) -> Result<Value, E> {
+        match i64::try_from(v) {
+          Ok(v) => self.visit_i64(v),
+          Err(_) => Ok(Value::Number(v as f64)),
+        }
+      }
+
+      fn visit_f64<E: de::Error>(self, v: f64) -> Result<Value, E> {
+        // The shortest representation which round-trips is the decimal the `f64` was parsed from.
+        match v.to_string().parse() {
+          Ok(decimal) => Ok(Value::Decimal(decimal)),
+          Err(_) => Ok(Value::Number(v)),
+        }
+      }
+
+      fn visit_str<E: de::Error>(self, v: &str) -> Result<Value, E> {
+        self.visit_string(v.to_owned())
+      }
+
+      fn visit_string<E: de::Error>(self, v: String) -> Result<Value, E> {
+        if let Ok(systime) = v.parse::<SysTime>() {
+          return Ok(Value::SysTime(systime))
+        }
+
+        if let Ok(cycletime) = v.parse::<CycleTime>() {
+          return Ok(Value::CycleTime(cycletime))
+        }
+
+        Ok(Value::String(v))
+      }
+
+      fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut values = Vec::new();
+
+        while let Some(value) = seq.next_element()? {
+          values.push(value);
+        }
+
+        Ok(cycletime(&values).map(Value::CycleTime).unwrap_or(Value::Array(values)))
+      }
+
+      fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut values = BTreeMap::new();
+
+        while let Some((key, value)) = map.next_entry()? {
+          values.insert(key, value);
+        }
+
+        Ok(Value::Map(values))
+      }
+    }
+
+    deserializer.deserialize_any(ValueVisitor)
+  }
+}
+
+/// Parses a list of `{"from": {"hh": …, "mm": …}, "to": …}` time spans as serialized for a `CycleTime`.
+fn cycletime(values: &[Value]) -> Option<CycleTime> {
+  if values.is_empty() || values.len() > CycleTime::MAX_PERIODS {
+    return None
+  }
+
+  let time = |timespan: &BTreeMap<String, Value>, key: &str| match timespan.get(key) {
+    Some(Value::Map(time)) => match (time.get("hh"), time.get("mm")) {
+      (Some(Value::String(hh)), Some(Value::String(mm))) => Some(format!("{}:{}", hh, mm)),
+      _ => None,
+    },
+    _ => None,
+  };
+
+  let timespans = values.iter().map(|value| match value {
+    Value::Map(timespan) => Some(format!("{} – {}", time(timespan, "from")?, time(timespan, "to")?)),
+    _ => None,
+  }).collect::<Option<Vec<_>>>()?;
+
+  timespans.join(",").parse().ok()
+}
+
+impl Value {
+  /// Formats a value nested inside an `Array` or a `Map`, quoting strings so the result can be parsed again.
+  fn fmt_nested(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Value::SysTime(systime) => write!(f, "\"{}\"", systime),
+      Value::String(string) => write!(f, "{:?}", string),
+      value => write!(f, "{}", value),
+    }
+  }
+}
+
+impl fmt::Display for Value {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Value::Number(number) => write!(f, "{}", number),
+      Value::Decimal(decimal) => write!(f, "{}", decimal),
+      Value::SysTime(systime) => write!(f, "{}", systime),
+      Value::CycleTime(cycletime) => write!(f, "{}", cycletime),
+      Value::String(string) => write!(f, "{}", string),
+      Value::Array(values) => {
+        write!(f, "[")?;
+
+        for (i, value) in values.iter().enumerate() {
+          if i > 0 {
+            write!(f, ", ")?;
+          }
+
+          value.fmt_nested(f)?;
+        }
+
+        write!(f, "]")
+      },
+      Value::Map(values) => {
+        write!(f, "{{")?;
+
+        for (i, (key, value)) in values.iter().enumerate() {
+          if i > 0 {
+            write!(f, ", ")?;
+          }
+
+          write!(f, "{:?}: ", key)?;
+          value.fmt_nested(f)?;
+        }
+
+        write!(f, "}}")
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn from_str() {
+    assert!(matches!("21".parse().unwrap(), Value::Decimal(d) if d == Decimal::new(21, 0)));
+    assert!(matches!("21.3".parse().unwrap(), Value::Decimal(d) if d == Decimal::new(213, 1)));
+    assert!(matches!("1e3".parse().unwrap(), Value::Number(n) if n == 1000.0));
+    assert!(matches!("2018-12-23T17:49:31".parse().unwrap(), Value::SysTime(_)));
+    assert!(matches!("06:00 – 22:00".parse().unwrap(), Value::CycleTime(_)));
+    assert!(matches!("ON".parse().unwrap(), Value::String(s) if s == "ON"));
+    assert!(matches!("[ON".parse().unwrap(), Value::String(s) if s == "[ON"));
+
+    match "[1, \"ON\", [2]]".parse().unwrap() {
+      Value::Array(values) => {
+        assert_eq!(values.len(), 3);
+        assert!(matches!(&values[1], Value::String(s) if s == "ON"));
+        assert!(matches!(&values[2], Value::Array(values) if values.len() == 1));
+      },
+      value => panic!("expected array, found {:?}", value),
+    }
+
+    match "{\"code\": 16, \"time\": \"2018-12-23T17:49:31\"}".parse().unwrap() {
+      Value::Map(values) => {
+        assert!(matches!(values["code"], Value::Decimal(d) if d == Decimal::new(16, 0)));
+        assert!(matches!(values["time"], Value::SysTime(_)));
+      },
+      value => panic!("expected map, found {:?}", value),
+    }
+  }
+
+  #[test]
+  fn display() {
+    let value = "{\"code\": 16, \"flags\": [1, \"ON\"], \"time\": \"2018-12-23T17:49:31\"}".parse::<Value>().unwrap();
+
+    let string = value.to_string();
+    assert_eq!(string, "{\"code\": 16, \"flags\": [1, \"ON\"], \"time\": \"2018-12-23T17:49:31\"}");
+    assert_eq!(string.parse::<Value>().unwrap().to_string(), string);
+  }
+
+  #[test]
+  fn json() {
+    let value = "{\"code\": 16, \"flags\": [1, \"ON\"]}".parse::<Value>().unwrap();
+
+    let json = serde_json::to_string(&value).unwrap();
+    assert_eq!(json, r#"{"code":16,"flags":[1,"ON"]}"#);
+    assert_eq!(serde_json::to_string(&Value::Decimal(Decimal::new(213, 1))).unwrap(), "21.3");
+    assert_eq!(serde_json::from_str::<Value>(&json).unwrap().to_string(), value.to_string());
+  }
+
+  #[test]
+  fn serde_round_trip() {
+    let round_trip = |json: &str| serde_json::to_string(&serde_json::from_str::<Value>(json).unwrap()).unwrap();
+
+    for json in &["21.3", "-0.05", "16", "\"21.3\"", "\"2018-12-23T17:49:31\"", r#"[1,"ON",[2.5]]"#, r#"{"code":16,"time":"2018-12-23T17:49:31"}"#] {
+      assert_eq!(round_trip(json), *json);
+    }
+
+    assert!(matches!(serde_json::from_str("21.3").unwrap(), Value::Decimal(d) if d == Decimal::new(213, 1)));
+    assert!(matches!(serde_json::from_str("1e300").unwrap(), Value::Number(n) if n == 1e300));
+    assert!(matches!(serde_json::from_str("\"21.3\"").unwrap(), Value::String(s) if s == "21.3"));
+    assert!(serde_json::from_str::<Value>("true").is_err());
+
+    assert!(matches!(serde_json::from_str("\"2018-12-23T17:49:31\"").unwrap(), Value::SysTime(_)));
+    assert!(matches!(serde_json::from_str("\"06:00 - 22:00\"").unwrap(), Value::CycleTime(_)));
+
+    let cycletime = "05:30 – 08:00,16:00 – 24:00".parse::<CycleTime>().unwrap();
+    let json = serde_json::to_string(&Value::CycleTime(cycletime.clone())).unwrap();
+    match serde_json::from_str(&json).unwrap() {
+      Value::CycleTime(deserialized) => assert_eq!(deserialized.to_string(), cycletime.to_string()),
+      value => panic!("expected cycletime, found {:?}", value),
+    }
+
+    let value = Value::Number(21.5);
+    assert_eq!(serde_json::to_string(&value).unwrap(), "21.5");
+    assert!(matches!(serde_json::from_str(&serde_json::to_string(&value).unwrap()).unwrap(), Value::Decimal(d) if d == Decimal::new(215, 1)));
+  }
+}
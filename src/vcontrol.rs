@@ -1,23 +1,39 @@
-use crate::{Error, Optolink, Device, Protocol, Value};
+use std::fmt;
+
+use crate::{Error, ErrorHistory, Optolink, Device, ProtocolKind, Value, WeeklySchedule};
+#[cfg(feature = "async")]
+use crate::AsyncOptolink;
 
 #[derive(Debug)]
 pub struct VControl<D: Device> {
   device: Optolink,
-  phantom: std::marker::PhantomData<D>,
+  config: D,
+}
+
+impl<D: Device + Default> VControl<D> {
+  pub fn connect(device: Optolink) -> Result<VControl<D>, Error> {
+    Self::connect_with(device, D::default())
+  }
 }
 
 impl<D: Device> VControl<D> {
-  pub fn connect(mut device: Optolink) -> Result<VControl<D>, Error> {
-    D::Protocol::negotiate(&mut device)?;
-    Ok(VControl { device, phantom: std::marker::PhantomData })
+  /// Connects using the given device definition, e.g. a runtime-loaded [`Configuration`](crate::Configuration).
+  pub fn connect_with(mut device: Optolink, config: D) -> Result<VControl<D>, Error> {
+    config.protocol().negotiate(&mut device)?;
+    Ok(VControl { device, config })
+  }
+
+  /// Returns the device definition, e.g. to inspect its commands.
+  pub fn device(&self) -> &D {
+    &self.config
   }
 
   /// Gets the value for the given command.
   ///
-  /// If the command specified is not available, an IO error of the kind `AddrNotAvailable` is returned.
+  /// If the command specified is not available, [`Error::UnsupportedCommand`] is returned.
   pub fn get(&mut self, command: &str) -> Result<Value, Error> {
-    if let Some(command) = D::command(command) {
-      command.get::<D::Protocol>(&mut self.device)
+    if let Some(command) = self.config.command(command) {
+      command.get(&mut self.device, self.config.protocol())
     } else {
       Err(Error::UnsupportedCommand(command.to_owned()))
     }
@@ -25,12 +41,262 @@ impl<D: Device> VControl<D> {
 
   /// Sets the value for the given command.
   ///
-  /// If the command specified is not available, an IO error of the kind `AddrNotAvailable` is returned.
+  /// If the command specified is not available, [`Error::UnsupportedCommand`] is returned.
   pub fn set(&mut self, command: &str, input: &Value) -> Result<(), Error> {
-    if let Some(command) = D::command(command) {
-      command.set::<D::Protocol>(&mut self.device, input)
+    if let Some(command) = self.config.command(command) {
+      command.set(&mut self.device, self.config.protocol(), input)
     } else {
       Err(Error::UnsupportedCommand(command.to_owned()))
     }
   }
+
+  /// Gets the switching times for every day of the given timer program, e.g. `hotwater`.
+  pub fn get_schedule(&mut self, program: &str) -> Result<WeeklySchedule, Error> {
+    let mut schedule = WeeklySchedule::default();
+
+    for (weekday, command) in WeeklySchedule::commands(program) {
+      let value = self.get(&command)?;
+      schedule.set_day(weekday, WeeklySchedule::cycletime(&command, value)?);
+    }
+
+    Ok(schedule)
+  }
+
+  /// Sets the switching times for every day of the given timer program, e.g. `hotwater`.
+  pub fn set_schedule(&mut self, program: &str, schedule: &WeeklySchedule) -> Result<(), Error> {
+    for (weekday, command) in WeeklySchedule::commands(program) {
+      self.set(&command, &Value::CycleTime(schedule.day(weekday).clone()))?;
+    }
+
+    Ok(())
+  }
+
+  /// Gets the error history, reading every entry's block only once.
+  pub fn get_error_history(&mut self) -> Result<ErrorHistory, Error> {
+    let mut history = ErrorHistory::default();
+
+    for (state, time) in ErrorHistory::slots(&self.config)? {
+      let block = state.read_block(&mut self.device, self.config.protocol())?;
+      history.push(state, time, block)?;
+    }
+
+    Ok(history)
+  }
+}
+
+/// A [`VControl`] for a device type which is only known at runtime.
+///
+/// This is returned by [`connect`].
+pub trait DynVControl: fmt::Debug + Send {
+  /// Returns the names of all commands supported by the device.
+  fn commands(&self) -> Vec<&str>;
+
+  /// Returns the device definition, e.g. to inspect its commands.
+  fn device(&self) -> &dyn Device;
+
+  /// Gets the value for the given command.
+  fn get(&mut self, command: &str) -> Result<Value, Error>;
+
+  /// Sets the value for the given command.
+  fn set(&mut self, command: &str, input: &Value) -> Result<(), Error>;
+
+  /// Gets the switching times for every day of the given timer program.
+  fn get_schedule(&mut self, program: &str) -> Result<WeeklySchedule, Error>;
+
+  /// Sets the switching times for every day of the given timer program.
+  fn set_schedule(&mut self, program: &str, schedule: &WeeklySchedule) -> Result<(), Error>;
+
+  /// Gets the error history.
+  fn get_error_history(&mut self) -> Result<ErrorHistory, Error>;
+}
+
+impl<D: Device + fmt::Debug + Send> DynVControl for VControl<D> {
+  fn commands(&self) -> Vec<&str> {
+    self.config.commands()
+  }
+
+  fn device(&self) -> &dyn Device {
+    &self.config
+  }
+
+  fn get(&mut self, command: &str) -> Result<Value, Error> {
+    VControl::get(self, command)
+  }
+
+  fn set(&mut self, command: &str, input: &Value) -> Result<(), Error> {
+    VControl::set(self, command, input)
+  }
+
+  fn get_schedule(&mut self, program: &str) -> Result<WeeklySchedule, Error> {
+    VControl::get_schedule(self, program)
+  }
+
+  fn set_schedule(&mut self, program: &str, schedule: &WeeklySchedule) -> Result<(), Error> {
+    VControl::set_schedule(self, program, schedule)
+  }
+
+  fn get_error_history(&mut self) -> Result<ErrorHistory, Error> {
+    VControl::get_error_history(self)
+  }
+}
+
+const SYSTEM_TYPE_ADDR: [u8; 2] = [0x00, 0xF8];
+
+/// Connects to a device of unknown type.
+///
+/// The protocol is detected automatically and the device type is identified by
+/// reading the system identification bytes at address `0x00F8`. If no matching
+/// device type is found, an `Error::UnknownDevice` with the ID is returned.
+///
+/// # Examples
+///
+/// ```no_run
+/// use vcontrol::Optolink;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut vcontrol = vcontrol::connect(Optolink::open("/dev/ttyUSB0")?)?;
+/// println!("{:?}", vcontrol.get("outside_temp_actual")?);
+/// # Ok(())
+/// # }
+/// ```
+pub fn connect(mut device: Optolink) -> Result<Box<dyn DynVControl>, Error> {
+  let protocol = device.detect_protocol()?;
+  identify(device, protocol)
+}
+
+/// Connects to a device of unknown type which uses the given protocol.
+///
+/// This is like [`connect`], but skips detecting the protocol.
+pub fn connect_with_protocol(mut device: Optolink, protocol: ProtocolKind) -> Result<Box<dyn DynVControl>, Error> {
+  protocol.negotiate(&mut device)?;
+  identify(device, protocol)
+}
+
+fn identify(mut device: Optolink, protocol: ProtocolKind) -> Result<Box<dyn DynVControl>, Error> {
+  let mut id = [0; 2];
+  protocol.get(&mut device, &SYSTEM_TYPE_ADDR, &mut id)?;
+
+  log::debug!("identify(…) id = {:02X?}", id);
+
+  crate::device::identify(&id)?.connect(device)
+}
+
+/// The asynchronous counterpart of [`connect`].
+///
+/// # Examples
+///
+/// ```no_run
+/// use vcontrol::AsyncOptolink;
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut vcontrol = vcontrol::connect_async(AsyncOptolink::open("/dev/ttyUSB0")?).await?;
+/// println!("{:?}", vcontrol.get("outside_temp_actual").await?);
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "async")]
+pub async fn connect_async(mut device: AsyncOptolink) -> Result<AsyncVControl<&'static (dyn Device + Sync)>, Error> {
+  let protocol = device.detect_protocol().await?;
+  identify_async(device, protocol).await
+}
+
+/// The asynchronous counterpart of [`connect_with_protocol`].
+#[cfg(feature = "async")]
+pub async fn connect_async_with_protocol(mut device: AsyncOptolink, protocol: ProtocolKind) -> Result<AsyncVControl<&'static (dyn Device + Sync)>, Error> {
+  protocol.negotiate_async(&mut device).await?;
+  identify_async(device, protocol).await
+}
+
+#[cfg(feature = "async")]
+async fn identify_async(mut device: AsyncOptolink, protocol: ProtocolKind) -> Result<AsyncVControl<&'static (dyn Device + Sync)>, Error> {
+  let mut id = [0; 2];
+  protocol.get_async(&mut device, &SYSTEM_TYPE_ADDR, &mut id).await?;
+
+  log::debug!("identify_async(…) id = {:02X?}", id);
+
+  AsyncVControl::connect_with(device, crate::device::identify(&id)?.device()).await
+}
+
+/// The asynchronous counterpart of [`VControl`].
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct AsyncVControl<D: Device> {
+  device: AsyncOptolink,
+  config: D,
+}
+
+#[cfg(feature = "async")]
+impl<D: Device + Default> AsyncVControl<D> {
+  pub async fn connect(device: AsyncOptolink) -> Result<AsyncVControl<D>, Error> {
+    Self::connect_with(device, D::default()).await
+  }
+}
+
+#[cfg(feature = "async")]
+impl<D: Device> AsyncVControl<D> {
+  /// Connects using the given device definition, e.g. a runtime-loaded [`Configuration`](crate::Configuration).
+  pub async fn connect_with(mut device: AsyncOptolink, config: D) -> Result<AsyncVControl<D>, Error> {
+    config.protocol().negotiate_async(&mut device).await?;
+    Ok(AsyncVControl { device, config })
+  }
+
+  /// Returns the device definition, e.g. to inspect its commands.
+  pub fn device(&self) -> &D {
+    &self.config
+  }
+
+  /// Gets the value for the given command.
+  ///
+  /// If the command specified is not available, [`Error::UnsupportedCommand`] is returned.
+  pub async fn get(&mut self, command: &str) -> Result<Value, Error> {
+    if let Some(command) = self.config.command(command) {
+      command.get_async(&mut self.device, self.config.protocol()).await
+    } else {
+      Err(Error::UnsupportedCommand(command.to_owned()))
+    }
+  }
+
+  /// Sets the value for the given command.
+  ///
+  /// If the command specified is not available, [`Error::UnsupportedCommand`] is returned.
+  pub async fn set(&mut self, command: &str, input: &Value) -> Result<(), Error> {
+    if let Some(command) = self.config.command(command) {
+      command.set_async(&mut self.device, self.config.protocol(), input).await
+    } else {
+      Err(Error::UnsupportedCommand(command.to_owned()))
+    }
+  }
+
+  /// Gets the switching times for every day of the given timer program, e.g. `hotwater`.
+  pub async fn get_schedule(&mut self, program: &str) -> Result<WeeklySchedule, Error> {
+    let mut schedule = WeeklySchedule::default();
+
+    for (weekday, command) in WeeklySchedule::commands(program) {
+      let value = self.get(&command).await?;
+      schedule.set_day(weekday, WeeklySchedule::cycletime(&command, value)?);
+    }
+
+    Ok(schedule)
+  }
+
+  /// Sets the switching times for every day of the given timer program, e.g. `hotwater`.
+  pub async fn set_schedule(&mut self, program: &str, schedule: &WeeklySchedule) -> Result<(), Error> {
+    for (weekday, command) in WeeklySchedule::commands(program) {
+      self.set(&command, &Value::CycleTime(schedule.day(weekday).clone())).await?;
+    }
+
+    Ok(())
+  }
+
+  /// Gets the error history, reading every entry's block only once.
+  pub async fn get_error_history(&mut self) -> Result<ErrorHistory, Error> {
+    let mut history = ErrorHistory::default();
+
+    for (state, time) in ErrorHistory::slots(&self.config)? {
+      let block = state.read_block_async(&mut self.device, self.config.protocol()).await?;
+      history.push(state, time, block)?;
+    }
+
+    Ok(history)
+  }
 }
@@ -0,0 +1,7 @@
+//! Conversion from and to [`vcontrold`](https://github.com/openv/vcontrold) XML configurations.
+
+mod import;
+pub use self::import::{import, Import};
+
+mod export;
+pub use self::export::{export, Export};
@@ -0,0 +1,423 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::{Command, Device, ProtocolKind, Unit, expression::Expression};
+
+/// The result of [`export`]ing a device definition.
+#[derive(Debug)]
+pub struct Export {
+  units: String,
+  vito: String,
+  warnings: Vec<String>,
+}
+
+impl Export {
+  /// Returns the `<units>` element to be included in `vcontrold.xml`.
+  pub fn units(&self) -> &str {
+    &self.units
+  }
+
+  /// Returns the `vito.xml` document containing the device and its commands.
+  pub fn vito(&self) -> &str {
+    &self.vito
+  }
+
+  /// Returns a description of every command which could not be exported.
+  pub fn warnings(&self) -> &[String] {
+    &self.warnings
+  }
+}
+
+fn escape(s: &str) -> String {
+  s.replace('&', "&amp;")
+   .replace('<', "&lt;")
+   .replace('>', "&gt;")
+   .replace('"', "&quot;")
+   .replace('\'', "&apos;")
+}
+
+fn hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(" ")
+}
+
+/// Converts a command name like `error_01_time` into `Error_01Time`, which the importer converts back.
+fn camel_case(name: &str) -> String {
+  name.split('_').enumerate().map(|(i, segment)| {
+    let mut chars = segment.chars();
+
+    match chars.next() {
+      Some(c) if c.is_alphabetic() => c.to_uppercase().chain(chars).collect(),
+      _ if i > 0 => format!("_{}", segment),
+      _ => segment.to_owned(),
+    }
+  }).collect()
+}
+
+fn unit_type(unit: &Unit) -> &'static str {
+  match unit {
+    Unit::I8 => "char",
+    Unit::U8 => "uchar",
+    Unit::I16 => "short",
+    Unit::U16 => "ushort",
+    Unit::I32 => "int",
+    Unit::U32 => "uint",
+    Unit::SysTime => "systime",
+    Unit::CycleTime => "cycletime",
+    Unit::Array | Unit::Map => unreachable!("composite values cannot be exported"),
+  }
+}
+
+/// Returns the `<calc>` element for a command with a `get_expr` or `set_expr`.
+///
+/// The `set` expression is the `set_expr` or the inverse of the `get_expr`, and may only be omitted for read-only commands.
+fn calc(command: &Command) -> Result<String, String> {
+  let get_expr = command.get_expr.as_ref().map(|expr| expr.expression());
+
+  let set_expr = match (get_expr, command.set_expr.as_ref()) {
+    (_, Some(set_expr)) => Some(Cow::Borrowed(set_expr.expression())),
+    (Some(get_expr), None) => match get_expr.inverse() {
+      Ok(inverse) => Some(Cow::Owned(inverse)),
+      Err(err) if command.mode.is_write() => return Err(format!("expression `{}` is not invertible: {}", get_expr.source(), err)),
+      Err(_) => None,
+    },
+    (None, None) => None,
+  };
+
+  let translate = |expr: &Expression| {
+    expr.to_calc().map(|calc| escape(&calc)).map_err(|err| format!("expression `{}` cannot be translated: {}", expr.source(), err))
+  };
+
+  let get = get_expr.map(translate).transpose()?.unwrap_or_else(|| String::from("V"));
+
+  match set_expr {
+    Some(set_expr) => Ok(format!("    <calc get=\"{}\" set=\"{}\"/>", get, translate(&set_expr)?)),
+    None => Ok(format!("    <calc get=\"{}\"/>", get)),
+  }
+}
+
+/// Returns the `<unit>` element for a command, without its name and abbreviation.
+fn unit_definition(command: &Command) -> Result<String, String> {
+  let mut definition = String::new();
+
+  if let Some(mapping) = &command.mapping {
+    writeln!(definition, "    <type>enum</type>").unwrap();
+
+    let mut keys = mapping.keys();
+    keys.sort();
+
+    for key in keys {
+      let text = mapping.get(&key).unwrap_or_default();
+      writeln!(definition, "    <enum bytes=\"{}\" text=\"{}\"/>", hex(&key), escape(text)).unwrap();
+    }
+  } else {
+    match command.unit {
+      Unit::SysTime | Unit::CycleTime => {},
+      _ if command.get_expr.is_some() || command.set_expr.is_some() => {
+        writeln!(definition, "{}", calc(command)?).unwrap();
+      },
+      _ if command.factor == 1.0 => {
+        writeln!(definition, "    <calc get=\"V\" set=\"V\"/>").unwrap();
+      },
+      _ => {
+        writeln!(definition, "    <calc get=\"V/{0}\" set=\"V*{0}\"/>", command.factor).unwrap();
+      },
+    }
+
+    writeln!(definition, "    <type>{}</type>", unit_type(&command.unit)).unwrap();
+  }
+
+  if let Some(unit_symbol) = command.unit_symbol() {
+    writeln!(definition, "    <entity>{}</entity>", escape(unit_symbol)).unwrap();
+  }
+
+  Ok(definition)
+}
+
+/// Exports a device definition as vcontrold XML.
+///
+/// Every distinct combination of type, `factor`, expressions and `mapping` becomes a vcontrold unit,
+/// where a `factor`, `get_expr` or `set_expr` is translated into a `calc` expression and a `mapping`
+/// into an `enum`. Readable commands become `get…` and writable commands `set…` commands for the
+/// device with the given `name` and `id`.
+///
+/// Commands with unit `array` or `map` and commands with expressions other than affine arithmetic
+/// have no vcontrold equivalent and are skipped, which is reported in [`Export::warnings`].
+///
+/// The result can be converted back using [`import`](super::import).
+pub fn export<D: Device + ?Sized>(device: &D, name: &str, id: &[u8]) -> Export {
+  log::trace!("vcontrold::export(…)");
+
+  let mut names = device.commands();
+  names.sort();
+
+  let mut warnings = Vec::new();
+  let mut definitions = BTreeMap::new();
+
+  for command_name in &names {
+    let command = device.command(command_name).unwrap();
+
+    if command.fields.is_some() {
+      warnings.push(format!("command `{}`: unit {} has no vcontrold equivalent", command_name, command.unit));
+      continue
+    }
+
+    match unit_definition(command) {
+      Ok(definition) => { definitions.insert(*command_name, definition); },
+      Err(err) => warnings.push(format!("command `{}`: {}", command_name, err)),
+    }
+  }
+
+  names.retain(|name| definitions.contains_key(name));
+
+  let mut units = BTreeMap::<String, String>::new();
+  let mut abbrevs = BTreeMap::new();
+
+  for command_name in &names {
+    let command = device.command(command_name).unwrap();
+    let definition = definitions.remove(command_name).unwrap();
+
+    if let Some(abbrev) = units.iter().find_map(|(abbrev, existing)| if *existing == definition { Some(abbrev.clone()) } else { None }) {
+      abbrevs.insert(*command_name, abbrev);
+      continue
+    }
+
+    let base = if command.mapping.is_some() {
+      format!("E_{}", command_name.to_uppercase())
+    } else if command.get_expr.is_some() || command.set_expr.is_some() {
+      format!("CALC_{}", command_name.to_uppercase())
+    } else if command.factor == 1.0 {
+      command.unit.to_string().to_uppercase()
+    } else {
+      format!("{}_{}", command.unit.to_string().to_uppercase(), command.factor.to_string().replace('.', "_"))
+    };
+
+    let abbrev = (1..).map(|i| if i == 1 { base.clone() } else { format!("{}_{}", base, i) })
+      .find(|abbrev| !units.contains_key(abbrev))
+      .unwrap();
+
+    abbrevs.insert(*command_name, abbrev.clone());
+    units.insert(abbrev, definition);
+  }
+
+  let mut units_xml = String::new();
+  writeln!(units_xml, "<units>").unwrap();
+  for (abbrev, definition) in &units {
+    writeln!(units_xml, "  <unit name=\"{0}\">\n    <abbrev>{0}</abbrev>\n{1}  </unit>", escape(abbrev), definition).unwrap();
+  }
+  writeln!(units_xml, "</units>").unwrap();
+
+  let protocol = match device.protocol() {
+    ProtocolKind::Kw2 => "KW2",
+    ProtocolKind::P300 => "P300",
+  };
+
+  let mut vito = String::new();
+  writeln!(vito, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>").unwrap();
+  writeln!(vito, "<vito>").unwrap();
+  writeln!(vito, "  <devices>").unwrap();
+  writeln!(vito, "    <device ID=\"{}\" name=\"{}\" protocol=\"{}\"/>", hex(id).replace(' ', ""), escape(name), protocol).unwrap();
+  writeln!(vito, "  </devices>").unwrap();
+  writeln!(vito, "  <commands>").unwrap();
+
+  for command_name in &names {
+    let command = device.command(command_name).unwrap();
+    let timer = command.unit == Unit::CycleTime && command.mapping.is_none();
+
+    let protocmds = [
+      (command.mode.is_read(), "get", if timer { "gettimer" } else { "getaddr" }),
+      (command.mode.is_write(), "set", if timer { "settimer" } else { "setaddr" }),
+    ];
+
+    for (_, prefix, protocmd) in protocmds.iter().filter(|(enabled, _, _)| *enabled) {
+      writeln!(vito, "    <command name=\"{}{}\" protocmd=\"{}\">", prefix, camel_case(command_name), protocmd).unwrap();
+      writeln!(vito, "      <addr>{:04X}</addr>", command.addr).unwrap();
+      writeln!(vito, "      <unit>{}</unit>", escape(&abbrevs[command_name])).unwrap();
+      writeln!(vito, "      <len>{}</len>", command.block_len).unwrap();
+
+      if let Some(description) = command.description() {
+        writeln!(vito, "      <description>{}</description>", escape(description)).unwrap();
+      }
+
+      let default_byte_len = command.mapping.as_ref().and_then(|mapping| mapping.keys().first().map(Vec::len)).unwrap_or_else(|| command.unit.size());
+      if command.byte_len != default_byte_len {
+        writeln!(vito, "      <byteLength>{}</byteLength>", command.byte_len).unwrap();
+      }
+      if command.byte_pos != 0 {
+        writeln!(vito, "      <bytePosition>{}</bytePosition>", command.byte_pos).unwrap();
+      }
+      if let Some(bit_pos) = command.bit_pos {
+        writeln!(vito, "      <bitPosition>{}</bitPosition>", bit_pos).unwrap();
+      }
+      if let Some(bit_len) = command.bit_len {
+        writeln!(vito, "      <bitLength>{}</bitLength>", bit_len).unwrap();
+      }
+
+      writeln!(vito, "    </command>").unwrap();
+    }
+  }
+
+  writeln!(vito, "  </commands>").unwrap();
+  writeln!(vito, "</vito>").unwrap();
+
+  Export { units: units_xml, vito, warnings }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::{AccessMode, Configuration, Optolink, Simulator, VControl, Value, vcontrold::import};
+  #[cfg(feature = "device-V200KW2_6")]
+  use crate::device::V200KW2;
+
+  #[test]
+  fn names() {
+    assert_eq!(camel_case("boiler_temp_actual"), "BoilerTempActual");
+    assert_eq!(camel_case("error_01_time"), "Error_01Time");
+  }
+
+  #[test]
+  fn escaping() {
+    assert_eq!(escape("<a & 'b'>"), "&lt;a &amp; &apos;b&apos;&gt;");
+  }
+
+  #[test]
+  fn expressions() {
+    let config = "
+      device:
+        protocol: Kw2
+      commands:
+        outside_temp:
+          addr: 0x5525
+          mode: read
+          unit: u16
+          get_expr: '($b1 * 256 + $b0) / 10 - 20'
+        room_temp_offset:
+          addr: 0x2306
+          mode: read_write
+          unit: i8
+          get_expr: '-$v / 2 + 20'
+        pressure:
+          addr: 0x0A00
+          mode: read_write
+          unit: u16
+          get_expr: 'round($v / 10)'
+          set_expr: '$v * 10'
+        flags:
+          addr: 0x3535
+          mode: read
+          unit: array
+          fields:
+            - unit: u8
+              bit_pos: 0
+    ".parse::<Configuration>().unwrap();
+
+    let export = export(&config, "Test", &[0x20, 0x98]);
+    assert!(export.units().contains(r#"<calc get="((((B1*256)+B0)/10)-20)"/>"#), "{}", export.units());
+    assert!(export.units().contains(r#"<calc get="(((0-V)/2)+20)" set="(0-((V-20)*2))"/>"#), "{}", export.units());
+    assert!(export.vito().contains("getRoomTempOffset") && export.vito().contains("setRoomTempOffset"));
+    assert!(!export.vito().contains("Pressure") && !export.vito().contains("Flags"));
+
+    let warnings = export.warnings();
+    assert_eq!(warnings.len(), 2, "{:#?}", warnings);
+    assert!(warnings[0].contains("flags") && warnings[0].contains("unit array"));
+    assert!(warnings[1].contains("pressure") && warnings[1].contains("function round is not supported"));
+  }
+
+  #[test]
+  fn round_trip_expressions() {
+    let config = "
+      device:
+        protocol: Kw2
+        id: [0x20, 0x98]
+      commands:
+        outside_temp:
+          addr: 0x5525
+          mode: read
+          unit: u16
+          get_expr: '($b1 * 256 + $b0) / 10 - 20'
+        room_temp_offset:
+          addr: 0x2306
+          mode: read_write
+          unit: i8
+          get_expr: '-$v / 2 + 20'
+    ".parse::<Configuration>().unwrap();
+
+    let export = export(&config, "Test", config.id().unwrap());
+    let import = import(&[export.units(), export.vito()], "Test").unwrap();
+    assert!(import.warnings().is_empty(), "{:#?}", import.warnings());
+
+    let imported = import.into_configuration();
+    assert_eq!(imported.command("outside_temp").unwrap().mode, AccessMode::Read);
+    assert_eq!(imported.command("room_temp_offset").unwrap().mode, AccessMode::ReadWrite);
+    assert_eq!(super::export(&imported, "Test", imported.id().unwrap()).units(), export.units());
+
+    let mut simulator = Simulator::new(&imported);
+    simulator.write_memory(0x5525, &[0xD4, 0x01]);
+
+    let mut vcontrol = VControl::connect_with(Optolink::simulate(simulator), imported).unwrap();
+    assert_eq!(vcontrol.get("outside_temp").unwrap().to_string(), "26.8");
+
+    vcontrol.set("room_temp_offset", &Value::Number(22.0)).unwrap();
+    assert_eq!(vcontrol.get("room_temp_offset").unwrap().to_string(), "22");
+  }
+
+  #[cfg(feature = "device-V200KW2_6")]
+  #[test]
+  fn round_trip() {
+    let export = export(&V200KW2, "V200KW2", &[0x20, 0x98]);
+    assert!(export.warnings().is_empty(), "{:#?}", export.warnings());
+
+    let import = import(&[export.units(), export.vito()], "V200KW2").unwrap();
+    assert!(import.warnings().is_empty(), "{:#?}", import.warnings());
+
+    let config = import.configuration();
+    assert_eq!(config.protocol(), V200KW2.protocol());
+    assert_eq!(config.id(), Some(&[0x20, 0x98][..]));
+
+    let mut commands = config.commands();
+    commands.sort();
+    let mut expected = V200KW2.commands();
+    expected.sort();
+    assert_eq!(commands, expected);
+
+    for name in commands {
+      let command = config.command(name).unwrap();
+      let expected = V200KW2.command(name).unwrap();
+
+      assert_eq!(command.addr, expected.addr, "{}", name);
+      assert_eq!(command.mode, expected.mode, "{}", name);
+      assert_eq!(command.block_len, expected.block_len, "{}", name);
+      assert_eq!(command.byte_len, expected.byte_len, "{}", name);
+      assert_eq!(command.byte_pos, expected.byte_pos, "{}", name);
+      assert_eq!(command.bit_pos, expected.bit_pos, "{}", name);
+      assert_eq!(command.factor, expected.factor, "{}", name);
+      assert_eq!(command.description(), expected.description(), "{}", name);
+      assert_eq!(command.unit_symbol(), expected.unit_symbol(), "{}", name);
+
+      if expected.mapping.is_none() {
+        assert_eq!(command.unit, expected.unit, "{}", name);
+      }
+
+      let entries = |command: &Command| command.mapping.as_ref().map(|mapping| {
+        let mut keys = mapping.keys();
+        keys.sort();
+        keys.into_iter().map(|key| (mapping.get(&key).map(str::to_owned), key)).collect::<Vec<_>>()
+      });
+      assert_eq!(entries(command), entries(expected), "{}", name);
+    }
+  }
+
+  #[test]
+  fn round_trip_configuration() {
+    let config = include_str!("../../config/V200KW2_6.yml").parse::<Configuration>().unwrap();
+    let first = export(&config, "V200KW2", config.id().unwrap());
+
+    let imported = import(&[first.units(), first.vito()], "2098").unwrap().into_configuration();
+    let second = export(&imported, "V200KW2", imported.id().unwrap());
+
+    assert_eq!(first.units(), second.units());
+    assert_eq!(first.vito(), second.vito());
+  }
+}
@@ -0,0 +1,629 @@
+use std::collections::HashMap;
+
+use roxmltree::{Document, Node};
+
+use crate::{AccessMode, Command, Configuration, Error, ProtocolKind, Unit, command::{Expr, RawCommand}, configuration, expression::Expression};
+
+/// The result of [`import`]ing a vcontrold configuration.
+#[derive(Debug)]
+pub struct Import {
+  configuration: Configuration,
+  warnings: Vec<String>,
+}
+
+impl Import {
+  /// Returns the imported configuration.
+  pub fn configuration(&self) -> &Configuration {
+    &self.configuration
+  }
+
+  /// Returns the imported configuration, discarding the warnings.
+  pub fn into_configuration(self) -> Configuration {
+    self.configuration
+  }
+
+  /// Returns a description of every construct which could not be translated.
+  pub fn warnings(&self) -> &[String] {
+    &self.warnings
+  }
+}
+
+#[derive(Debug)]
+struct UnitDef {
+  kind: String,
+  get: Option<String>,
+  set: Option<String>,
+  enums: Vec<(Option<Vec<u8>>, String)>,
+  entity: Option<String>,
+}
+
+fn location(node: Node) -> String {
+  format!("line {}", node.document().text_pos_at(node.range().start).row)
+}
+
+fn child_text<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+  node.children().find(|child| child.has_tag_name(name)).and_then(|child| child.text()).map(str::trim)
+}
+
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+  let s = s.split_whitespace().collect::<String>();
+
+  if s.is_empty() || s.len() % 2 != 0 {
+    return None
+  }
+
+  (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..(i + 2))?, 16).ok()).collect()
+}
+
+/// Converts a vcontrold command name like `getTempWWsoll` into `temp_wwsoll`.
+fn command_name(name: &str) -> String {
+  let name = ["get", "set"].iter()
+    .find_map(|prefix| name.strip_prefix(prefix).filter(|rest| rest.starts_with(char::is_uppercase)))
+    .unwrap_or(name);
+
+  let mut snake_case = String::new();
+  let mut previous_lowercase = false;
+
+  for c in name.chars() {
+    if c.is_uppercase() && previous_lowercase {
+      snake_case.push('_');
+    }
+
+    previous_lowercase = c.is_lowercase() || c.is_ascii_digit();
+    snake_case.extend(c.to_lowercase());
+  }
+
+  snake_case
+}
+
+fn round_factor(factor: f64) -> f64 {
+  if (factor.round() - factor).abs() < 1e-9 { factor.round() } else { factor }
+}
+
+/// Translates a `calc` expression of the form `V`, `V/n` or `V*n` into a factor
+/// for converting raw values (`get`) or into raw values (`set`).
+fn calc_factor(expr: &str, get: bool) -> Option<f64> {
+  let expr = expr.split_whitespace().collect::<String>();
+
+  let (divide, n) = if expr == "V" {
+    (false, 1.0)
+  } else if let Some(n) = expr.strip_prefix("V/") {
+    (true, n.parse::<f64>().ok()?)
+  } else if let Some(n) = expr.strip_prefix("V*") {
+    (false, n.parse::<f64>().ok()?)
+  } else {
+    return None
+  };
+
+  if n == 0.0 || !n.is_finite() {
+    return None
+  }
+
+  Some(round_factor(if divide == get { n } else { 1.0 / n }))
+}
+
+/// Translates any other `calc` expression into an expression, replacing `V` with `$v` and `B0`, `B1`, … with `$b0`, `$b1`, ….
+fn calc_expr(expr: &str) -> Result<Expression, String> {
+  let mut translated = String::new();
+  let mut chars = expr.chars().peekable();
+
+  while let Some(c) = chars.next() {
+    if !c.is_ascii_alphanumeric() {
+      translated.push(c);
+      continue
+    }
+
+    let mut word = c.to_string();
+    while let Some(&c) = chars.peek().filter(|c| c.is_ascii_alphanumeric() || **c == '.') {
+      word.push(c);
+      chars.next();
+    }
+
+    match word.as_str() {
+      "V" => translated.push_str("$v"),
+      byte if byte.starts_with('B') && byte.len() > 1 && byte[1..].bytes().all(|b| b.is_ascii_digit()) => {
+        translated.push_str(&format!("$b{}", &byte[1..]));
+      },
+      number if number.starts_with(|c: char| c.is_ascii_digit()) => translated.push_str(number),
+      variable => return Err(format!("calc expression `{}` cannot be translated: unknown variable `{}`", expr, variable)),
+    }
+  }
+
+  translated.parse().map_err(|err| format!("calc expression `{}` cannot be translated: {}", expr, err))
+}
+
+fn numeric_unit(kind: &str) -> Option<Unit> {
+  Some(match kind {
+    "char" => Unit::I8,
+    "uchar" => Unit::U8,
+    "short" => Unit::I16,
+    "ushort" => Unit::U16,
+    "int" => Unit::I32,
+    "uint" => Unit::U32,
+    "systime" => Unit::SysTime,
+    "cycletime" => Unit::CycleTime,
+    _ => return None,
+  })
+}
+
+const COMMAND_ELEMENTS: &[&str] = &[
+  "addr", "len", "unit", "description", "device",
+  "blockLength", "bytePosition", "byteLength", "bitPosition", "bitLength",
+];
+
+fn translate_command(node: Node, fields: &HashMap<&str, &str>, units: &HashMap<String, UnitDef>, warnings: &mut Vec<String>) -> Result<Command, String> {
+  let mode = match node.attribute("protocmd") {
+    Some("getaddr") | Some("gettimer") => AccessMode::Read,
+    Some("setaddr") | Some("settimer") => AccessMode::Write,
+    Some(protocmd) => return Err(format!("protocol command `{}` is not supported", protocmd)),
+    None => unreachable!(),
+  };
+
+  let usize_field = |name: &str| -> Result<Option<usize>, String> {
+    fields.get(name).map(|value| value.parse().map_err(|_| format!("invalid <{}> `{}`", name, value))).transpose()
+  };
+
+  let addr = fields.get("addr").ok_or("missing <addr>")?;
+  let addr = u16::from_str_radix(addr, 16).map_err(|_| format!("invalid <addr> `{}`", addr))?;
+
+  let abbrev = fields.get("unit").ok_or("missing <unit>")?;
+  let unit_def = units.get(*abbrev).ok_or_else(|| format!("unknown unit `{}`", abbrev))?;
+
+  let len = usize_field("len")?;
+
+  let (unit, byte_len, factor, get_expr, set_expr, mapping) = match unit_def.kind.as_str() {
+    "enum" | "errstate" => {
+      let mut mapping = HashMap::new();
+
+      for (bytes, text) in &unit_def.enums {
+        match bytes {
+          Some(bytes) => { mapping.insert(bytes.clone(), text.clone()); },
+          None => warnings.push(format!("{}: unit `{}`: enum default `{}` cannot be translated", location(node), abbrev, text)),
+        }
+      }
+
+      let byte_len = mapping.keys().map(Vec::len).next().unwrap_or(1);
+      if mapping.keys().any(|key| key.len() != byte_len) {
+        return Err(format!("enum unit `{}` has keys of different lengths", abbrev))
+      }
+
+      let unit = match byte_len {
+        1 => Unit::U8,
+        2 => Unit::U16,
+        _ => return Err(format!("enum unit `{}` with {} byte keys is not supported", abbrev, byte_len)),
+      };
+
+      (unit, byte_len, None, None, None, Some(mapping))
+    },
+    kind => {
+      let unit = numeric_unit(kind).ok_or_else(|| format!("unit type `{}` is not supported", kind))?;
+
+      let get = unit_def.get.as_deref().map(|get| (get, calc_factor(get, true)));
+      let set = unit_def.set.as_deref().map(|set| (set, calc_factor(set, false)));
+
+      let (factor, get_expr, set_expr) = match (get, set) {
+        (Some((_, None)), _) | (_, Some((_, None))) => {
+          let get_expr = get.map(|(get, _)| calc_expr(get)).transpose()?;
+          let set_expr = set.map(|(set, _)| calc_expr(set)).transpose()?;
+          (None, get_expr, set_expr)
+        },
+        (Some((get, Some(get_factor))), Some((set, Some(set_factor)))) if (get_factor - set_factor).abs() > 1e-9 * get_factor.abs() => {
+          return Err(format!("calc expressions `{}` and `{}` are not inverse", get, set))
+        },
+        (get, set) => (get.and_then(|(_, factor)| factor).or_else(|| set.and_then(|(_, factor)| factor)), None, None),
+      };
+
+      let size = unit.size();
+      (unit, size, factor, get_expr, set_expr, None)
+    },
+  };
+
+  let byte_len = usize_field("byteLength")?.unwrap_or(byte_len);
+  let byte_pos = usize_field("bytePosition")?.unwrap_or(0);
+  let block_len = usize_field("blockLength")?.or(len).unwrap_or(byte_pos + byte_len);
+
+  RawCommand {
+    addr,
+    mode,
+    unit,
+    block_len: Some(block_len),
+    byte_len: Some(byte_len),
+    byte_pos: Some(byte_pos),
+    bit_pos: usize_field("bitPosition")?,
+    bit_len: usize_field("bitLength")?,
+    factor,
+    get_expr,
+    set_expr,
+    mapping,
+    fields: None,
+    description: fields.get("description").map(|description| description.to_string()),
+    unit_symbol: unit_def.entity.clone(),
+    min: None,
+    max: None,
+    step: None,
+  }.into_command()
+}
+
+/// Returns whether two commands read and write the same value.
+fn same_value(a: &Command, b: &Command) -> bool {
+  let mapping = |command: &Command| command.mapping.as_ref().map(|mapping| {
+    let mut entries = mapping.keys().into_iter().map(|key| (mapping.get(&key).map(str::to_owned), key)).collect::<Vec<_>>();
+    entries.sort();
+    entries
+  });
+
+  a.addr == b.addr && a.unit == b.unit &&
+    a.block_len == b.block_len && a.byte_len == b.byte_len && a.byte_pos == b.byte_pos &&
+    a.bit_pos == b.bit_pos && a.bit_len == b.bit_len &&
+    a.factor == b.factor && mapping(a) == mapping(b) &&
+    a.get_expr.as_ref().map(Expr::source) == b.get_expr.as_ref().map(Expr::source) &&
+    a.set_expr.as_ref().map(Expr::source) == b.set_expr.as_ref().map(Expr::source)
+}
+
+/// Imports a vcontrold configuration for the given device.
+///
+/// The `documents` are the contents of vcontrold's XML files, usually `vcontrold.xml`
+/// containing the units and `vito.xml` containing the devices and commands. The `device`
+/// is selected by its name or its ID, e.g. `V200KW2` or `2098`.
+///
+/// Matching `get…` and `set…` commands are combined into a single `read_write` command.
+/// Commands which cannot be translated, e.g. because of a `calc` expression other than
+/// a plain factor, are skipped and reported in [`Import::warnings`].
+pub fn import(documents: &[&str], device: &str) -> Result<Import, Error> {
+  log::trace!("vcontrold::import(…)");
+
+  let documents = documents.iter()
+    .map(|document| Document::parse(document))
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(|err| Error::InvalidArgument(format!("invalid XML: {}", err)))?;
+
+  let elements = || documents.iter().flat_map(|document| document.descendants()).filter(|node| node.is_element());
+
+  let mut warnings = Vec::new();
+
+  let mut units = HashMap::new();
+  for node in elements().filter(|node| node.has_tag_name("unit") && node.attribute("name").is_some()) {
+    let abbrev = match child_text(node, "abbrev") {
+      Some(abbrev) => abbrev,
+      None => continue,
+    };
+
+    let calc = node.children().find(|child| child.has_tag_name("calc"));
+
+    let mut enums = Vec::new();
+    for entry in node.children().filter(|child| child.has_tag_name("enum")) {
+      let text = entry.attribute("text").unwrap_or_default().to_owned();
+
+      match entry.attribute("bytes").map(|bytes| (bytes, parse_hex(bytes))) {
+        Some((_, Some(bytes))) => enums.push((Some(bytes), text)),
+        Some((bytes, None)) => warnings.push(format!("{}: unit `{}`: invalid enum bytes `{}`", location(entry), abbrev, bytes)),
+        None => enums.push((None, text)),
+      }
+    }
+
+    units.insert(abbrev.to_owned(), UnitDef {
+      kind: child_text(node, "type").unwrap_or_default().to_owned(),
+      get: calc.and_then(|calc| calc.attribute("get")).map(str::to_owned),
+      set: calc.and_then(|calc| calc.attribute("set")).map(str::to_owned),
+      enums,
+      entity: child_text(node, "entity").map(str::to_owned),
+    });
+  }
+
+  let device_node = elements()
+    .filter(|node| node.has_tag_name("device") && node.parent_element().map(|parent| parent.has_tag_name("devices")).unwrap_or(false))
+    .find(|node| node.attribute("name") == Some(device) || node.attribute("ID").map(|id| id.eq_ignore_ascii_case(device)).unwrap_or(false))
+    .ok_or_else(|| Error::InvalidArgument(format!("device {} not found", device)))?;
+
+  let device_id = device_node.attribute("ID").unwrap_or_default();
+  let id = parse_hex(device_id);
+
+  let protocol = match device_node.attribute("protocol").unwrap_or_default() {
+    "KW2" => ProtocolKind::Kw2,
+    "P300" => ProtocolKind::P300,
+    protocol => return Err(Error::InvalidArgument(format!("protocol {} is not supported", protocol))),
+  };
+
+  let mut reads = Vec::new();
+  let mut writes = Vec::new();
+
+  for node in elements().filter(|node| node.has_tag_name("command") && node.attribute("protocmd").is_some()) {
+    let name = node.attribute("name").unwrap_or_default();
+
+    let overrides = node.children()
+      .filter(|child| child.has_tag_name("device") && child.attribute("ID").map(|id| id.eq_ignore_ascii_case(device_id)).unwrap_or(false));
+
+    let mut fields = HashMap::new();
+    for child in node.children().chain(overrides.flat_map(|node| node.children())).filter(|child| child.is_element()) {
+      let tag = child.tag_name().name();
+
+      if !COMMAND_ELEMENTS.contains(&tag) {
+        warnings.push(format!("{}: command `{}`: element <{}> is ignored", location(child), name, tag));
+        continue
+      }
+
+      if let Some(text) = child.text() {
+        fields.insert(tag, text.trim());
+      }
+    }
+
+    match translate_command(node, &fields, &units, &mut warnings) {
+      Ok(command) if command.mode == AccessMode::Read => reads.push((command_name(name), command)),
+      Ok(command) => writes.push((command_name(name), command)),
+      Err(err) => warnings.push(format!("{}: command `{}`: {}", location(node), name, err)),
+    }
+  }
+
+  let mut commands = HashMap::new();
+
+  for (name, mut command) in reads {
+    if let Some(i) = writes.iter().position(|(write_name, write)| *write_name == name && same_value(&command, write)) {
+      writes.remove(i);
+      command.mode = AccessMode::ReadWrite;
+    }
+
+    if commands.contains_key(&name) {
+      warnings.push(format!("command `{}` is defined more than once", name));
+      continue
+    }
+
+    commands.insert(name, command);
+  }
+
+  for (name, command) in writes {
+    let name = if commands.contains_key(&name) { format!("{}_set", name) } else { name };
+
+    if commands.contains_key(&name) {
+      warnings.push(format!("command `{}` is defined more than once", name));
+      continue
+    }
+
+    commands.insert(name, command);
+  }
+
+  let overlapping = configuration::overlaps(&commands).into_iter()
+    .map(|(name, message)| (name.to_owned(), message))
+    .collect::<Vec<_>>();
+
+  for (name, message) in overlapping {
+    if commands.remove(&name).is_some() {
+      warnings.push(format!("command `{}`: {}", name, message));
+    }
+  }
+
+  Ok(Import { configuration: Configuration::new(protocol, id, commands), warnings })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::Device;
+
+  const VCONTROLD_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+    <V-Control xmlns:vcontrol="http://www.openv.de/vcontrol">
+      <units>
+        <unit name="Temperatur">
+          <abbrev>UT</abbrev>
+          <calc get="V/10" set="V*10"/>
+          <type>short</type>
+          <entity>Grad Celsius</entity>
+        </unit>
+        <unit name="Temperatur 1Byte">
+          <abbrev>UTI</abbrev>
+          <calc get="V" set="V"/>
+          <type>uchar</type>
+        </unit>
+        <unit name="Kennlinie">
+          <abbrev>UK</abbrev>
+          <calc get="(B1*256+B0)/10-20" set="V"/>
+          <type>ushort</type>
+        </unit>
+        <unit name="BetriebsArt">
+          <abbrev>BA</abbrev>
+          <type>enum</type>
+          <enum bytes="00" text="WW"/>
+          <enum bytes="01" text="RED"/>
+          <enum text="UNKNOWN"/>
+        </unit>
+        <unit name="CycleTime">
+          <abbrev>CT</abbrev>
+          <type>cycletime</type>
+        </unit>
+      </units>
+      <protocols>
+        <protocol name="KW2">
+          <commands>
+            <command name="getaddr">
+              <send>SYNC;SEND 01 F7 $addr $hexlen;RECV $len $unit</send>
+            </command>
+          </commands>
+        </protocol>
+      </protocols>
+    </V-Control>
+  "#;
+
+  const VITO_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+    <vito>
+      <devices>
+        <device ID="2098" name="V200KW2" protocol="KW2"/>
+        <device ID="20CB" name="VScotHO1" protocol="P300"/>
+      </devices>
+      <commands>
+        <command name="getTempA" protocmd="getaddr">
+          <addr>5525</addr>
+          <unit>UT</unit>
+          <len>2</len>
+          <error>05 05</error>
+          <description>Aussentemperatur</description>
+        </command>
+        <command name="getTempWWsoll" protocmd="getaddr">
+          <addr>6300</addr>
+          <unit>UTI</unit>
+          <len>1</len>
+        </command>
+        <command name="setTempWWsoll" protocmd="setaddr">
+          <addr>6300</addr>
+          <unit>UTI</unit>
+          <len>1</len>
+        </command>
+        <command name="getBetriebArt" protocmd="getaddr">
+          <addr>2323</addr>
+          <unit>BA</unit>
+          <len>1</len>
+          <device ID="20CB">
+            <addr>2500</addr>
+          </device>
+        </command>
+        <command name="getKennlinie" protocmd="getaddr">
+          <addr>27D3</addr>
+          <unit>UK</unit>
+          <len>2</len>
+        </command>
+        <command name="getTimerWWMo" protocmd="gettimer">
+          <addr>2100</addr>
+          <unit>CT</unit>
+          <len>8</len>
+        </command>
+      </commands>
+    </vito>
+  "#;
+
+  #[test]
+  fn import_device() {
+    let import = import(&[VCONTROLD_XML, VITO_XML], "V200KW2").unwrap();
+    let config = import.configuration();
+
+    assert_eq!(config.protocol(), ProtocolKind::Kw2);
+    assert_eq!(config.id(), Some(&[0x20, 0x98][..]));
+
+    let mut commands = config.commands();
+    commands.sort();
+    assert_eq!(commands, vec!["betrieb_art", "kennlinie", "temp_a", "temp_wwsoll", "timer_wwmo"]);
+
+    let temp_a = config.command("temp_a").unwrap();
+    assert_eq!(temp_a.addr, 0x5525);
+    assert_eq!(temp_a.description(), Some("Aussentemperatur"));
+    assert_eq!(temp_a.unit_symbol(), Some("Grad Celsius"));
+    assert_eq!(temp_a.mode, AccessMode::Read);
+    assert_eq!(temp_a.unit, Unit::I16);
+    assert_eq!(temp_a.factor, 10.0);
+
+    let temp_wwsoll = config.command("temp_wwsoll").unwrap();
+    assert_eq!(temp_wwsoll.mode, AccessMode::ReadWrite);
+    assert_eq!(temp_wwsoll.unit, Unit::U8);
+
+    let betrieb_art = config.command("betrieb_art").unwrap();
+    assert_eq!(betrieb_art.addr, 0x2323);
+    assert_eq!(betrieb_art.mapping.as_ref().unwrap().get(&[0x01]), Some("RED"));
+
+    let timer = config.command("timer_wwmo").unwrap();
+    assert_eq!(timer.unit, Unit::CycleTime);
+    assert_eq!(timer.block_len, 8);
+
+    let kennlinie = config.command("kennlinie").unwrap();
+    assert_eq!(kennlinie.factor, 1.0);
+    assert_eq!(kennlinie.get_expr.as_ref().map(Expr::source), Some("($b1*256+$b0)/10-20"));
+    assert_eq!(kennlinie.set_expr.as_ref().map(Expr::source), Some("$v"));
+
+    let warnings = import.warnings();
+    assert_eq!(warnings.len(), 2, "{:#?}", warnings);
+    assert!(warnings.iter().any(|w| w.contains("element <error> is ignored")));
+    assert!(warnings.iter().any(|w| w.contains("enum default `UNKNOWN`")));
+  }
+
+  #[test]
+  fn import_device_override() {
+    let import = import(&[VCONTROLD_XML, VITO_XML], "20cb").unwrap();
+    let config = import.configuration();
+
+    assert_eq!(config.protocol(), ProtocolKind::P300);
+    assert_eq!(config.command("betrieb_art").unwrap().addr, 0x2500);
+  }
+
+  #[test]
+  fn import_yaml() {
+    let import = import(&[VCONTROLD_XML, VITO_XML], "V200KW2").unwrap();
+    let yaml = import.configuration().to_yaml();
+
+    let config = yaml.parse::<Configuration>().unwrap();
+    assert_eq!(config.to_yaml(), yaml);
+    assert_eq!(config.command("temp_a").unwrap().factor, 10.0);
+  }
+
+  #[test]
+  fn invalid_layout() {
+    let vito = r#"<?xml version="1.0" encoding="UTF-8"?>
+      <vito>
+        <devices>
+          <device ID="2098" name="V200KW2" protocol="KW2"/>
+        </devices>
+        <commands>
+          <command name="getFlag" protocmd="getaddr">
+            <addr>2500</addr>
+            <unit>UT</unit>
+            <len>2</len>
+            <bitPosition>3</bitPosition>
+          </command>
+          <command name="getTempB" protocmd="getaddr">
+            <addr>5527</addr>
+            <unit>UT</unit>
+            <len>4</len>
+            <byteLength>3</byteLength>
+          </command>
+          <command name="getTempC" protocmd="getaddr">
+            <addr>5525</addr>
+            <unit>UT</unit>
+            <len>2</len>
+          </command>
+          <command name="getTempD" protocmd="getaddr">
+            <addr>5526</addr>
+            <unit>UT</unit>
+            <len>2</len>
+          </command>
+        </commands>
+      </vito>
+    "#;
+
+    let import = import(&[VCONTROLD_XML, vito], "V200KW2").unwrap();
+    assert_eq!(import.configuration().commands(), vec!["temp_d"]);
+
+    let warnings = import.warnings();
+    assert!(warnings.iter().any(|w| w.contains("getFlag") && w.contains("bit_pos requires byte_pos 0 and byte_len 1")), "{:#?}", warnings);
+    assert!(warnings.iter().any(|w| w.contains("getTempB") && w.contains("does not match the size of unit i16")), "{:#?}", warnings);
+    assert!(warnings.iter().any(|w| w.contains("temp_c") && w.contains("overlaps with command `temp_d`")), "{:#?}", warnings);
+
+    import.configuration().to_yaml().parse::<Configuration>().unwrap();
+  }
+
+  #[test]
+  fn unknown_device() {
+    let err = import(&[VCONTROLD_XML, VITO_XML], "V333MW1").unwrap_err();
+    assert_eq!(err.to_string(), "device V333MW1 not found");
+  }
+
+  #[test]
+  fn names() {
+    assert_eq!(command_name("getTempA"), "temp_a");
+    assert_eq!(command_name("setTempWWsoll"), "temp_wwsoll");
+    assert_eq!(command_name("getTimerM1Mo"), "timer_m1_mo");
+    assert_eq!(command_name("getter"), "getter");
+  }
+
+  #[test]
+  fn calc() {
+    assert_eq!(calc_factor("V/10", true), Some(10.0));
+    assert_eq!(calc_factor("V * 10", false), Some(10.0));
+    assert_eq!(calc_factor("V*0.1", true), Some(10.0));
+    assert_eq!(calc_factor("V/2", false), Some(0.5));
+    assert_eq!(calc_factor("V", true), Some(1.0));
+    assert_eq!(calc_factor("V/0", true), None);
+    assert_eq!(calc_factor("V/10-2", true), None);
+
+    let source = |expr: &str| calc_expr(expr).map(|expr| expr.source().to_owned());
+    assert_eq!(source("(B1*256+B0)/10-20").unwrap(), "($b1*256+$b0)/10-20");
+    assert_eq!(source("V / 2.5 + 0x10").unwrap(), "$v / 2.5 + 0x10");
+    assert!(source("V*P").unwrap_err().contains("unknown variable `P`"));
+    assert!(source("V*(2").is_err());
+  }
+}
@@ -0,0 +1,123 @@
+use chrono::Weekday;
+use serde_derive::*;
+
+use crate::{Error, Value, types::CycleTime};
+
+const WEEKDAYS: [Weekday; 7] = [
+  Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri, Weekday::Sat, Weekday::Sun,
+];
+
+/// The switching times of a timer program for every day of the week.
+///
+/// A program named e.g. `hotwater` consists of the seven commands
+/// `hotwater_timer_mon` to `hotwater_timer_sun`. It is serialized as
+/// an object with the keys `mon` to `sun`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WeeklySchedule {
+  mon: CycleTime,
+  tue: CycleTime,
+  wed: CycleTime,
+  thu: CycleTime,
+  fri: CycleTime,
+  sat: CycleTime,
+  sun: CycleTime,
+}
+
+impl WeeklySchedule {
+  /// Creates a schedule from the cycle times for Monday to Sunday.
+  pub fn new(days: [CycleTime; 7]) -> WeeklySchedule {
+    let [mon, tue, wed, thu, fri, sat, sun] = days;
+    WeeklySchedule { mon, tue, wed, thu, fri, sat, sun }
+  }
+
+  /// Returns the cycle time for the given day.
+  pub fn day(&self, weekday: Weekday) -> &CycleTime {
+    match weekday {
+      Weekday::Mon => &self.mon,
+      Weekday::Tue => &self.tue,
+      Weekday::Wed => &self.wed,
+      Weekday::Thu => &self.thu,
+      Weekday::Fri => &self.fri,
+      Weekday::Sat => &self.sat,
+      Weekday::Sun => &self.sun,
+    }
+  }
+
+  /// Returns the cycle time for the given day mutably.
+  pub fn day_mut(&mut self, weekday: Weekday) -> &mut CycleTime {
+    match weekday {
+      Weekday::Mon => &mut self.mon,
+      Weekday::Tue => &mut self.tue,
+      Weekday::Wed => &mut self.wed,
+      Weekday::Thu => &mut self.thu,
+      Weekday::Fri => &mut self.fri,
+      Weekday::Sat => &mut self.sat,
+      Weekday::Sun => &mut self.sun,
+    }
+  }
+
+  /// Sets the cycle time for the given day.
+  pub fn set_day(&mut self, weekday: Weekday, cycletime: CycleTime) {
+    *self.day_mut(weekday) = cycletime;
+  }
+
+  /// Copies the cycle time of one day to the given other days.
+  pub fn copy_day(&mut self, from: Weekday, to: &[Weekday]) {
+    let cycletime = self.day(from).clone();
+
+    for &weekday in to {
+      self.set_day(weekday, cycletime.clone());
+    }
+  }
+
+  /// Returns an iterator over all days, starting with Monday.
+  pub fn iter(&self) -> impl Iterator<Item = (Weekday, &CycleTime)> {
+    WEEKDAYS.iter().map(move |&weekday| (weekday, self.day(weekday)))
+  }
+
+  /// Returns the names of the commands for every day of the given program.
+  pub(crate) fn commands(program: &str) -> impl Iterator<Item = (Weekday, String)> + '_ {
+    WEEKDAYS.iter().map(move |&weekday| (weekday, format!("{}_timer_{}", program, weekday.to_string().to_lowercase())))
+  }
+
+  pub(crate) fn cycletime(command: &str, value: Value) -> Result<CycleTime, Error> {
+    match value {
+      Value::CycleTime(cycletime) => Ok(cycletime),
+      value => Err(Error::InvalidArgument(format!("command {} returned {:?} instead of a cycle time", command, value))),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn commands() {
+    let commands = WeeklySchedule::commands("hotwater").map(|(_, command)| command).collect::<Vec<_>>();
+    assert_eq!(commands.first().unwrap(), "hotwater_timer_mon");
+    assert_eq!(commands.last().unwrap(), "hotwater_timer_sun");
+  }
+
+  #[test]
+  fn copy_day() {
+    let mut schedule = WeeklySchedule::default();
+    schedule.set_day(Weekday::Mon, "06:00 – 22:00".parse().unwrap());
+    schedule.copy_day(Weekday::Mon, &[Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri]);
+
+    assert_eq!(schedule.day(Weekday::Fri), schedule.day(Weekday::Mon));
+    assert_eq!(schedule.day(Weekday::Sat), &CycleTime::empty());
+    assert_eq!(schedule.iter().filter(|(_, cycletime)| cycletime.periods().is_empty()).count(), 2);
+  }
+
+  #[test]
+  fn json() {
+    let mut schedule = WeeklySchedule::default();
+    schedule.set_day(Weekday::Sun, "08:00 – 12:00,14:00 – 24:00".parse().unwrap());
+
+    let json = serde_json::to_string(&schedule).unwrap();
+    assert_eq!(serde_json::from_str::<WeeklySchedule>(&json).unwrap(), schedule);
+
+    assert!(serde_json::from_str::<WeeklySchedule>(r#"{"mon": "06:00 – 22:00"}"#).is_err());
+  }
+}